@@ -0,0 +1,573 @@
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+use yagna_offer_server::audit::AuditEntry;
+use yagna_offer_server::config::ServerConfig;
+use yagna_offer_server::grouping::GroupingEpoch;
+use yagna_offer_server::health::{HealthReport, Readiness};
+use yagna_offer_server::jobs::JobStatus;
+use yagna_offer_server::model::demand::base::{
+    DemandCancellation, DemandRefresh, DemandSubscription, DemandUpdate,
+};
+use yagna_offer_server::model::offer::base::GolemBaseOffer;
+use yagna_offer_server::offers::{MirrorSyncStats, OfferInsertResult};
+use yagna_offer_server::rest::admin::audit::AuditQuery;
+use yagna_offer_server::rest::admin::invariants::InvariantReport;
+use yagna_offer_server::rest::admin::offers::{
+    BulkReassign, OfferAssignmentChange, OfferInspection, OfferReassign, OfferSelector,
+};
+use yagna_offer_server::rest::allocations::{AllocationFilter, AllocationListing};
+use yagna_offer_server::rest::demand::add_offer_to_demand::AddOfferToDemand;
+use yagna_offer_server::rest::demand::pick_offer_to_demand::PickOfferToDemand;
+use yagna_offer_server::rest::demand::take_offer_from_queue::ModelOffer;
+use yagna_offer_server::rest::demand::TakeOfferFromQueue;
+use yagna_offer_server::rest::error::{ApiErrorBody, ErrorCode};
+use yagna_offer_server::rest::offer::get_if_available::FilterAttributes;
+use yagna_offer_server::rest::offer::list_offers::{
+    OfferListEntry, OfferListQuery, OFFER_LIST_PATH,
+};
+use yagna_offer_server::rest::offer::unsubscribe_offer::{
+    OfferUnsubscribe, OfferUnsubscribeResult,
+};
+use yagna_offer_server::rest::provider::{ProviderEntry, ProviderHeartbeat};
+use yagna_offer_server::rest::test::report::{ReportFormat, ReportQuery, TestReport};
+use yagna_offer_server::rest::test::{
+    TestFinishArguments, TestInitializeArguments, TestRerunArguments, TestStartArguments,
+};
+use yagna_offer_server::state::{DemandObj, IntegrationTest, ProviderLiveness, Snapshot};
+use yagna_offer_server::NodeId;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Request to {url} failed: {source}")]
+    Http {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Server returned {status}: {} ({:?})", .body.message, .body.code)]
+    Api {
+        status: StatusCode,
+        body: ApiErrorBody,
+    },
+    #[error("Server returned {status}: {body}")]
+    UnexpectedResponse { status: StatusCode, body: String },
+    #[error("Failed to decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("Failed to encode query: {0}")]
+    Encode(#[from] serde_urlencoded::ser::Error),
+}
+
+impl ClientError {
+    /// Error code sent by the server, if the error came from the API
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Api { body, .. } => Some(body.code),
+            _ => None,
+        }
+    }
+
+    /// A request that never reached the server is always safe to repeat. After a timeout or a
+    /// 5xx response the server may have handled it, so only idempotent methods are retried;
+    /// repeating e.g. `/offer/take` could assign a second offer.
+    fn is_retryable(&self, method: &Method) -> bool {
+        let idempotent = matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+        );
+        match self {
+            ClientError::Http { source, .. } => {
+                source.is_connect() || (idempotent && source.is_timeout())
+            }
+            ClientError::Api { status, .. } | ClientError::UnexpectedResponse { status, .. } => {
+                idempotent && (status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS)
+            }
+            ClientError::Decode(_) | ClientError::Encode(_) => false,
+        }
+    }
+}
+
+/// Exponential backoff applied to connection errors, and to timeouts and 5xx responses of
+/// idempotent requests
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+}
+
+/// Typed client for the offer matcher REST API
+#[derive(Debug, Clone)]
+pub struct MatcherClient {
+    base_url: String,
+    http: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl MatcherClient {
+    /// `base_url` is the address of the server, e.g. `http://127.0.0.1:15155`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn send_once(
+        &self,
+        method: &Method,
+        path: &str,
+        body: Option<&String>,
+    ) -> Result<String, ClientError> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self.http.request(method.clone(), &url);
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+        }
+        let response = request.send().await.map_err(|source| ClientError::Http {
+            url: url.clone(),
+            source,
+        })?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|source| ClientError::Http { url, source })?;
+        if status.is_success() {
+            return Ok(text);
+        }
+        match serde_json::from_str::<ApiErrorBody>(&text) {
+            Ok(body) => Err(ClientError::Api { status, body }),
+            Err(_) => Err(ClientError::UnexpectedResponse { status, body: text }),
+        }
+    }
+
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<String, ClientError> {
+        let body = body.map(serde_json::to_string).transpose()?;
+        let mut attempt = 0;
+        loop {
+            match self.send_once(&method, path, body.as_ref()).await {
+                Ok(text) => return Ok(text),
+                Err(e) if e.is_retryable(&method) && attempt < self.retry.max_retries => {
+                    let backoff = self.retry.backoff(attempt);
+                    log::warn!(
+                        "{} {} failed: {}, retrying in {:.1}s",
+                        method,
+                        path,
+                        e,
+                        backoff.as_secs_f64()
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        let text = self.send::<()>(Method::GET, path, None).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn post_text<B: Serialize>(&self, path: &str, body: &B) -> Result<String, ClientError> {
+        self.send(Method::POST, path, Some(body)).await
+    }
+
+    async fn post_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, ClientError> {
+        let text = self.post_text(path, body).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub async fn version(&self) -> Result<String, ClientError> {
+        self.send::<()>(Method::GET, "/version", None).await
+    }
+
+    pub async fn health(&self) -> Result<HealthReport, ClientError> {
+        let text = self.send::<()>(Method::GET, "/health", None).await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Readiness report, returned also when the server is not ready. Not retried.
+    pub async fn readiness(&self) -> Result<Readiness, ClientError> {
+        match self.send_once(&Method::GET, "/ready", None).await {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(ClientError::UnexpectedResponse {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                body,
+            }) => Ok(serde_json::from_str(&body)?),
+            Err(e) => Err(e),
+        }
+    }
+
+    // --- offers ---
+
+    pub async fn push_offer(
+        &self,
+        offer: &GolemBaseOffer,
+    ) -> Result<OfferInsertResult, ClientError> {
+        self.post_json("/provider/offer/new", offer).await
+    }
+
+    pub async fn unsubscribe_offer(
+        &self,
+        unsubscribe: &OfferUnsubscribe,
+    ) -> Result<OfferUnsubscribeResult, ClientError> {
+        self.post_json("/provider/offer/unsubscribe", unsubscribe)
+            .await
+    }
+
+    pub async fn provider_heartbeat(
+        &self,
+        node_id: NodeId,
+    ) -> Result<ProviderLiveness, ClientError> {
+        self.post_json("/provider/heartbeat", &ProviderHeartbeat { node_id })
+            .await
+    }
+
+    pub async fn list_providers(&self) -> Result<Vec<ProviderEntry>, ClientError> {
+        self.get_json("/providers/list").await
+    }
+
+    pub async fn list_allocations(
+        &self,
+        filter: &AllocationFilter,
+    ) -> Result<AllocationListing, ClientError> {
+        let query = serde_urlencoded::to_string(filter)?;
+        self.get_json(&format!("/allocations?{}", query)).await
+    }
+
+    pub async fn list_offers(&self) -> Result<Vec<OfferListEntry>, ClientError> {
+        self.get_json(OFFER_LIST_PATH).await
+    }
+
+    pub async fn list_offers_on_platform(
+        &self,
+        payment_platform: &str,
+    ) -> Result<Vec<OfferListEntry>, ClientError> {
+        let query = serde_urlencoded::to_string(OfferListQuery {
+            payment_platform: Some(payment_platform.to_string()),
+        })?;
+        self.get_json(&format!("{}?{}", OFFER_LIST_PATH, query))
+            .await
+    }
+
+    pub async fn list_taken_offers(&self) -> Result<Vec<OfferListEntry>, ClientError> {
+        self.get_json("/offers/list/taken").await
+    }
+
+    pub async fn list_available_offers(&self) -> Result<Vec<OfferListEntry>, ClientError> {
+        self.get_json("/offers/list/available").await
+    }
+
+    pub async fn grouping_epoch(&self) -> Result<GroupingEpoch, ClientError> {
+        self.get_json("/offers/grouping").await
+    }
+
+    pub async fn clear_offers(&self) -> Result<String, ClientError> {
+        self.send::<()>(Method::POST, "/offers/clear", None).await
+    }
+
+    /// Take the first available offer matching the filter. Returns `None` when nothing matches.
+    pub async fn take_offer(
+        &self,
+        filter: &FilterAttributes,
+    ) -> Result<Option<GolemBaseOffer>, ClientError> {
+        match self.post_text("/offer/take", filter).await {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(offer) => Ok(Some(offer)),
+                // server running with LEGACY_ERROR_RESPONSES answers 200 with plain text
+                Err(_) if text == "No available offers" => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) if e.code() == Some(ErrorCode::NoAvailableOffers) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // --- demands ---
+
+    pub async fn create_demand(
+        &self,
+        demand: &DemandSubscription,
+    ) -> Result<DemandSubscription, ClientError> {
+        self.post_json("/requestor/demand/new", demand).await
+    }
+
+    pub async fn cancel_demand(&self, demand_id: &str) -> Result<String, ClientError> {
+        let cancellation = DemandCancellation {
+            demand_id: demand_id.to_string(),
+        };
+        self.post_text("/requestor/demand/cancel", &cancellation)
+            .await
+    }
+
+    pub async fn refresh_demand(
+        &self,
+        refresh: &DemandRefresh,
+    ) -> Result<DemandSubscription, ClientError> {
+        self.post_json("/requestor/demand/refresh", refresh).await
+    }
+
+    pub async fn update_demand(
+        &self,
+        update: &DemandUpdate,
+    ) -> Result<DemandSubscription, ClientError> {
+        self.post_json("/requestor/demand/update", update).await
+    }
+
+    pub async fn list_demands(&self) -> Result<Vec<DemandObj>, ClientError> {
+        self.get_json("/requestor/demands/list").await
+    }
+
+    pub async fn append_offer(&self, add_offer: &AddOfferToDemand) -> Result<String, ClientError> {
+        self.post_text("/requestor/demand/append-offer", add_offer)
+            .await
+    }
+
+    pub async fn append_any_offer(
+        &self,
+        pick_offer: &PickOfferToDemand,
+    ) -> Result<String, ClientError> {
+        self.post_text("/requestor/demand/append-any-offer", pick_offer)
+            .await
+    }
+
+    pub async fn take_from_queue(
+        &self,
+        take_offer: &TakeOfferFromQueue,
+    ) -> Result<Vec<ModelOffer>, ClientError> {
+        self.post_json("/requestor/demand/take-from-queue", take_offer)
+            .await
+    }
+
+    // --- administration ---
+
+    pub async fn server_config(&self) -> Result<ServerConfig, ClientError> {
+        self.get_json("/admin/config").await
+    }
+
+    pub async fn check_invariants(&self) -> Result<InvariantReport, ClientError> {
+        self.get_json("/admin/invariants").await
+    }
+
+    pub async fn audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, ClientError> {
+        let query = serde_urlencoded::to_string(query)?;
+        self.get_json(&format!("/admin/audit?{}", query)).await
+    }
+
+    pub async fn inspect_offer(&self, offer_id: &str) -> Result<OfferInspection, ClientError> {
+        self.get_json(&format!("/admin/offer/{}", offer_id)).await
+    }
+
+    pub async fn unassign_offer(
+        &self,
+        offer_id: &str,
+    ) -> Result<OfferAssignmentChange, ClientError> {
+        let text = self
+            .send::<()>(
+                Method::POST,
+                &format!("/admin/offer/{}/unassign", offer_id),
+                None,
+            )
+            .await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub async fn reassign_offer(
+        &self,
+        offer_id: &str,
+        demand_id: &str,
+    ) -> Result<OfferAssignmentChange, ClientError> {
+        self.post_json(
+            &format!("/admin/offer/{}/reassign", offer_id),
+            &OfferReassign {
+                demand_id: demand_id.to_string(),
+            },
+        )
+        .await
+    }
+
+    pub async fn unassign_offers(
+        &self,
+        selector: &OfferSelector,
+    ) -> Result<Vec<OfferAssignmentChange>, ClientError> {
+        self.post_json("/admin/offers/unassign", selector).await
+    }
+
+    pub async fn reassign_offers(
+        &self,
+        request: &BulkReassign,
+    ) -> Result<Vec<OfferAssignmentChange>, ClientError> {
+        self.post_json("/admin/offers/reassign", request).await
+    }
+
+    pub async fn list_jobs(&self) -> Result<Vec<JobStatus>, ClientError> {
+        self.get_json("/admin/jobs").await
+    }
+
+    pub async fn pause_job(&self, name: &str) -> Result<JobStatus, ClientError> {
+        let text = self
+            .send::<()>(Method::POST, &format!("/admin/jobs/{}/pause", name), None)
+            .await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub async fn resume_job(&self, name: &str) -> Result<JobStatus, ClientError> {
+        let text = self
+            .send::<()>(Method::POST, &format!("/admin/jobs/{}/resume", name), None)
+            .await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Trigger a run of the job, it runs in the background also when the job is paused
+    pub async fn run_job_now(&self, name: &str) -> Result<JobStatus, ClientError> {
+        let text = self
+            .send::<()>(Method::POST, &format!("/admin/jobs/{}/run", name), None)
+            .await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub async fn mirror_sync_now(&self) -> Result<MirrorSyncStats, ClientError> {
+        let text = self
+            .send::<()>(Method::POST, "/admin/mirror/sync", None)
+            .await?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub async fn export_snapshot(&self) -> Result<Snapshot, ClientError> {
+        self.get_json("/admin/snapshot").await
+    }
+
+    pub async fn import_snapshot(&self, snapshot: &Snapshot) -> Result<String, ClientError> {
+        self.post_text("/admin/snapshot", snapshot).await
+    }
+
+    // --- integration test control ---
+
+    pub async fn test_initialize(
+        &self,
+        args: &TestInitializeArguments,
+    ) -> Result<String, ClientError> {
+        self.post_text("/test/initialize", args).await
+    }
+
+    pub async fn test_start(&self, args: &TestStartArguments) -> Result<String, ClientError> {
+        self.post_text("/test/start", args).await
+    }
+
+    pub async fn test_finish(&self, args: &TestFinishArguments) -> Result<String, ClientError> {
+        self.post_text("/test/finish", args).await
+    }
+
+    /// Reset a finished group so it can be started again
+    pub async fn test_rerun(&self, group: &str) -> Result<String, ClientError> {
+        self.post_text(
+            "/test/rerun",
+            &TestRerunArguments {
+                group: group.to_string(),
+            },
+        )
+        .await
+    }
+
+    pub async fn test_report(&self) -> Result<TestReport, ClientError> {
+        self.get_json("/test/report").await
+    }
+
+    pub async fn test_report_junit(&self) -> Result<String, ClientError> {
+        let query = serde_urlencoded::to_string(ReportQuery {
+            format: ReportFormat::Junit,
+        })?;
+        self.send::<()>(Method::GET, &format!("/test/report?{}", query), None)
+            .await
+    }
+
+    pub async fn test_status(&self) -> Result<IntegrationTest, ClientError> {
+        self.get_json("/test/status").await
+    }
+
+    pub async fn test_is_finished(&self) -> Result<bool, ClientError> {
+        match self
+            .send::<()>(Method::GET, "/test/finished/check", None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.code() == Some(ErrorCode::TestNotFinished) => Ok(false),
+            Err(ClientError::UnexpectedResponse { status, .. })
+                if status == StatusCode::BAD_REQUEST =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[test]
+fn test_only_idempotent_requests_retry_server_errors() {
+    let error = |status| ClientError::Api {
+        status,
+        body: ApiErrorBody {
+            code: ErrorCode::AuditLogUnavailable,
+            message: String::new(),
+            details: None,
+        },
+    };
+    assert!(error(StatusCode::INTERNAL_SERVER_ERROR).is_retryable(&Method::GET));
+    assert!(!error(StatusCode::INTERNAL_SERVER_ERROR).is_retryable(&Method::POST));
+    assert!(!error(StatusCode::TOO_MANY_REQUESTS).is_retryable(&Method::POST));
+    assert!(!error(StatusCode::BAD_REQUEST).is_retryable(&Method::GET));
+}
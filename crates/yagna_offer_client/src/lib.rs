@@ -0,0 +1,61 @@
+//! Async client for the Yagna offer server.
+//!
+//! The request and response types are defined by the server and re-exported here for
+//! provider and requestor integrations. Because of that the server cannot use the client,
+//! its mirror sync shares only the list path and entry type with it.
+
+mod client;
+
+pub use client::{ClientError, MatcherClient, RetryPolicy};
+pub use yagna_offer_server::model;
+pub use yagna_offer_server::offers::{MirrorSyncStats, OfferInsertResult, OfferInsertStatus};
+pub use yagna_offer_server::NodeId;
+
+pub mod types {
+    pub use yagna_offer_server::allocations::{
+        Allocation, AllocationSource, Allocations, LeaseState,
+    };
+    pub use yagna_offer_server::audit::{AuditAction, AuditEntry};
+    pub use yagna_offer_server::config::{
+        AuditConfig, GroupingConfig, JobConfig, LivenessConfig, Preemption, RecordingConfig,
+        ReplacedOfferAssignment, RequestorSchedule, ServerConfig, StorageConfig,
+    };
+    pub use yagna_offer_server::grouping::GroupingEpoch;
+    pub use yagna_offer_server::health::{HealthReport, Readiness};
+    pub use yagna_offer_server::jobs::{JobStats, JobStatus};
+    pub use yagna_offer_server::rest::admin::audit::AuditQuery;
+    pub use yagna_offer_server::rest::admin::invariants::{
+        AllocationIssue, InvariantReport, QueueEntryIssue,
+    };
+    pub use yagna_offer_server::rest::admin::offers::{
+        BulkReassign, OfferAssignmentChange, OfferInspection, OfferReassign, OfferSelector,
+        QueuePosition,
+    };
+    pub use yagna_offer_server::rest::allocations::{
+        AllocationFilter, AllocationListing, RequestorAllocationCounts,
+    };
+    pub use yagna_offer_server::rest::demand::add_offer_to_demand::AddOfferToDemand;
+    pub use yagna_offer_server::rest::demand::list_demands::DemandListEntry;
+    pub use yagna_offer_server::rest::demand::pick_offer_to_demand::PickOfferToDemand;
+    pub use yagna_offer_server::rest::demand::take_offer_from_queue::ModelOffer;
+    pub use yagna_offer_server::rest::demand::TakeOfferFromQueue;
+    pub use yagna_offer_server::rest::error::{ApiErrorBody, ErrorCode};
+    pub use yagna_offer_server::rest::offer::get_if_available::FilterAttributes;
+    pub use yagna_offer_server::rest::offer::list_offers::{OfferListEntry, OfferListQuery};
+    pub use yagna_offer_server::rest::offer::unsubscribe_offer::{
+        OfferUnsubscribe, OfferUnsubscribeResult,
+    };
+    pub use yagna_offer_server::rest::provider::{
+        ProviderEntry, ProviderHeartbeat, ProviderLivenessInfo,
+    };
+    pub use yagna_offer_server::rest::test::report::{
+        GroupOutcome, GroupReport, ReportFormat, ReportQuery, TestReport,
+    };
+    pub use yagna_offer_server::rest::test::{
+        TestFinishArguments, TestInitializeArguments, TestRerunArguments, TestStartArguments,
+    };
+    pub use yagna_offer_server::state::{
+        DemandObj, DemandProgress, Demands, IntegrationTest, IntegrationTestGroup, OfferObj,
+        Offers, ProviderLiveness, Providers, Snapshot,
+    };
+}
@@ -0,0 +1,306 @@
+use crate::clock::checked_sub_secs;
+use crate::state::{AppState, Demands, OfferObj, Offers};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+/// How an offer got assigned to a requestor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AllocationSource {
+    /// Periodic matching
+    Matching,
+    /// Periodic matching, taken from the queue of a lower priority demand
+    Preemption,
+    /// `/requestor/demand/append-offer`
+    AppendOffer,
+    /// `/requestor/demand/append-any-offer`
+    AppendAnyOffer,
+    /// `/offer/take`
+    OfferTake,
+    /// Carried over from an older offer of the same provider
+    Migrated,
+    /// Offer was already assigned in an imported snapshot
+    Snapshot,
+    /// `/admin/offer/{id}/reassign` and its bulk form
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum LeaseState {
+    /// Waiting in a demand queue
+    Queued,
+    /// Handed to the requestor
+    Taken,
+    /// Returned to the pool when the demand was cancelled or expired
+    Released,
+    /// Moved to a higher priority demand
+    Preempted,
+    /// Offer superseded by a newer offer of the same provider
+    Replaced,
+    /// Provider unsubscribed the offer
+    Withdrawn,
+    /// Returned to the pool or moved to another demand by an administrator
+    Unassigned,
+    Expired,
+    Cleared,
+}
+
+impl LeaseState {
+    /// Live allocations hold the offer, the others are history
+    pub fn is_live(&self) -> bool {
+        matches!(self, LeaseState::Queued | LeaseState::Taken)
+    }
+}
+
+/// One assignment of an offer to a requestor
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Allocation {
+    pub id: u64,
+    pub offer_id: String,
+    #[schema(value_type = String)]
+    pub provider_id: NodeId,
+    #[schema(value_type = String)]
+    pub requestor_id: NodeId,
+    /// `None` for offers taken without a demand
+    pub demand_id: Option<String>,
+    pub source: AllocationSource,
+    pub allocated_at: DateTime<Utc>,
+    pub state: LeaseState,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// Every assignment of offers to requestors, live ones and recent history.
+/// Live counts used for fairness are derived from here.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct Allocations {
+    /// Ordered by id, which is the order of allocation
+    pub allocations: Vec<Allocation>,
+    /// Offer id to the index of its live allocation
+    #[serde(skip)]
+    live: HashMap<String, usize>,
+    #[serde(skip)]
+    next_id: u64,
+}
+
+impl Allocations {
+    /// Rebuild the lookup of live allocations, needed after deserializing
+    pub fn reindex(&mut self) {
+        self.live = self
+            .allocations
+            .iter()
+            .enumerate()
+            .filter(|(_, allocation)| allocation.state.is_live())
+            .map(|(index, allocation)| (allocation.offer_id.clone(), index))
+            .collect();
+        self.next_id = self.allocations.last().map(|a| a.id + 1).unwrap_or(0);
+    }
+
+    /// Record that `offer` was assigned to `requestor_id`. An earlier live allocation of the
+    /// offer, which should not exist, is released.
+    pub fn allocate(
+        &mut self,
+        offer: &OfferObj,
+        requestor_id: NodeId,
+        demand_id: Option<&str>,
+        source: AllocationSource,
+        state: LeaseState,
+        now: DateTime<Utc>,
+    ) -> &Allocation {
+        if self
+            .end(&offer.offer.id, LeaseState::Released, now)
+            .is_some()
+        {
+            log::warn!(
+                "Offer {} was allocated again without being released",
+                offer.offer.id
+            );
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.live
+            .insert(offer.offer.id.clone(), self.allocations.len());
+        self.allocations.push(Allocation {
+            id,
+            offer_id: offer.offer.id.clone(),
+            provider_id: offer.offer.provider_id,
+            requestor_id,
+            demand_id: demand_id.map(str::to_string),
+            source,
+            allocated_at: now,
+            state,
+            ended_at: None,
+        });
+        self.allocations.last().unwrap()
+    }
+
+    /// End the live allocation of an offer, returns it
+    pub fn end(
+        &mut self,
+        offer_id: &str,
+        state: LeaseState,
+        now: DateTime<Utc>,
+    ) -> Option<Allocation> {
+        let index = self.live.remove(offer_id)?;
+        let allocation = &mut self.allocations[index];
+        allocation.state = state;
+        allocation.ended_at = Some(now);
+        Some(allocation.clone())
+    }
+
+    /// The requestor took the offer from the demand queue
+    pub fn mark_taken(&mut self, offer_id: &str) {
+        if let Some(index) = self.live.get(offer_id) {
+            self.allocations[*index].state = LeaseState::Taken;
+        }
+    }
+
+    /// Queued and taken offers are inherited by the demand that replaced their demand
+    pub fn move_demand(&mut self, replaced_demand_id: &str, demand_id: &str) {
+        for index in self.live.values() {
+            let allocation = &mut self.allocations[*index];
+            if allocation.demand_id.as_deref() == Some(replaced_demand_id) {
+                allocation.demand_id = Some(demand_id.to_string());
+            }
+        }
+    }
+
+    /// The allocation of a replaced offer continues with the newer offer of the provider
+    pub fn migrate(&mut self, replaced_offer_id: &str, offer: &OfferObj, now: DateTime<Utc>) {
+        let Some(state) = self
+            .live_allocation(replaced_offer_id)
+            .map(|replaced| replaced.state)
+        else {
+            return;
+        };
+        let Some(replaced) = self.end(replaced_offer_id, LeaseState::Replaced, now) else {
+            return;
+        };
+        self.allocate(
+            offer,
+            replaced.requestor_id,
+            replaced.demand_id.as_deref(),
+            AllocationSource::Migrated,
+            state,
+            now,
+        );
+    }
+
+    pub fn live_allocation(&self, offer_id: &str) -> Option<&Allocation> {
+        self.live
+            .get(offer_id)
+            .map(|index| &self.allocations[*index])
+    }
+
+    pub fn live_allocations(&self) -> impl Iterator<Item = &Allocation> {
+        self.live.values().map(|index| &self.allocations[*index])
+    }
+
+    /// Offers currently held by each requestor
+    pub fn live_counts(&self) -> HashMap<NodeId, u64> {
+        let mut counts = HashMap::new();
+        for allocation in self.live_allocations() {
+            *counts.entry(allocation.requestor_id).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Record assigned offers the ledger does not know about, e.g. from an older snapshot
+    pub fn adopt_assigned(&mut self, offers: &Offers, demands: &Demands, now: DateTime<Utc>) {
+        for offer in offers.offer_map.values() {
+            let Some(requestor_id) = offer.requestor_id else {
+                continue;
+            };
+            if self.live.contains_key(&offer.offer.id) {
+                continue;
+            }
+            let demand = demands.demand_map.values().find(|demand| {
+                demand.demand.node_id == requestor_id && demand.offer_list.contains(&offer.offer.id)
+            });
+            let state = match demand {
+                Some(_) => LeaseState::Queued,
+                None => LeaseState::Taken,
+            };
+            self.allocate(
+                offer,
+                requestor_id,
+                demand.map(|demand| demand.demand.id.as_str()),
+                AllocationSource::Snapshot,
+                state,
+                now,
+            );
+        }
+    }
+
+    /// Forget allocations that ended before `before`
+    pub fn prune(&mut self, before: DateTime<Utc>) -> usize {
+        let len = self.allocations.len();
+        self.allocations.retain(|allocation| {
+            allocation
+                .ended_at
+                .map(|ended_at| ended_at >= before)
+                .unwrap_or(true)
+        });
+        let pruned = len - self.allocations.len();
+        if pruned > 0 {
+            let next_id = self.next_id;
+            self.reindex();
+            self.next_id = next_id;
+        }
+        pruned
+    }
+}
+
+/// Drop ended allocations older than `cleanup.allocation_history_secs`
+pub async fn prune_allocation_history(data: web::Data<AppState>) {
+    let ended_before = checked_sub_secs(data.now(), data.config().cleanup.allocation_history_secs)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let mut allocations = data.allocations.lock().await;
+    let pruned = allocations.prune(ended_before);
+    if pruned > 0 {
+        log::debug!("Pruned {} ended allocations", pruned);
+    }
+}
+
+#[test]
+fn test_allocation_lifecycle() {
+    use crate::state::test_offer_obj;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let requestor_id = NodeId::default();
+    let mut allocations = Allocations::default();
+    let first = test_offer_obj("first", now);
+    allocations.allocate(
+        &first,
+        requestor_id,
+        Some("demand"),
+        AllocationSource::Matching,
+        LeaseState::Queued,
+        now,
+    );
+    allocations.mark_taken("first");
+    allocations.migrate("first", &test_offer_obj("second", now), now);
+    assert_eq!(allocations.live_counts()[&requestor_id], 1);
+    let migrated = allocations.live_allocation("second").unwrap();
+    assert_eq!(migrated.state, LeaseState::Taken);
+    assert_eq!(migrated.demand_id.as_deref(), Some("demand"));
+    assert_eq!(allocations.allocations[0].state, LeaseState::Replaced);
+
+    allocations.end("second", LeaseState::Expired, now);
+    assert!(allocations.live_counts().is_empty());
+    assert_eq!(allocations.prune(now + chrono::Duration::seconds(1)), 2);
+    allocations.allocate(
+        &first,
+        requestor_id,
+        None,
+        AllocationSource::OfferTake,
+        LeaseState::Taken,
+        now,
+    );
+    assert_eq!(allocations.allocations[0].id, 2);
+}
@@ -0,0 +1,445 @@
+use crate::allocations::Allocation;
+use crate::clock::{Clock, SystemClock};
+use crate::config::AuditConfig;
+use crate::offers::{OfferInsertResult, OfferInsertStatus, OfferRemoval};
+use crate::state::OfferObj;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Callers can name themselves, otherwise their address is recorded
+pub const CALLER_ID_HEADER: &str = "x-caller-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditAction {
+    OfferPushed,
+    /// A newer offer of the provider took the place of this one
+    OfferReplaced,
+    OfferExpired,
+    OfferCleared,
+    OfferUnsubscribed,
+    OfferAssigned,
+    /// Taken away from a demand for a higher priority one
+    OfferPreempted,
+    /// Taken back from its requestor by an administrator
+    OfferUnassigned,
+    OffersTaken,
+    DemandCreated,
+    /// An older demand of the same workload gave way to a new one
+    DemandReplaced,
+    DemandUpdated,
+    DemandRefreshed,
+    DemandCancelled,
+    DemandExpired,
+    TestInitialized,
+    TestGroupStarted,
+    TestGroupFinished,
+    /// Failed by the server after the group or test timeout
+    TestGroupTimedOut,
+    TestGroupRerun,
+    SnapshotImported,
+    ConfigReloaded,
+    JobPaused,
+    JobResumed,
+    JobRunRequested,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Increases with every entry, continues from the last entry of the file after a restart
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    /// `None` for changes made by the server on its own, e.g. expiry or periodic matching
+    pub caller: Option<String>,
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub demand_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requestor_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            seq: 0,
+            timestamp,
+            action,
+            caller: None,
+            request_id: None,
+            offer_id: None,
+            demand_id: None,
+            provider_id: None,
+            requestor_id: None,
+            details: Value::Null,
+        }
+    }
+
+    pub fn offer(mut self, offer_id: &str) -> Self {
+        self.offer_id = Some(offer_id.to_string());
+        self
+    }
+
+    pub fn demand(mut self, demand_id: &str) -> Self {
+        self.demand_id = Some(demand_id.to_string());
+        self
+    }
+
+    pub fn provider(mut self, provider_id: NodeId) -> Self {
+        self.provider_id = Some(provider_id.to_string());
+        self
+    }
+
+    pub fn requestor(mut self, requestor_id: NodeId) -> Self {
+        self.requestor_id = Some(requestor_id.to_string());
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// Offer assigned to a requestor, with the endpoint or strategy that did it
+    pub fn assigned(allocation: &Allocation) -> Self {
+        let entry = Self::new(AuditAction::OfferAssigned, allocation.allocated_at)
+            .offer(&allocation.offer_id)
+            .provider(allocation.provider_id)
+            .requestor(allocation.requestor_id)
+            .with_details(serde_json::json!({
+                "allocationId": allocation.id,
+                "source": allocation.source,
+                "state": allocation.state,
+            }));
+        match &allocation.demand_id {
+            Some(demand_id) => entry.demand(demand_id),
+            None => entry,
+        }
+    }
+}
+
+/// Who sent the request being handled, set by the middleware installed with `wrap_fn`
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub caller: String,
+    pub request_id: String,
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+impl RequestContext {
+    pub fn from_request(req: &ServiceRequest) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            caller: header(CALLER_ID_HEADER).unwrap_or_else(|| {
+                req.connection_info()
+                    .realip_remote_addr()
+                    .unwrap_or("unknown")
+                    .to_string()
+            }),
+            request_id: header(REQUEST_ID_HEADER)
+                .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>())),
+        }
+    }
+
+    /// Handle the request with this context and return the request id in the response
+    pub async fn scope<B>(
+        self,
+        response: impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>,
+    ) -> Result<ServiceResponse<B>, actix_web::Error> {
+        let request_id = self.request_id.clone();
+        let mut response = REQUEST_CONTEXT.scope(self, response).await?;
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    }
+
+    pub(crate) fn current() -> Option<Self> {
+        REQUEST_CONTEXT.try_with(|context| context.clone()).ok()
+    }
+}
+
+struct AuditWriter {
+    seq: u64,
+    file: Option<File>,
+    file_size: u64,
+    recent: VecDeque<AuditEntry>,
+}
+
+/// Append-only trail of state changes, written as JSON lines and rotated by size
+pub struct AuditLog {
+    config: AuditConfig,
+    clock: Arc<dyn Clock>,
+    writer: Mutex<AuditWriter>,
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Sequence number of the last readable entry in the file
+fn last_seq(path: &Path) -> u64 {
+    let Ok(file) = File::open(path) else {
+        return 0;
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+        .last()
+        .map(|entry| entry.seq)
+        .unwrap_or(0)
+}
+
+fn open_append(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> std::io::Result<Self> {
+        let (file, file_size, seq) = match &config.path {
+            Some(path) => {
+                let seq = last_seq(path);
+                let (file, size) = open_append(path)?;
+                log::info!("Writing audit log to {}", path.display());
+                (Some(file), size, seq)
+            }
+            None => (None, 0, 0),
+        };
+        Ok(Self {
+            config: config.clone(),
+            clock: Arc::new(SystemClock),
+            writer: Mutex::new(AuditWriter {
+                seq,
+                file,
+                file_size,
+                recent: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Entries built by the log itself are timestamped by `clock`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn record(&self, mut entry: AuditEntry) {
+        if let Some(context) = RequestContext::current() {
+            entry.caller = Some(context.caller);
+            entry.request_id = Some(context.request_id);
+        }
+        let mut writer = self.writer.lock().unwrap();
+        writer.seq += 1;
+        entry.seq = writer.seq;
+
+        let Some(path) = &self.config.path else {
+            writer.recent.push_back(entry);
+            while writer.recent.len() > self.config.memory_entries {
+                writer.recent.pop_front();
+            }
+            return;
+        };
+        self.write_line(path, &mut writer, &entry);
+    }
+
+    /// Make sure recorded entries reach the disk
+    pub fn flush(&self) -> std::io::Result<()> {
+        match &mut self.writer.lock().unwrap().file {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_all(&self, entries: impl IntoIterator<Item = AuditEntry>) {
+        for entry in entries {
+            self.record(entry);
+        }
+    }
+
+    /// Record a pushed offer and the older offer of the provider it replaced
+    pub fn offer_inserted(&self, result: &OfferInsertResult, provider_id: NodeId) {
+        if !matches!(
+            result.status,
+            OfferInsertStatus::Added | OfferInsertStatus::Replaced
+        ) {
+            return;
+        }
+        self.record(
+            AuditEntry::new(AuditAction::OfferPushed, self.clock.now())
+                .offer(&result.offer_id)
+                .provider(provider_id),
+        );
+        if let Some(replaced_offer_id) = &result.replaced_offer_id {
+            let entry = AuditEntry::new(AuditAction::OfferReplaced, self.clock.now())
+                .offer(replaced_offer_id)
+                .provider(provider_id)
+                .with_details(serde_json::json!({
+                    "replacedBy": result.offer_id,
+                    "assignment": result.assignment,
+                }));
+            self.record(match result.requestor_id {
+                Some(requestor_id) => entry.requestor(requestor_id),
+                None => entry,
+            });
+        }
+    }
+
+    /// Record removed offers, assigned ones with the requestor that lost them
+    pub fn offers_removed(&self, removed: &[OfferObj], reason: OfferRemoval) {
+        let action = match reason {
+            OfferRemoval::Expired => AuditAction::OfferExpired,
+            OfferRemoval::Cleared => AuditAction::OfferCleared,
+            OfferRemoval::Replaced => AuditAction::OfferReplaced,
+            OfferRemoval::Unsubscribed => AuditAction::OfferUnsubscribed,
+        };
+        let now = self.clock.now();
+        self.record_all(removed.iter().map(|offer| {
+            let entry = AuditEntry::new(action, now)
+                .offer(&offer.offer.id)
+                .provider(offer.offer.provider_id);
+            match offer.requestor_id {
+                Some(requestor_id) => entry.requestor(requestor_id),
+                None => entry,
+            }
+        }));
+    }
+
+    fn write_line(&self, path: &Path, writer: &mut AuditWriter, entry: &AuditEntry) {
+        let mut line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to serialize audit entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+        if writer.file_size > 0 && writer.file_size + line.len() as u64 > self.config.max_file_bytes
+        {
+            if let Err(e) = self.rotate(path, writer) {
+                log::error!("Failed to rotate audit log {}: {}", path.display(), e);
+            }
+        }
+        if writer.file.is_none() {
+            // rotation failed half way, keep writing to the current path
+            match open_append(path) {
+                Ok((file, size)) => {
+                    writer.file = Some(file);
+                    writer.file_size = size;
+                }
+                Err(e) => {
+                    log::error!("Failed to open audit log {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if let Some(file) = writer.file.as_mut() {
+            match file.write_all(line.as_bytes()) {
+                Ok(()) => writer.file_size += line.len() as u64,
+                Err(e) => log::error!("Failed to write audit log {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn rotate(&self, path: &Path, writer: &mut AuditWriter) -> std::io::Result<()> {
+        writer.file = None;
+        for index in (1..self.config.max_files).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(path, index + 1))?;
+            }
+        }
+        std::fs::rename(path, rotated_path(path, 1))?;
+        let (file, size) = open_append(path)?;
+        writer.file = Some(file);
+        writer.file_size = size;
+        Ok(())
+    }
+
+    /// All entries still available, oldest first: the rotated files and the current one,
+    /// or the entries kept in memory when there is no file
+    pub fn entries(&self) -> std::io::Result<Vec<AuditEntry>> {
+        let writer = self.writer.lock().unwrap();
+        let Some(path) = &self.config.path else {
+            return Ok(writer.recent.iter().cloned().collect());
+        };
+        let mut entries = Vec::new();
+        let files = (1..=self.config.max_files)
+            .rev()
+            .map(|index| rotated_path(path, index))
+            .chain([path.clone()]);
+        for file_path in files {
+            let file = match File::open(&file_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                match serde_json::from_str::<AuditEntry>(&line?) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => log::warn!("Skipping audit line in {}: {}", file_path.display(), e),
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[test]
+fn test_audit_log_rotation() {
+    let path = std::env::temp_dir().join(format!("audit-test-{}.jsonl", rand::random::<u64>()));
+    let config = AuditConfig {
+        path: Some(path.clone()),
+        max_file_bytes: 300,
+        max_files: 2,
+        memory_entries: 10,
+    };
+    let audit = AuditLog::open(&config).unwrap();
+    for i in 0..20 {
+        audit.record(
+            AuditEntry::new(AuditAction::OfferPushed, Utc::now()).offer(&format!("offer{}", i)),
+        );
+    }
+    let entries = audit.entries().unwrap();
+    // older files were dropped, what is left is in order and ends with the newest entry
+    assert!(!entries.is_empty() && entries.len() < 20);
+    assert!(entries.windows(2).all(|w| w[0].seq + 1 == w[1].seq));
+    assert_eq!(entries.last().unwrap().offer_id.as_deref(), Some("offer19"));
+    assert!(!rotated_path(&path, 3).exists());
+    for file_path in [path.clone(), rotated_path(&path, 1), rotated_path(&path, 2)] {
+        let _ = std::fs::remove_file(file_path);
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+/// Source of the current time for expiry, liveness and scheduling decisions
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// `time` moved by `secs` seconds, `None` when the result is out of range
+pub fn checked_add_secs(time: DateTime<Utc>, secs: f64) -> Option<DateTime<Utc>> {
+    time.checked_add_signed(chrono::Duration::try_milliseconds((secs * 1000.0) as i64)?)
+}
+
+pub fn checked_sub_secs(time: DateTime<Utc>, secs: f64) -> Option<DateTime<Utc>> {
+    time.checked_sub_signed(chrono::Duration::try_milliseconds((secs * 1000.0) as i64)?)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that only moves when told to, used by tests and replays
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,597 @@
+use crate::model::demand::base::DemandSubscription;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+use utoipa::ToSchema;
+
+/// Pick intervals at or above this disable periodic matching, whichever source set them.
+/// Kept for compatibility with deployments that used it before `matching.enabled` existed.
+const LEGACY_PICK_DISABLED_SECS: f64 = 1E9;
+
+#[derive(Debug, StructOpt, Clone, Default)]
+pub struct CliOptions {
+    #[structopt(
+        long = "config",
+        env = "OFFER_SERVER_CONFIG",
+        help = "Path to the TOML configuration file"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[structopt(
+        long = "http-port",
+        help = "Port number of the server [default: 15155]"
+    )]
+    pub http_port: Option<u16>,
+
+    #[structopt(
+        long = "http-addr",
+        help = "Bind address of the server [default: 127.0.0.1]"
+    )]
+    pub http_addr: Option<String>,
+
+    #[structopt(
+        long = "file-name",
+        help = "File offers and demands are saved to on shutdown and loaded from at start"
+    )]
+    pub file_name: Option<PathBuf>,
+
+    #[structopt(long = "offer-source-url", help = "Offer list URL of the mirror")]
+    pub offer_source_url: Option<String>,
+
+    #[structopt(
+        long = "pick-offers-interval-secs",
+        help = "Interval between periodic matching rounds"
+    )]
+    pub pick_offers_interval_secs: Option<f64>,
+
+    #[structopt(
+        long = "offer-group",
+        help = "Regex on provider node name limiting which offers are matched"
+    )]
+    pub offer_group: Option<String>,
+
+    #[structopt(long = "audit-log", help = "JSON lines file of the audit trail")]
+    pub audit_log: Option<PathBuf>,
+
+    #[structopt(
+        long = "record-requests",
+        help = "JSON lines file every API request is appended to, for replaying it later"
+    )]
+    pub record_requests: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub addr: String,
+    pub port: u16,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1".to_string(),
+            port: 15155,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// URL of `/offers/list` on another matcher, offers are copied from there
+    pub source_url: Option<String>,
+    pub sync_interval_secs: f64,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            source_url: None,
+            sync_interval_secs: 300.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchingConfig {
+    /// Periodically assign offers to demands
+    pub enabled: bool,
+    pub pick_interval_secs: f64,
+    /// Regex on provider node name, used in integration tests to separate provider groups.
+    /// When it is not a valid regex, node names containing `<offer_group>-` match.
+    pub offer_group: Option<String>,
+    pub log_every_secs: f64,
+    /// What happens to the assignment of an offer superseded by a newer one from the same provider
+    pub replaced_offer_assignment: ReplacedOfferAssignment,
+    /// Whether a demand that finds no free offer can take queued offers of lower priority demands
+    pub preemption: Preemption,
+    /// Priority and weight of demands of a requestor node, unless set on the demand itself
+    pub requestors: BTreeMap<String, RequestorSchedule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Preemption {
+    #[default]
+    Never,
+    /// Offers still waiting in the queue of a lower priority demand are moved over
+    QueuedOffers,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestorSchedule {
+    pub priority: Option<i32>,
+    pub weight: Option<f64>,
+}
+
+/// Effective scheduling parameters of a demand
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DemandSchedule {
+    /// Higher priority demands are served first
+    pub priority: i32,
+    /// Share of offers relative to demands of the same priority
+    pub weight: f64,
+}
+
+impl MatchingConfig {
+    pub fn schedule(&self, demand: &DemandSubscription) -> DemandSchedule {
+        let requestor = self.requestors.get(&demand.node_id.to_string());
+        DemandSchedule {
+            priority: demand
+                .priority
+                .or(requestor.and_then(|r| r.priority))
+                .unwrap_or(0),
+            weight: demand
+                .weight
+                .or(requestor.and_then(|r| r.weight))
+                .unwrap_or(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ReplacedOfferAssignment {
+    /// The new offer is assigned to the same requestor and takes the old one's place in queues
+    #[default]
+    Migrate,
+    /// The new offer is left unassigned, the old one is dropped from queues
+    Invalidate,
+}
+
+impl Default for MatchingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pick_interval_secs: 30.0,
+            offer_group: None,
+            log_every_secs: 10.0,
+            replaced_offer_assignment: ReplacedOfferAssignment::default(),
+            preemption: Preemption::default(),
+            requestors: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanupConfig {
+    pub offer_interval_secs: f64,
+    pub demand_interval_secs: f64,
+    /// Offers are kept this long after their expiration before being removed
+    pub offer_expiry_grace_secs: f64,
+    /// Ended allocations are kept in the ledger this long
+    pub allocation_history_secs: f64,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            offer_interval_secs: 60.0,
+            demand_interval_secs: 60.0,
+            offer_expiry_grace_secs: 3600.0,
+            allocation_history_secs: 86400.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LivenessConfig {
+    /// Skip offers of providers without a recent heartbeat when matching
+    pub require_heartbeat: bool,
+    pub heartbeat_interval_secs: f64,
+    /// Provider is considered offline after missing more heartbeats than this
+    pub max_missed_beats: u64,
+    /// Providers silent for this long are forgotten
+    pub forget_after_secs: f64,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            require_heartbeat: false,
+            heartbeat_interval_secs: 30.0,
+            max_missed_beats: 3,
+            forget_after_secs: 86400.0,
+        }
+    }
+}
+
+impl LivenessConfig {
+    pub fn missed_beats(&self, last_seen: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+        let elapsed = (now - last_seen).num_milliseconds().max(0) as f64 / 1000.0;
+        (elapsed / self.heartbeat_interval_secs) as u64
+    }
+
+    /// Whether offers of a provider last seen at `last_seen` can be handed out
+    pub fn is_alive(&self, last_seen: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        if !self.require_heartbeat {
+            return true;
+        }
+        match last_seen {
+            Some(last_seen) => self.missed_beats(last_seen, now) <= self.max_missed_beats,
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// JSON lines file of the audit trail, only the most recent entries are kept in memory
+    /// when not set
+    #[schema(value_type = Option<String>)]
+    pub path: Option<PathBuf>,
+    /// The file is rotated once it grows past this size
+    pub max_file_bytes: u64,
+    /// Rotated files kept next to the current one as `<path>.1`, `<path>.2`, ...
+    pub max_files: usize,
+    pub memory_entries: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+            memory_entries: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupingConfig {
+    /// Number of provider and offer groups
+    pub buckets: u32,
+    /// Seed the groups are derived from, random at every start when not set. Servers sharing
+    /// a seed put providers in the same groups.
+    pub seed: Option<u64>,
+    /// Reshuffle groups every `epoch_secs`, otherwise groups are fixed for the seed
+    pub rotate: bool,
+    /// Epochs are counted from the Unix epoch
+    pub epoch_secs: f64,
+}
+
+impl Default for GroupingConfig {
+    fn default() -> Self {
+        Self {
+            buckets: 1000,
+            seed: None,
+            rotate: true,
+            epoch_secs: 3600.0,
+        }
+    }
+}
+
+/// Scheduling of a background job, its interval comes from the section of the job
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    /// Up to this many seconds are added to every interval, so servers started together
+    /// do not run their jobs in lockstep
+    pub jitter_secs: f64,
+    /// Start paused, the job runs only when triggered or resumed through `/admin/jobs`
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Snapshot of offers, demands and allocations written on shutdown and loaded at start,
+    /// state is kept only in memory when not set
+    #[schema(value_type = Option<String>)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// JSON lines file every API request is appended to, nothing is recorded when not set.
+    /// The file is not rotated.
+    #[schema(value_type = Option<String>)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub http: HttpConfig,
+    pub mirror: MirrorConfig,
+    pub matching: MatchingConfig,
+    pub cleanup: CleanupConfig,
+    pub liveness: LivenessConfig,
+    pub grouping: GroupingConfig,
+    pub audit: AuditConfig,
+    pub storage: StorageConfig,
+    pub recording: RecordingConfig,
+    /// Keyed by job name: `cleanup`, `demand_expiry`, `mirror_sync`, `matching` or `regroup`
+    pub jobs: BTreeMap<String, JobConfig>,
+    /// Return errors as plain text with the status codes used by older versions
+    pub legacy_error_responses: bool,
+}
+
+fn env_value<T: FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => match value.parse::<T>() {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => bail!("Invalid value {} for {}: {}", value, name, e),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_flag(name: &str) -> Option<bool> {
+    env::var(name)
+        .ok()
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Offers are regrouped at the start of every epoch, shorter epochs would keep it busy
+const MIN_GROUP_EPOCH_SECS: f64 = 1.0;
+
+fn check_interval(name: &str, value: f64) -> anyhow::Result<()> {
+    if !value.is_finite() || value <= 0.0 {
+        bail!(
+            "{} has to be a positive number of seconds, got {}",
+            name,
+            value
+        );
+    }
+    Ok(())
+}
+
+impl ServerConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Configuration file, then environment variables, then command line options
+    pub fn load(cli: &CliOptions) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.apply_cli(cli);
+        config.apply_legacy_pick_interval();
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Ok(url) = env::var("OFFER_SOURCE_URL") {
+            self.mirror.source_url = Some(url);
+        }
+        if let Some(secs) = env_value("OFFER_MIRROR_SYNC_INTERVAL_SECS")? {
+            self.mirror.sync_interval_secs = secs;
+        }
+        if let Some(secs) = env_value("PICK_OFFERS_INTERVAL_SECS")? {
+            self.matching.pick_interval_secs = secs;
+        }
+        if let Some(secs) = env_value("LOG_EVERY_SEC")? {
+            self.matching.log_every_secs = secs;
+        }
+        if let Ok(group) = env::var("OFFER_GROUP") {
+            self.matching.offer_group = Some(group);
+        }
+        if let Some(legacy) = env_flag("LEGACY_ERROR_RESPONSES") {
+            self.legacy_error_responses = legacy;
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &CliOptions) {
+        if let Some(port) = cli.http_port {
+            self.http.port = port;
+        }
+        if let Some(addr) = &cli.http_addr {
+            self.http.addr = addr.clone();
+        }
+        if let Some(url) = &cli.offer_source_url {
+            self.mirror.source_url = Some(url.clone());
+        }
+        if let Some(secs) = cli.pick_offers_interval_secs {
+            self.matching.pick_interval_secs = secs;
+        }
+        if let Some(group) = &cli.offer_group {
+            self.matching.offer_group = Some(group.clone());
+        }
+        if let Some(path) = &cli.audit_log {
+            self.audit.path = Some(path.clone());
+        }
+        if let Some(path) = &cli.file_name {
+            self.storage.path = Some(path.clone());
+        }
+        if let Some(path) = &cli.record_requests {
+            self.recording.path = Some(path.clone());
+        }
+    }
+
+    /// Turn a huge pick interval into disabled matching with the default interval
+    fn apply_legacy_pick_interval(&mut self) {
+        if self.matching.pick_interval_secs >= LEGACY_PICK_DISABLED_SECS {
+            self.matching.enabled = false;
+            self.matching.pick_interval_secs = MatchingConfig::default().pick_interval_secs;
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(url) = &self.mirror.source_url {
+            reqwest::Url::parse(url)
+                .with_context(|| format!("mirror.source_url {} is not a valid URL", url))?;
+        }
+        check_interval("mirror.sync_interval_secs", self.mirror.sync_interval_secs)?;
+        check_interval(
+            "matching.pick_interval_secs",
+            self.matching.pick_interval_secs,
+        )?;
+        check_interval("matching.log_every_secs", self.matching.log_every_secs)?;
+        if let Some(group) = &self.matching.offer_group {
+            if let Err(e) = regex::Regex::new(group) {
+                log::warn!(
+                    "matching.offer_group {} is not a valid regex ({}), matching node names containing {}-",
+                    group,
+                    e,
+                    group
+                );
+            }
+        }
+        for (node_id, schedule) in &self.matching.requestors {
+            ya_client_model::NodeId::from_str(node_id)
+                .with_context(|| format!("matching.requestors: invalid node id {}", node_id))?;
+            if let Some(weight) = schedule.weight {
+                check_interval(&format!("matching.requestors.{}.weight", node_id), weight)?;
+            }
+        }
+        check_interval(
+            "cleanup.offer_interval_secs",
+            self.cleanup.offer_interval_secs,
+        )?;
+        check_interval(
+            "cleanup.demand_interval_secs",
+            self.cleanup.demand_interval_secs,
+        )?;
+        check_interval(
+            "cleanup.allocation_history_secs",
+            self.cleanup.allocation_history_secs,
+        )?;
+        check_interval(
+            "liveness.heartbeat_interval_secs",
+            self.liveness.heartbeat_interval_secs,
+        )?;
+        check_interval(
+            "liveness.forget_after_secs",
+            self.liveness.forget_after_secs,
+        )?;
+        for (name, job) in &self.jobs {
+            if !crate::jobs::JOB_NAMES.contains(&name.as_str()) {
+                bail!(
+                    "jobs.{}: unknown job, expected one of {}",
+                    name,
+                    crate::jobs::JOB_NAMES.join(", ")
+                );
+            }
+            if !job.jitter_secs.is_finite() || job.jitter_secs < 0.0 {
+                bail!(
+                    "jobs.{}.jitter_secs has to be a non-negative number, got {}",
+                    name,
+                    job.jitter_secs
+                );
+            }
+        }
+        if !self.cleanup.offer_expiry_grace_secs.is_finite()
+            || self.cleanup.offer_expiry_grace_secs < 0.0
+        {
+            bail!(
+                "cleanup.offer_expiry_grace_secs has to be a non-negative number, got {}",
+                self.cleanup.offer_expiry_grace_secs
+            );
+        }
+        if self.grouping.buckets == 0 {
+            bail!("grouping.buckets has to be positive");
+        }
+        check_interval("grouping.epoch_secs", self.grouping.epoch_secs)?;
+        if self.grouping.epoch_secs < MIN_GROUP_EPOCH_SECS {
+            bail!(
+                "grouping.epoch_secs has to be at least {} seconds, got {}",
+                MIN_GROUP_EPOCH_SECS,
+                self.grouping.epoch_secs
+            );
+        }
+        if self.audit.max_file_bytes == 0 || self.audit.max_files == 0 {
+            bail!("audit.max_file_bytes and audit.max_files have to be positive");
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_config_from_toml() {
+    let config: ServerConfig = toml::from_str(
+        r#"
+        legacy_error_responses = true
+
+        [matching]
+        pick_interval_secs = 5
+        offer_group = "brick-[0-9]+"
+
+        [cleanup]
+        offer_expiry_grace_secs = 0
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.matching.pick_interval_secs, 5.0);
+    assert_eq!(config.http, HttpConfig::default());
+    assert!(config.legacy_error_responses);
+
+    // falls back to a substring match
+    let mut fallback = config.clone();
+    fallback.matching.offer_group = Some("brick-(".to_string());
+    fallback.validate().unwrap();
+
+    let mut invalid = config.clone();
+    invalid.grouping.epoch_secs = 0.0005;
+    assert!(invalid.validate().is_err());
+
+    assert!(toml::from_str::<ServerConfig>("[matching]\npick_interval = 5").is_err());
+}
+
+#[test]
+fn test_huge_pick_interval_disables_matching() {
+    let cli = CliOptions {
+        pick_offers_interval_secs: Some(LEGACY_PICK_DISABLED_SECS),
+        ..Default::default()
+    };
+    let config = ServerConfig::load(&cli).unwrap();
+    assert!(!config.matching.enabled);
+    assert_eq!(
+        config.matching.pick_interval_secs,
+        MatchingConfig::default().pick_interval_secs
+    );
+
+    let cli = CliOptions {
+        pick_offers_interval_secs: Some(2.0),
+        ..Default::default()
+    };
+    let config = ServerConfig::load(&cli).unwrap();
+    assert!(config.matching.enabled);
+    assert_eq!(config.matching.pick_interval_secs, 2.0);
+}
@@ -0,0 +1,179 @@
+use crate::config::GroupingConfig;
+use crate::model::offer::attributes::OfferFlatAttributes;
+use crate::state::{AppState, Offers};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use utoipa::ToSchema;
+
+/// Grouping in effect at some point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupingEpoch {
+    /// Number of the epoch, always 0 when groups do not rotate
+    pub epoch: u64,
+    /// Groups of the epoch are the first four bytes of `sha3_256("{seed}{id}")`, big endian,
+    /// modulo `buckets`; the id is the provider id or the offer id
+    pub seed: u64,
+    pub buckets: u32,
+    /// `None` when groups do not rotate
+    pub started_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+fn hash_prefix(value: &str) -> [u8; 8] {
+    let hash = sha3::Sha3_256::digest(value.as_bytes());
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&hash[..8]);
+    prefix
+}
+
+/// Assigns providers and offers to buckets, so requestors can shard the offer pool with
+/// `providerGroupMin`/`providerGroupMax` and `idGroupMin`/`idGroupMax`
+#[derive(Debug, Clone)]
+pub struct Grouping {
+    config: GroupingConfig,
+    /// Seed of every epoch is derived from this one
+    base_seed: u64,
+}
+
+impl Grouping {
+    /// A random seed is drawn when the configuration does not fix one
+    pub fn new(config: &GroupingConfig) -> Self {
+        Self {
+            config: config.clone(),
+            base_seed: config.seed.unwrap_or_else(rand::random),
+        }
+    }
+
+    fn epoch_length(&self) -> Option<chrono::Duration> {
+        self.config
+            .rotate
+            .then(|| chrono::Duration::milliseconds((self.config.epoch_secs * 1000.0) as i64))
+    }
+
+    pub fn epoch(&self, now: DateTime<Utc>) -> u64 {
+        match self.epoch_length() {
+            Some(length) => {
+                (now.timestamp_millis().max(0) / length.num_milliseconds().max(1)) as u64
+            }
+            None => 0,
+        }
+    }
+
+    pub fn epoch_seed(&self, epoch: u64) -> u64 {
+        if !self.config.rotate {
+            return self.base_seed;
+        }
+        u64::from_be_bytes(hash_prefix(&format!("{}{}", self.base_seed, epoch)))
+    }
+
+    pub fn current(&self, now: DateTime<Utc>) -> GroupingEpoch {
+        let epoch = self.epoch(now);
+        let started_at = self.epoch_length().and_then(|length| {
+            DateTime::from_timestamp_millis(epoch as i64 * length.num_milliseconds())
+        });
+        GroupingEpoch {
+            epoch,
+            seed: self.epoch_seed(epoch),
+            buckets: self.config.buckets,
+            started_at,
+            ends_at: started_at.zip(self.epoch_length()).map(|(s, l)| s + l),
+        }
+    }
+
+    pub fn group(&self, epoch: u64, id: &str) -> u32 {
+        let prefix = hash_prefix(&format!("{}{}", self.epoch_seed(epoch), id));
+        u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) % self.config.buckets
+    }
+
+    /// Set the groups of an offer for `epoch`
+    pub fn assign(&self, attributes: &mut OfferFlatAttributes, offer_id: &str, epoch: u64) {
+        attributes.node_id_group = self.group(epoch, &attributes.node_id);
+        attributes.offer_id_group = self.group(epoch, offer_id);
+        attributes.group_epoch = epoch;
+    }
+
+    /// Recompute groups of offers grouped in another epoch, returns how many changed
+    pub fn regroup(&self, offers: &mut Offers, now: DateTime<Utc>) -> usize {
+        let epoch = self.epoch(now);
+        let mut regrouped = 0;
+        for offer in offers.offer_map.values_mut() {
+            if offer.attributes.group_epoch != epoch {
+                self.assign(&mut offer.attributes, &offer.offer.id, epoch);
+                regrouped += 1;
+            }
+        }
+        regrouped
+    }
+}
+
+pub async fn regroup_offers(data: web::Data<AppState>) {
+    let now = data.now();
+    let mut offers = data.lock.lock().await;
+    let regrouped = data.grouping.regroup(&mut offers, now);
+    if regrouped > 0 {
+        log::info!(
+            "Group epoch {} started, regrouped {} offers",
+            data.grouping.epoch(now),
+            regrouped
+        );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/offers/grouping",
+    tag = "offers",
+    responses((status = 200, description = "Current group epoch, seed and bucket count", body = GroupingEpoch))
+)]
+pub async fn grouping_epoch(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.grouping.current(data.now()))
+}
+
+#[test]
+fn test_grouping_epochs() {
+    let config = GroupingConfig {
+        buckets: 16,
+        seed: Some(42),
+        rotate: true,
+        epoch_secs: 3600.0,
+    };
+    let grouping = Grouping::new(&config);
+    let start = DateTime::parse_from_rfc3339("2025-12-11T11:00:00Z")
+        .unwrap()
+        .to_utc();
+    let epoch = grouping.current(start + chrono::Duration::minutes(59));
+    assert_eq!(epoch.started_at, Some(start));
+    assert_eq!(epoch.ends_at, Some(start + chrono::Duration::hours(1)));
+    assert_eq!(
+        grouping.epoch(start + chrono::Duration::hours(1)),
+        epoch.epoch + 1
+    );
+
+    // same seed on another server gives the same groups
+    let ids: Vec<String> = (0..50).map(|i| format!("0x{:040x}", i)).collect();
+    let groups = |grouping: &Grouping, epoch| {
+        ids.iter()
+            .map(|id| grouping.group(epoch, id))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        groups(&grouping, epoch.epoch),
+        groups(&Grouping::new(&config), epoch.epoch)
+    );
+    assert_ne!(
+        groups(&grouping, epoch.epoch),
+        groups(&grouping, epoch.epoch + 1)
+    );
+    assert!(groups(&grouping, epoch.epoch).iter().all(|g| *g < 16));
+
+    let fixed = Grouping::new(&GroupingConfig {
+        rotate: false,
+        ..config
+    });
+    assert_eq!(fixed.epoch(start), 0);
+    assert_eq!(fixed.epoch_seed(0), 42);
+    assert_eq!(fixed.current(start).ends_at, None);
+}
@@ -0,0 +1,197 @@
+use crate::jobs::JobStatus;
+use crate::rest::error::{ApiError, ErrorCode};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    /// `None` when no mirror is configured
+    pub initial_sync_done: Option<bool>,
+    pub storage_loaded: bool,
+    pub shutting_down: bool,
+    pub jobs: BTreeMap<String, JobStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthStatus {
+    Ok,
+    /// A job readiness does not depend on is stopped or stale
+    Degraded,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Stopped or stale jobs that are not essential for serving, e.g. the mirror download
+    pub degraded_jobs: Vec<String>,
+}
+
+/// Startup progress reported by `/ready`, together with the state of the background jobs
+#[derive(Debug, Default)]
+pub struct Health {
+    initial_sync_done: AtomicBool,
+    storage_loaded: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+impl Health {
+    pub fn set_initial_sync_done(&self) {
+        self.initial_sync_done.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_storage_loaded(&self) {
+        self.storage_loaded.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop handing out offers, the server is about to exit
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Ready once startup finished and every essential job is alive and not stale
+    pub fn readiness(&self, mirror_configured: bool, jobs: Vec<JobStatus>) -> Readiness {
+        let jobs = jobs
+            .into_iter()
+            .map(|job| (job.name.clone(), job))
+            .collect::<BTreeMap<_, _>>();
+        let initial_sync_done =
+            mirror_configured.then(|| self.initial_sync_done.load(Ordering::SeqCst));
+        let storage_loaded = self.storage_loaded.load(Ordering::SeqCst);
+        let shutting_down = self.is_shutting_down();
+        Readiness {
+            ready: initial_sync_done.unwrap_or(true)
+                && storage_loaded
+                && !shutting_down
+                && jobs.values().any(|job| job.essential)
+                && jobs
+                    .values()
+                    .filter(|job| job.essential)
+                    .all(|job| job.alive && !job.stale),
+            initial_sync_done,
+            storage_loaded,
+            shutting_down,
+            jobs,
+        }
+    }
+}
+
+/// Degraded when a job that is not essential for serving is stopped or stale
+pub fn health_report(jobs: Vec<JobStatus>) -> HealthReport {
+    let degraded_jobs: Vec<String> = jobs
+        .into_iter()
+        .filter(|job| !job.essential && (!job.alive || job.stale))
+        .map(|job| job.name)
+        .collect();
+    HealthReport {
+        status: match degraded_jobs.is_empty() {
+            true => HealthStatus::Ok,
+            false => HealthStatus::Degraded,
+        },
+        degraded_jobs,
+    }
+}
+
+/// Refuse to assign offers once shutdown has begun
+pub fn ensure_accepting_assignments(data: &AppState) -> Result<(), ApiError> {
+    if data.health.is_shutting_down() {
+        return Err(ApiError::new(
+            ErrorCode::ShuttingDown,
+            "Server is shutting down, no new offers are assigned",
+        ));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "meta",
+    responses((status = 200, description = "Server process is alive, with the jobs that stalled without affecting readiness", body = HealthReport))
+)]
+pub async fn health(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(health_report(data.jobs.statuses(data.now())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Server is ready to serve requests", body = Readiness),
+        (status = 503, description = "Server is starting, shutting down or an essential background job stalled", body = Readiness),
+    )
+)]
+pub async fn ready(data: web::Data<AppState>) -> HttpResponse {
+    let readiness = data.health.readiness(
+        data.config().mirror.source_url.is_some(),
+        data.jobs.statuses(data.now()),
+    );
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+#[test]
+fn test_readiness() {
+    let job = |stale| JobStatus {
+        name: crate::jobs::MATCHING.to_string(),
+        essential: true,
+        interval_secs: 10.0,
+        jitter_secs: 0.0,
+        paused: false,
+        alive: true,
+        running: false,
+        next_run_at: None,
+        stale,
+        stats: Default::default(),
+    };
+    let mirror = |stale| JobStatus {
+        name: crate::jobs::MIRROR_SYNC.to_string(),
+        essential: false,
+        ..job(stale)
+    };
+    let health = Health::default();
+    assert!(!health.readiness(false, vec![job(false)]).ready);
+
+    health.set_storage_loaded();
+    assert!(health.readiness(false, vec![job(false)]).ready);
+    assert!(!health.readiness(false, vec![job(true)]).ready);
+    assert!(!health.readiness(false, vec![]).ready);
+    // a stalled mirror only degrades health
+    assert!(
+        health
+            .readiness(false, vec![job(false), mirror(true)])
+            .ready
+    );
+    assert!(!health.readiness(false, vec![mirror(false)]).ready);
+    let report = health_report(vec![job(false), mirror(true)]);
+    assert_eq!(report.status, HealthStatus::Degraded);
+    assert_eq!(report.degraded_jobs, [crate::jobs::MIRROR_SYNC]);
+    assert_eq!(
+        health_report(vec![job(true), mirror(false)]).status,
+        HealthStatus::Ok
+    );
+    // mirror configured but not downloaded yet
+    assert!(!health.readiness(true, vec![job(false)]).ready);
+    health.set_initial_sync_done();
+    assert!(health.readiness(true, vec![job(false)]).ready);
+
+    health.begin_shutdown();
+    let readiness = health.readiness(false, vec![job(false)]);
+    assert!(readiness.shutting_down);
+    assert!(!readiness.ready);
+}
@@ -0,0 +1,372 @@
+use crate::clock::checked_add_secs;
+use crate::config::{JobConfig, ServerConfig};
+use crate::state::AppState;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+/// Expired offers, ended allocations and silent providers
+pub const CLEANUP: &str = "cleanup";
+pub const DEMAND_EXPIRY: &str = "demand_expiry";
+pub const MIRROR_SYNC: &str = "mirror_sync";
+pub const MATCHING: &str = "matching";
+/// New offer groups at the start of every group epoch
+pub const REGROUP: &str = "regroup";
+pub const JOB_NAMES: [&str; 5] = [CLEANUP, DEMAND_EXPIRY, MIRROR_SYNC, MATCHING, REGROUP];
+
+/// A job is stale when it has not finished a run for this many intervals
+const STALE_AFTER_INTERVALS: f64 = 3.0;
+
+pub type JobFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+type JobFn = Arc<dyn Fn(web::Data<AppState>) -> JobFuture + Send + Sync>;
+
+/// Periodic job run by the [`Scheduler`]
+pub struct JobSpec {
+    pub name: &'static str,
+    /// Read before every run, so reloaded intervals apply from the next run
+    pub interval: fn(&ServerConfig) -> f64,
+    /// Run as soon as the job starts instead of after the first interval
+    pub run_at_start: bool,
+    /// Runs start at multiples of the interval since the Unix epoch
+    pub aligned: bool,
+    /// The server is not ready while the job is stopped or stale
+    pub essential: bool,
+    pub run: JobFn,
+}
+
+impl JobSpec {
+    pub fn new<F, Fut>(name: &'static str, interval: fn(&ServerConfig) -> f64, run: F) -> Self
+    where
+        F: Fn(web::Data<AppState>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        Self {
+            name,
+            interval,
+            run_at_start: false,
+            aligned: false,
+            essential: false,
+            run: Arc::new(move |data| Box::pin(run(data))),
+        }
+    }
+
+    pub fn run_at_start(mut self) -> Self {
+        self.run_at_start = true;
+        self
+    }
+
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    pub fn essential(mut self) -> Self {
+        self.essential = true;
+        self
+    }
+}
+
+/// Seconds from `now` to the next multiple of `interval_secs` since the Unix epoch
+fn until_aligned(now: DateTime<Utc>, interval_secs: f64) -> f64 {
+    let interval_ms = (interval_secs * 1000.0) as i64;
+    if interval_ms <= 0 {
+        return interval_secs;
+    }
+    (interval_ms - now.timestamp_millis().rem_euclid(interval_ms)) as f64 / 1000.0
+}
+
+#[derive(Debug, Clone, Default)]
+struct JobState {
+    paused: bool,
+    run_requested: bool,
+    /// The supervising task is alive
+    alive: bool,
+    running: bool,
+    interval_secs: f64,
+    jitter_secs: f64,
+    started_at: Option<DateTime<Utc>>,
+    next_run_at: Option<DateTime<Utc>>,
+    stats: JobStats,
+}
+
+/// Run statistics of a job since the server started
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStats {
+    pub runs: u64,
+    pub failures: u64,
+    /// Runs that panicked, the job keeps its schedule afterwards
+    pub panics: u64,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub name: String,
+    pub interval_secs: f64,
+    /// Up to this many seconds are added to every interval
+    pub jitter_secs: f64,
+    pub paused: bool,
+    /// `false` once the job has stopped for good
+    pub alive: bool,
+    /// A run is in progress
+    pub running: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// Not paused and no successful run for several intervals
+    pub stale: bool,
+    /// Readiness depends on the job, other jobs only degrade `/health`
+    pub essential: bool,
+    #[serde(flatten)]
+    pub stats: JobStats,
+}
+
+struct Job {
+    spec: JobSpec,
+    state: Mutex<JobState>,
+    wake: Notify,
+}
+
+impl Job {
+    fn status(&self, now: DateTime<Utc>) -> JobStatus {
+        let state = self.state.lock().unwrap();
+        let since = state.stats.last_success_at.or(state.started_at);
+        let limit_ms = (state.interval_secs + state.jitter_secs) * STALE_AFTER_INTERVALS * 1000.0;
+        let stale = !state.paused
+            && since.is_some_and(|since| (now - since).num_milliseconds() as f64 > limit_ms);
+        JobStatus {
+            name: self.spec.name.to_string(),
+            interval_secs: state.interval_secs,
+            jitter_secs: state.jitter_secs,
+            paused: state.paused,
+            alive: state.alive,
+            running: state.running,
+            next_run_at: state.next_run_at,
+            stale,
+            essential: self.spec.essential,
+            stats: state.stats.clone(),
+        }
+    }
+
+    /// Run once in its own task, so a panic ends only this run
+    async fn run_once(&self, data: &web::Data<AppState>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running = true;
+            state.run_requested = false;
+            state.stats.last_started_at = Some(data.now());
+        }
+        let started = Instant::now();
+        let result = tokio::spawn((self.spec.run)(data.clone())).await;
+        let now = data.now();
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.stats.runs += 1;
+        state.stats.last_finished_at = Some(now);
+        state.stats.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+        match result {
+            Ok(Ok(())) => {
+                state.stats.last_success_at = Some(now);
+                state.stats.last_error = None;
+            }
+            Ok(Err(e)) => {
+                log::warn!("Job {} failed: {:#}", self.spec.name, e);
+                state.stats.failures += 1;
+                state.stats.last_error = Some(format!("{:#}", e));
+            }
+            Err(e) => {
+                log::error!("Job {} panicked: {}", self.spec.name, e);
+                state.stats.panics += 1;
+                state.stats.last_error = Some(format!("panic: {}", e));
+            }
+        }
+    }
+
+    async fn run_loop(self: Arc<Self>, data: web::Data<AppState>) {
+        let mut first = true;
+        loop {
+            let config = data.config();
+            let interval_secs = (self.spec.interval)(&config);
+            let jitter_secs = config
+                .jobs
+                .get(self.spec.name)
+                .map(|job| job.jitter_secs)
+                .unwrap_or_default();
+            let delay = if first && self.spec.run_at_start {
+                0.0
+            } else if self.spec.aligned {
+                until_aligned(data.now(), interval_secs) + rand::random::<f64>() * jitter_secs
+            } else {
+                interval_secs + rand::random::<f64>() * jitter_secs
+            };
+            first = false;
+            {
+                let mut state = self.state.lock().unwrap();
+                state.interval_secs = interval_secs;
+                state.jitter_secs = jitter_secs;
+                state.next_run_at = checked_add_secs(data.now(), delay);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::try_from_secs_f64(delay).unwrap_or(Duration::MAX)) => {}
+                _ = self.wake.notified() => {}
+            }
+            let run = {
+                let state = self.state.lock().unwrap();
+                !state.paused || state.run_requested
+            };
+            if run {
+                self.run_once(&data).await;
+            }
+        }
+    }
+}
+
+/// Owns the named background jobs of the server: runs them on their intervals with optional
+/// jitter, survives panics of single runs and lets administrators pause, resume or trigger them
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Mutex<BTreeMap<&'static str, Arc<Job>>>,
+}
+
+impl Scheduler {
+    /// Start the job, paused when its configuration says so
+    pub fn spawn(&self, data: web::Data<AppState>, spec: JobSpec) {
+        let name = spec.name;
+        let config = data
+            .config()
+            .jobs
+            .get(name)
+            .cloned()
+            .unwrap_or_else(JobConfig::default);
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState {
+                paused: config.paused,
+                alive: true,
+                interval_secs: (spec.interval)(&data.config()),
+                jitter_secs: config.jitter_secs,
+                started_at: Some(data.now()),
+                ..Default::default()
+            }),
+            spec,
+            wake: Notify::new(),
+        });
+        self.jobs.lock().unwrap().insert(name, job.clone());
+        let handle = tokio::spawn(job.clone().run_loop(data));
+        tokio::spawn(async move {
+            if let Err(e) = handle.await {
+                log::error!("Job {} stopped: {}", name, e);
+            }
+            job.state.lock().unwrap().alive = false;
+        });
+    }
+
+    fn job(&self, name: &str) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn statuses(&self, now: DateTime<Utc>) -> Vec<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values().map(|job| job.status(now)).collect()
+    }
+
+    pub fn status(&self, name: &str, now: DateTime<Utc>) -> Option<JobStatus> {
+        self.job(name).map(|job| job.status(now))
+    }
+
+    /// Skip scheduled runs until resumed. Returns `None` for an unknown job.
+    pub fn pause(&self, name: &str) -> Option<()> {
+        self.job(name)?.state.lock().unwrap().paused = true;
+        Some(())
+    }
+
+    pub fn resume(&self, name: &str) -> Option<()> {
+        self.job(name)?.state.lock().unwrap().paused = false;
+        Some(())
+    }
+
+    pub fn is_paused(&self, name: &str) -> bool {
+        self.job(name)
+            .is_some_and(|job| job.state.lock().unwrap().paused)
+    }
+
+    /// Run the job now, also when it is paused; a run in progress is followed by another one
+    pub fn run_now(&self, name: &str) -> Option<()> {
+        let job = self.job(name)?;
+        job.state.lock().unwrap().run_requested = true;
+        job.wake.notify_one();
+        Some(())
+    }
+}
+
+#[actix_web::test]
+async fn test_job_survives_panics_and_can_be_paused() {
+    use crate::state::test_state_at_fixture_time;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let (data, now) = test_state_at_fixture_time();
+    let calls = Arc::new(AtomicU64::new(0));
+    let job_calls = calls.clone();
+    let spec = JobSpec::new(
+        "test",
+        |_| 3600.0,
+        move |_| {
+            let call = job_calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => panic!("first run panics"),
+                    1 => anyhow::bail!("second run fails"),
+                    _ => Ok(()),
+                }
+            }
+        },
+    )
+    .run_at_start();
+    let scheduler = Scheduler::default();
+    scheduler.spawn(data.clone(), spec);
+
+    let wait_for_runs = |runs: u64| {
+        let scheduler = &scheduler;
+        async move {
+            for _ in 0..100 {
+                if scheduler.status("test", now).unwrap().stats.runs >= runs {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("job did not run");
+        }
+    };
+    wait_for_runs(1).await;
+    scheduler.run_now("test").unwrap();
+    wait_for_runs(2).await;
+
+    scheduler.pause("test").unwrap();
+    scheduler.run_now("test").unwrap();
+    wait_for_runs(3).await;
+    let status = scheduler.status("test", now).unwrap();
+    assert!(status.paused && status.alive);
+    assert_eq!((status.stats.panics, status.stats.failures), (1, 1));
+    assert_eq!(status.stats.last_success_at, Some(now));
+    assert!(scheduler.pause("missing").is_none());
+}
+
+#[test]
+fn test_aligned_runs_start_at_multiples_of_the_interval() {
+    let epoch_start = DateTime::from_timestamp(7200, 0).unwrap();
+    assert_eq!(until_aligned(epoch_start, 3600.0), 3600.0);
+    assert_eq!(
+        until_aligned(epoch_start + chrono::Duration::milliseconds(1500), 3600.0),
+        3598.5
+    );
+}
@@ -0,0 +1,16 @@
+pub mod allocations;
+pub mod audit;
+pub mod clock;
+pub mod config;
+pub mod grouping;
+pub mod health;
+pub mod jobs;
+pub mod model;
+pub mod offers;
+pub mod recorder;
+pub mod replay;
+pub mod rest;
+pub mod server;
+pub mod state;
+
+pub use ya_client_model::NodeId;
@@ -1,13 +1,20 @@
+use crate::model::offer::attributes::{OfferFlatAttributes, ResourceRequirements};
+use crate::model::offer::properties::{
+    flatten, COMPUTATION_EXPIRATION, COMPUTATION_PREFIX, PAYMENT_PLATFORM_PREFIX, SUBNET,
+};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
 use ya_client_model::NodeId;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DemandSubscription {
     pub id: String,
     pub properties: String,
     pub constraints: String,
+    #[schema(value_type = String)]
     pub node_id: NodeId,
     /// Creation time of Demand on Requestor side.
     pub creation_ts: NaiveDateTime,
@@ -17,10 +24,168 @@ pub struct DemandSubscription {
     pub expiration_ts: NaiveDateTime,
     /// Filter by central net address
     pub central_net_address: Option<String>,
+    /// Number of offers the requestor wants, matching stops once queued and taken offers reach it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_count: Option<u64>,
+    /// Offers assigned to the demand in one matching round, 1 when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_per_tick: Option<u64>,
+    /// Higher priority demands are served first, falls back to the requestor setting, then 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Share of offers relative to demands of the same priority, falls back to the requestor
+    /// setting, then 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f64>,
+    /// Only offers providing at least these resources are matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirements: Option<ResourceRequirements>,
+    /// Payment platforms the requestor pays on, e.g. `erc20-hoodi-tglm`. When not set, the
+    /// `golem.com.payment.platform.*` entries of `properties` are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payment_platforms: Option<Vec<String>>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Identity of a demand that stays the same when the requestor subscribes again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkloadKey {
+    pub node_id: NodeId,
+    pub central_net_address: Option<String>,
+    pub properties: BTreeMap<String, String>,
+}
+
+/// What a demand asks of offers, computed once per matching round
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OfferCriteria {
+    pub requirements: Option<ResourceRequirements>,
+    /// Any platform is accepted when empty
+    pub payment_platforms: BTreeSet<String>,
+}
+
+impl OfferCriteria {
+    /// Whether matching may give the offer to the demand
+    pub fn accepts(&self, attributes: &OfferFlatAttributes) -> bool {
+        self.requirements
+            .as_ref()
+            .is_none_or(|requirements| attributes.meets(requirements))
+            && (self.payment_platforms.is_empty()
+                || attributes
+                    .payment_platforms
+                    .iter()
+                    .any(|platform| self.payment_platforms.contains(platform)))
+    }
+}
+
+impl DemandSubscription {
+    /// Payment platforms the requestor accepts, empty when it does not say
+    pub fn accepted_payment_platforms(&self) -> BTreeSet<String> {
+        if let Some(platforms) = &self.payment_platforms {
+            return platforms.iter().cloned().collect();
+        }
+        let Ok(properties) = serde_json::from_str(&self.properties) else {
+            return BTreeSet::new();
+        };
+        flatten(properties)
+            .keys()
+            .filter_map(|name| {
+                let platform = name.strip_prefix(PAYMENT_PLATFORM_PREFIX)?;
+                let platform = platform.strip_suffix(".address").unwrap_or(platform);
+                (!platform.contains('.')).then(|| platform.to_string())
+            })
+            .collect()
+    }
+
+    pub fn criteria(&self) -> OfferCriteria {
+        OfferCriteria {
+            requirements: self.requirements.clone(),
+            payment_platforms: self.accepted_payment_platforms(),
+        }
+    }
+
+    /// What the demand runs and where: the subnet and the `golem.srv.comp.*` properties
+    /// without the expiration. Requestors put timestamps into every new subscription, so
+    /// the raw properties and constraints differ after each restart.
+    pub fn workload_key(&self) -> WorkloadKey {
+        let properties = serde_json::from_str(&self.properties)
+            .map(flatten)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| {
+                name == SUBNET
+                    || (name.starts_with(COMPUTATION_PREFIX) && name != COMPUTATION_EXPIRATION)
+            })
+            .map(|(name, value)| (name, value.to_string()))
+            .collect();
+        WorkloadKey {
+            node_id: self.node_id,
+            central_net_address: self.central_net_address.clone(),
+            properties,
+        }
+    }
+
+    /// Same requestor asking for the same thing, e.g. after restarting
+    pub fn same_workload(&self, other: &DemandSubscription) -> bool {
+        self.workload_key() == other.workload_key()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DemandCancellation {
     pub demand_id: String,
 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DemandRefresh {
+    pub demand_id: String,
+    /// New expiration time, has to be in the future
+    pub expiration_ts: NaiveDateTime,
+}
+
+/// Fields left out are not changed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DemandUpdate {
+    pub demand_id: String,
+    pub properties: Option<String>,
+    pub constraints: Option<String>,
+    pub central_net_address: Option<String>,
+    pub target_count: Option<u64>,
+    pub max_per_tick: Option<u64>,
+    pub priority: Option<i32>,
+    pub weight: Option<f64>,
+    pub requirements: Option<ResourceRequirements>,
+    pub payment_platforms: Option<Vec<String>>,
+}
+
+#[test]
+fn test_offer_criteria_payment_platforms() {
+    let offer = crate::model::offer::base::test_offer();
+    let mut demand =
+        crate::state::test_demand_obj("demand-1", NodeId::default(), offer.timestamp).demand;
+    demand.properties = serde_json::json!({
+        "golem.com.payment.platform.erc20-hoodi-tglm.address": "0x01",
+        "golem.com.payment.protocol.version": 3,
+    })
+    .to_string();
+    let mut attributes = OfferFlatAttributes::from_gbo(
+        &offer,
+        &crate::grouping::Grouping::new(&Default::default()),
+        offer.timestamp,
+    );
+    assert_eq!(attributes.payment_platforms, vec!["erc20-polygon-glm"]);
+    assert!(!demand.criteria().accepts(&attributes));
+
+    attributes
+        .payment_platforms
+        .push("erc20-hoodi-tglm".to_string());
+    assert!(demand.criteria().accepts(&attributes));
+
+    // an explicit list replaces the platforms of the properties
+    demand.payment_platforms = Some(vec!["erc20-mainnet-glm".to_string()]);
+    assert!(!demand.criteria().accepts(&attributes));
+    demand.properties = "{}".to_string();
+    demand.payment_platforms = None;
+    assert!(demand.criteria().accepts(&attributes));
+}
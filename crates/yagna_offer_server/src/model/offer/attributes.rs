@@ -1,82 +1,299 @@
+use crate::grouping::Grouping;
 use crate::model::offer::base::GolemBaseOffer;
-use rand::Rng;
+use crate::model::offer::properties::Gpu;
+use chrono::{DateTime, Utc};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
-use sha3::Digest;
-use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct OfferFlatAttributes {
     pub exe_name: String,
+    /// Version of the runtime as the provider declares it
+    #[serde(default)]
+    pub runtime_version: Option<String>,
+    /// Enabled capabilities, e.g. `multi-activity`, `payload-manifest`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
     pub subnet: String,
     pub cpu_architecture: String,
     pub cpu_threads: u32,
+    /// 0 when the provider does not declare it, same for memory and storage
+    #[serde(default)]
+    pub cpu_cores: u32,
+    #[serde(default)]
+    pub memory_gib: f64,
+    #[serde(default)]
+    pub storage_gib: f64,
+    #[serde(default)]
+    pub gpus: Vec<Gpu>,
+    /// Names of the payment platforms the provider accepts
+    #[serde(default)]
+    pub payment_platforms: Vec<String>,
     pub node_id: String,
     pub node_name: String,
     pub node_id_group: u32,
     pub offer_id_group: u32,
-}
-static STATE: Mutex<(u64, u64)> = Mutex::new((0, 0));
-// (last_hour, random_value)
-
-//every hour new random number is generated
-pub fn get_static_random() -> u64 {
-    let secs = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    let hour = secs / 3600;
-    let mut state = STATE.lock().unwrap();
-
-    if state.0 != hour {
-        // Generate a fresh random value for this hour
-        let value = rand::rng().random();
-        *state = (hour, value);
-    }
-    state.1
+    /// Group epoch `node_id_group` and `offer_id_group` belong to
+    #[serde(default)]
+    pub group_epoch: u64,
 }
 
 impl OfferFlatAttributes {
-    pub fn from_gbo(gbo: &GolemBaseOffer) -> Self {
-        let node_id = gbo.provider_id.to_string();
-
-        let string_to_hash = format!("{}{}", get_static_random(), node_id);
-
-        let sha256_hash = sha3::Sha3_256::digest(string_to_hash.as_bytes());
-        let node_id_group = u32::from_be_bytes([
-            sha256_hash[0],
-            sha256_hash[1],
-            sha256_hash[2],
-            sha256_hash[3],
-        ]) % 1000;
-
-        let offer_id_hash = format!("{}{}", get_static_random(), gbo.id);
-        let offer_id_hash = sha3::Sha3_256::digest(offer_id_hash.as_bytes());
-
-        let offer_id_group = u32::from_be_bytes([
-            offer_id_hash[0],
-            offer_id_hash[1],
-            offer_id_hash[2],
-            offer_id_hash[3],
-        ]) % 1000;
-
-        OfferFlatAttributes {
-            node_id,
-            node_name: gbo.properties.golem.node.id.name.clone(),
-            node_id_group,
-            exe_name: gbo.properties.golem.runtime.name.clone(),
+    /// Groups are those of the epoch at `now`
+    pub fn from_gbo(gbo: &GolemBaseOffer, grouping: &Grouping, now: DateTime<Utc>) -> Self {
+        let mut attributes = OfferFlatAttributes {
+            node_id: gbo.provider_id.to_string(),
+            node_name: gbo.properties.node_name.clone(),
+            node_id_group: 0,
+            exe_name: gbo.properties.runtime_name.clone(),
+            runtime_version: gbo.properties.runtime_version.clone(),
+            capabilities: gbo.properties.capabilities(),
             subnet: gbo
                 .properties
-                .golem
-                .node
-                .debug
-                .as_ref()
-                .map(|f| f.subnet.clone())
+                .subnet
+                .clone()
                 .unwrap_or_else(|| "public".to_string()),
-            cpu_architecture: gbo.properties.golem.inf.cpu.architecture.clone(),
-            cpu_threads: gbo.properties.golem.inf.cpu.threads,
-            offer_id_group,
+            cpu_architecture: gbo.properties.cpu_architecture.clone(),
+            cpu_threads: gbo.properties.cpu_threads,
+            cpu_cores: gbo.properties.cpu_cores.unwrap_or(0),
+            memory_gib: gbo.properties.mem_gib.unwrap_or(0.0),
+            storage_gib: gbo.properties.storage_gib.unwrap_or(0.0),
+            gpus: gbo.properties.gpus(),
+            payment_platforms: gbo.properties.payment_platforms.keys().cloned().collect(),
+            offer_id_group: 0,
+            group_epoch: 0,
+        };
+        grouping.assign(&mut attributes, &gbo.id, grouping.epoch(now));
+        attributes
+    }
+
+    /// GPUs with at least `memory_gib` of video memory, all GPUs when `None`
+    pub fn gpus_with_memory(&self, memory_gib: Option<f64>) -> usize {
+        self.gpus
+            .iter()
+            .filter(|gpu| match memory_gib {
+                Some(min) => gpu.memory_gib.is_some_and(|memory| memory >= min),
+                None => true,
+            })
+            .count()
+    }
+
+    pub fn meets(&self, requirements: &ResourceRequirements) -> bool {
+        if let Some(runtime) = &requirements.runtime {
+            if !runtime.matches(&self.exe_name, self.runtime_version.as_deref()) {
+                return false;
+            }
+        }
+        if let Some(capabilities) = &requirements.capabilities {
+            if !capabilities
+                .iter()
+                .all(|capability| self.capabilities.contains(capability))
+            {
+                return false;
+            }
+        }
+        if requirements
+            .cpu_cores_min
+            .is_some_and(|min| self.cpu_cores < min)
+            || requirements
+                .memory_gib_min
+                .is_some_and(|min| self.memory_gib < min)
+            || requirements
+                .storage_gib_min
+                .is_some_and(|min| self.storage_gib < min)
+        {
+            return false;
+        }
+        if let Some(model) = &requirements.gpu_model {
+            if !self.gpus.iter().any(|gpu| {
+                gpu.model
+                    .as_ref()
+                    .is_some_and(|name| name.to_lowercase().contains(&model.to_lowercase()))
+            }) {
+                return false;
+            }
+        }
+        let gpu_count_min = match (requirements.gpu_count_min, requirements.gpu_memory_gib_min) {
+            (Some(count), _) => count,
+            // asking for video memory implies a GPU
+            (None, Some(_)) => 1,
+            (None, None) => 0,
+        };
+        self.gpus_with_memory(requirements.gpu_memory_gib_min) >= gpu_count_min as usize
+    }
+}
+
+/// Parse a version leniently, `0.4` is read as `0.4.0`
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches('v');
+    Version::parse(version).ok().or_else(|| {
+        let parts = version.split('.').count();
+        let padded = format!("{}{}", version, ".0".repeat(3usize.saturating_sub(parts)));
+        Version::parse(&padded).ok()
+    })
+}
+
+/// Runtime name optionally followed by a semver range, e.g. `ya-runtime-vm >=0.4, <0.6`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RuntimeSpec {
+    pub name: String,
+    pub version: Option<VersionReq>,
+}
+
+impl RuntimeSpec {
+    /// Offers without a parsable version do not match a version range
+    pub fn matches(&self, name: &str, version: Option<&str>) -> bool {
+        if self.name != name {
+            return false;
+        }
+        match &self.version {
+            Some(requirement) => version
+                .and_then(parse_version)
+                .is_some_and(|version| requirement.matches(&version)),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for RuntimeSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        let (name, version) = match spec.split_once(char::is_whitespace) {
+            Some((name, version)) => (name, Some(version.trim())),
+            None => (spec, None),
+        };
+        if name.is_empty() {
+            return Err("runtime name is empty".to_string());
         }
+        let version = version
+            .map(|version| {
+                VersionReq::parse(version)
+                    .map_err(|e| format!("invalid version range {}: {}", version, e))
+            })
+            .transpose()?;
+        Ok(Self {
+            name: name.to_string(),
+            version,
+        })
+    }
+}
+
+impl TryFrom<String> for RuntimeSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+impl From<RuntimeSpec> for String {
+    fn from(spec: RuntimeSpec) -> Self {
+        spec.to_string()
     }
 }
+
+impl fmt::Display for RuntimeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Minimum resources an offer has to provide, e.g. at least 16 GiB of memory and one GPU
+/// with at least 24 GiB of video memory. Providers not declaring a resource have none of it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceRequirements {
+    pub cpu_cores_min: Option<u32>,
+    pub memory_gib_min: Option<f64>,
+    pub storage_gib_min: Option<f64>,
+    pub gpu_count_min: Option<u32>,
+    /// Video memory each of the `gpu_count_min` GPUs needs
+    pub gpu_memory_gib_min: Option<f64>,
+    /// Part of the GPU model name, case insensitive
+    pub gpu_model: Option<String>,
+    /// Runtime name optionally followed by a semver range, e.g. `ya-runtime-vm >=0.4`
+    #[schema(value_type = Option<String>)]
+    pub runtime: Option<RuntimeSpec>,
+    /// Capabilities the provider has to enable, e.g. `multi-activity`, `payload-manifest`
+    pub capabilities: Option<Vec<String>>,
+}
+
+#[test]
+fn test_resource_requirements() {
+    let offer = crate::model::offer::base::test_offer();
+    let mut attributes = OfferFlatAttributes::from_gbo(
+        &offer,
+        &crate::grouping::Grouping::new(&Default::default()),
+        offer.timestamp,
+    );
+    assert_eq!(attributes.cpu_cores, 14);
+    let requirements = ResourceRequirements {
+        memory_gib_min: Some(16.0),
+        gpu_memory_gib_min: Some(24.0),
+        ..Default::default()
+    };
+    assert!(!attributes.meets(&requirements));
+    attributes.gpus = vec![
+        Gpu {
+            model: Some("NVIDIA GeForce RTX 3060".to_string()),
+            memory_gib: Some(12.0),
+            ..Default::default()
+        },
+        Gpu {
+            model: Some("NVIDIA GeForce RTX 4090".to_string()),
+            memory_gib: Some(24.0),
+            ..Default::default()
+        },
+    ];
+    assert!(attributes.meets(&requirements));
+    assert!(!attributes.meets(&ResourceRequirements {
+        gpu_count_min: Some(2),
+        ..requirements.clone()
+    }));
+    assert!(attributes.meets(&ResourceRequirements {
+        gpu_model: Some("rtx 40".to_string()),
+        ..requirements.clone()
+    }));
+    assert!(!attributes.meets(&ResourceRequirements {
+        memory_gib_min: Some(64.0),
+        ..requirements
+    }));
+}
+
+#[test]
+fn test_runtime_requirements() {
+    let offer = crate::model::offer::base::test_offer();
+    let attributes = OfferFlatAttributes::from_gbo(
+        &offer,
+        &crate::grouping::Grouping::new(&Default::default()),
+        offer.timestamp,
+    );
+    assert_eq!(attributes.capabilities, vec!["multi-activity"]);
+    let runtime = |spec: &str| ResourceRequirements {
+        runtime: Some(spec.parse().unwrap()),
+        ..Default::default()
+    };
+    assert!(attributes.meets(&runtime("ya-runtime-cruncher")));
+    assert!(attributes.meets(&runtime("ya-runtime-cruncher >=0.1, <0.2")));
+    assert!(!attributes.meets(&runtime("ya-runtime-cruncher >=0.4")));
+    assert!(!attributes.meets(&runtime("ya-runtime-vm")));
+    assert!(!attributes.meets(&ResourceRequirements {
+        capabilities: Some(vec!["payload-manifest".to_string()]),
+        ..Default::default()
+    }));
+    assert_eq!(parse_version("0.4"), Some(Version::new(0, 4, 0)));
+    assert!("ya-runtime-vm >=x".parse::<RuntimeSpec>().is_err());
+    assert_eq!(
+        serde_json::to_value(runtime("ya-runtime-vm >=0.4")).unwrap()["runtime"],
+        "ya-runtime-vm >=0.4"
+    );
+}
@@ -1,16 +1,56 @@
 use crate::model::offer::properties::Properties;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
 use ya_client_model::NodeId;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct GolemBaseOffer {
     pub id: String,
+    #[schema(value_type = Object)]
     pub properties: Properties,
     pub constraints: String,
     #[serde(rename = "providerId")]
+    #[schema(value_type = String)]
     pub provider_id: NodeId,
     pub expiration: DateTime<Utc>,
     pub timestamp: DateTime<Utc>,
 }
+
+/// Top level fields every offer has
+const REQUIRED_FIELDS: [&str; 6] = [
+    "id",
+    "properties",
+    "constraints",
+    "providerId",
+    "expiration",
+    "timestamp",
+];
+
+impl GolemBaseOffer {
+    /// Required fields and properties an offer in JSON does not have, used to explain why it
+    /// was rejected
+    pub fn missing_fields(value: &Value) -> Vec<String> {
+        let mut missing: Vec<String> = REQUIRED_FIELDS
+            .iter()
+            .filter(|field| value.get(**field).is_none())
+            .map(|field| field.to_string())
+            .collect();
+        if let Some(Err(e)) = value
+            .get("properties")
+            .map(|properties| Properties::from_value(properties.clone()))
+        {
+            missing.extend(e.missing);
+        }
+        missing
+    }
+}
+
+/// Offer published by a real provider, used as a fixture in tests
+#[cfg(test)]
+pub(crate) fn test_offer() -> GolemBaseOffer {
+    let offer = "{\"id\":\"00082a0389918034011dbcc885bd3da086eaaa66dceef7e6784386842571854d\",\"properties\":{\"golem\":{\"com\":{\"payment\":{\"debit-notes\":{\"accept-timeout?\":240},\"platform\":{\"erc20-polygon-glm\":{\"address\":\"0xa3bde9e2ef344407afdc931c97fd33d506ec6545\"}},\"protocol\":{\"version\":3}},\"pricing\":{\"model\":{\"@tag\":\"linear\",\"linear\":{\"coeffs\":[1e-9,0.0,0.0]}}},\"scheme\":{\"@tag\":\"payu\",\"payu\":{\"debit-note\":{\"interval-sec?\":120},\"payment-timeout-sec?\":120}},\"usage\":{\"vector\":[\"golem.usage.cpu_sec\",\"golem.usage.duration_sec\"]}},\"inf\":{\"cpu\":{\"architecture\":\"x86_64\",\"cores\":14,\"threads\":1},\"mem\":{\"gib\":42.79507473111153},\"storage\":{\"gib\":3257.801303100586}},\"node\":{\"debug\":{\"subnet\":\"public\"},\"id\":{\"name\":\"brick-54\"},\"net\":{\"is-public\":false}},\"runtime\":{\"name\":\"ya-runtime-cruncher\",\"version\":\"0.1.0\"},\"srv\":{\"caps\":{\"multi-activity\":true,\"payload-manifest\":false}}}},\"constraints\":\"(&\\n  (golem.srv.comp.expiration>1765401640654)\\n  (golem.node.debug.subnet=public)\\n)\",\"providerId\":\"0xa3bde9e2ef344407afdc931c97fd33d506ec6545\",\"expiration\":\"2025-12-11T12:20:45.222028719Z\",\"timestamp\":\"2025-12-11T11:20:45.222028719Z\"}";
+    serde_json::from_str(offer).unwrap()
+}
@@ -1,176 +1,449 @@
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Properties {
-    pub golem: GolemProperties,
-}
-
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct GolemProperties {
-    pub com: Com,
-    pub inf: Inf,
-    pub node: Node,
-    pub runtime: Runtime,
-    pub srv: Srv,
-}
+/// Key under which a nested object stores the value of the property it is named after,
+/// e.g. `{"model": {"@tag": "linear", "linear": {...}}}` for `golem.com.pricing.model`
+pub const PROPERTY_TAG: &str = "@tag";
 
-// --- Communication (com) ---
+pub const NODE_NAME: &str = "golem.node.id.name";
+pub const SUBNET: &str = "golem.node.debug.subnet";
+pub const RUNTIME_NAME: &str = "golem.runtime.name";
+pub const RUNTIME_VERSION: &str = "golem.runtime.version";
+pub const CAPABILITY_PREFIX: &str = "golem.srv.caps.";
+pub const CPU_ARCHITECTURE: &str = "golem.inf.cpu.architecture";
+pub const CPU_THREADS: &str = "golem.inf.cpu.threads";
+pub const CPU_CORES: &str = "golem.inf.cpu.cores";
+pub const MEM_GIB: &str = "golem.inf.mem.gib";
+pub const STORAGE_GIB: &str = "golem.inf.storage.gib";
+/// GPU properties as proposed in GAP-35, either of one GPU or of each device as `d0`, `d1`, ...
+pub const GPU_PREFIX: &str = "golem.!exp.gap-35.v1.inf.gpu.";
+pub const PAYMENT_PLATFORM_PREFIX: &str = "golem.com.payment.platform.";
+/// Computation a demand asks for, e.g. `golem.srv.comp.task_package`
+pub const COMPUTATION_PREFIX: &str = "golem.srv.comp.";
+pub const COMPUTATION_EXPIRATION: &str = "golem.srv.comp.expiration";
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Com {
-    pub payment: Payment,
-    pub pricing: Pricing,
-    pub scheme: Scheme,
-    pub usage: Usage,
+/// Flatten nested properties into dotted names, keys that are already dotted are kept
+pub fn flatten(value: Value) -> Map<String, Value> {
+    let mut map = Map::new();
+    flatten_inner(String::new(), &mut map, value);
+    map
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Payment {
-    #[serde(rename = "debit-notes")]
-    pub debit_notes: DebitNotes,
-    pub platform: Platform,
-    pub protocol: Protocol,
+fn flatten_inner(prefix: String, result: &mut Map<String, Value>, value: Value) {
+    match value {
+        Value::Object(m) => {
+            if m.is_empty() {
+                // Important to keep this value in case we want to un-flatten later
+                // and get the same structure.
+                result.insert(prefix, Value::Object(Map::new()));
+            } else {
+                for (k, v) in m.into_iter() {
+                    if k.as_str() == PROPERTY_TAG {
+                        result.insert(prefix.clone(), v);
+                        continue;
+                    }
+                    let p = match prefix.is_empty() {
+                        true => k,
+                        _ => format!("{}.{}", prefix, k),
+                    };
+                    flatten_inner(p, result, v);
+                }
+            }
+        }
+        v => {
+            result.insert(prefix, v);
+        }
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct DebitNotes {
-    // The key in JSON literally contains the question mark
-    #[serde(rename = "accept-timeout?")]
-    pub accept_timeout: u64,
+/// Inverse of [`flatten`], a property that also has nested properties keeps its value
+/// under [`PROPERTY_TAG`]
+pub fn unflatten<'a>(properties: impl IntoIterator<Item = (&'a String, &'a Value)>) -> Value {
+    let mut root = Map::new();
+    for (name, value) in properties {
+        let mut node = &mut root;
+        let mut parts = name.split('.').peekable();
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                match node.get_mut(part) {
+                    Some(Value::Object(children)) => {
+                        if !value.is_object() {
+                            children.insert(PROPERTY_TAG.to_string(), value.clone());
+                        }
+                    }
+                    _ => {
+                        node.insert(part.to_string(), value.clone());
+                    }
+                }
+                break;
+            }
+            let child = node
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                let tag = child.take();
+                *child = Value::Object(Map::from_iter([(PROPERTY_TAG.to_string(), tag)]));
+            }
+            node = child.as_object_mut().unwrap();
+        }
+    }
+    Value::Object(root)
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Erc20Platform {
+/// Payment platform the provider accepts, named `<driver>-<network>-<token>`,
+/// e.g. `erc20-polygon-glm`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PaymentPlatform {
+    pub driver: String,
+    pub network: String,
+    pub token: String,
+    /// Address payments are sent to
     pub address: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Platform {
-    #[serde(rename = "erc20-polygon-glm")]
-    pub erc20_polygon_glm: Option<Erc20Platform>,
-    #[serde(rename = "erc20-hoodi-tglm")]
-    pub erc20_hoodi_tglm: Option<Erc20Platform>,
+/// GPU of a provider, fields the provider does not declare are `None`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Gpu {
+    pub model: Option<String>,
+    /// Video memory
+    pub memory_gib: Option<f64>,
+    pub cuda_cores: Option<u32>,
+    pub cuda_version: Option<String>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Protocol {
-    pub version: u32,
-}
+impl PaymentPlatform {
+    /// `None` when the name does not have the driver, network and token parts
+    pub fn parse(name: &str, address: String) -> Option<Self> {
+        let (driver, rest) = name.split_once('-')?;
+        let (network, token) = rest.rsplit_once('-')?;
+        if driver.is_empty() || network.is_empty() || token.is_empty() {
+            return None;
+        }
+        Some(Self {
+            driver: driver.to_string(),
+            network: network.to_string(),
+            token: token.to_string(),
+            address,
+        })
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Pricing {
-    pub model: PricingModel,
+    pub fn name(&self) -> String {
+        format!("{}-{}-{}", self.driver, self.network, self.token)
+    }
 }
 
-// The JSON uses "@tag" to determine which variant this is
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "@tag")]
-pub enum PricingModel {
-    #[serde(rename = "linear")]
-    Linear { linear: LinearPricing },
+/// Offer properties that could not be read
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PropertiesError {
+    /// Required properties the offer does not declare
+    pub missing: Vec<String>,
+    /// Properties present with a value of the wrong type, with the expected type
+    pub invalid: Vec<(String, &'static str)>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct LinearPricing {
-    pub coeffs: Vec<f64>,
+impl fmt::Display for PropertiesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut problems = Vec::new();
+        if !self.missing.is_empty() {
+            problems.push(format!("missing {}", self.missing.join(", ")));
+        }
+        for (name, expected) in &self.invalid {
+            problems.push(format!("{} is not {}", name, expected));
+        }
+        write!(f, "{}", problems.join("; "))
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "@tag")]
-pub enum Scheme {
-    #[serde(rename = "payu")]
-    Payu { payu: PayuScheme },
-}
+impl std::error::Error for PropertiesError {}
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct PayuScheme {
-    #[serde(rename = "debit-note")]
-    pub debit_note: PayuDebitNote,
-    #[serde(rename = "payment-timeout-sec?")]
-    pub payment_timeout_sec: u64,
+/// Properties of an offer. Accepts the nested form (`{"golem": {"inf": ...}}`) as well as
+/// dotted names as yagna publishes them (`{"golem.inf.cpu.threads": 1}`) and serializes to
+/// the nested form. Properties the matcher reads are typed, all others are kept as they came.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Properties {
+    pub node_name: String,
+    pub runtime_name: String,
+    pub runtime_version: Option<String>,
+    pub cpu_architecture: String,
+    pub cpu_threads: u32,
+    pub cpu_cores: Option<u32>,
+    pub mem_gib: Option<f64>,
+    pub storage_gib: Option<f64>,
+    /// `None` for providers not limited to a subnet
+    pub subnet: Option<String>,
+    /// Keyed by platform name, only platforms with an address
+    pub payment_platforms: BTreeMap<String, PaymentPlatform>,
+    /// All other properties by dotted name
+    pub other: BTreeMap<String, Value>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct PayuDebitNote {
-    #[serde(rename = "interval-sec?")]
-    pub interval_sec: u64,
+fn take_str(
+    flat: &mut BTreeMap<String, Value>,
+    name: &str,
+    error: &mut PropertiesError,
+) -> Option<String> {
+    match flat.remove(name) {
+        Some(Value::String(value)) => Some(value),
+        Some(value) => {
+            flat.insert(name.to_string(), value);
+            error.invalid.push((name.to_string(), "a string"));
+            None
+        }
+        None => None,
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Usage {
-    pub vector: Vec<String>,
+fn take_u32(
+    flat: &mut BTreeMap<String, Value>,
+    name: &str,
+    error: &mut PropertiesError,
+) -> Option<u32> {
+    let value = flat.remove(name)?;
+    match value.as_u64().and_then(|value| u32::try_from(value).ok()) {
+        Some(value) => Some(value),
+        None => {
+            flat.insert(name.to_string(), value);
+            error
+                .invalid
+                .push((name.to_string(), "an unsigned integer"));
+            None
+        }
+    }
 }
 
-// --- Infrastructure (inf) ---
-
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Inf {
-    pub cpu: Cpu,
-    pub mem: Mem,
-    pub storage: Storage,
+fn take_f64(
+    flat: &mut BTreeMap<String, Value>,
+    name: &str,
+    error: &mut PropertiesError,
+) -> Option<f64> {
+    let value = flat.remove(name)?;
+    match value.as_f64() {
+        Some(value) => Some(value),
+        None => {
+            flat.insert(name.to_string(), value);
+            error.invalid.push((name.to_string(), "a number"));
+            None
+        }
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Cpu {
-    pub architecture: String,
-    pub cores: u32,
-    pub threads: u32,
-}
+impl Properties {
+    pub fn from_value(value: Value) -> Result<Self, PropertiesError> {
+        let mut error = PropertiesError::default();
+        if !value.is_object() {
+            error.invalid.push(("properties".to_string(), "an object"));
+            return Err(error);
+        }
+        let mut flat: BTreeMap<String, Value> = flatten(value).into_iter().collect();
+        flat.remove("");
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Mem {
-    pub gib: f64,
-}
+        let node_name = take_str(&mut flat, NODE_NAME, &mut error);
+        let runtime_name = take_str(&mut flat, RUNTIME_NAME, &mut error);
+        let runtime_version = take_str(&mut flat, RUNTIME_VERSION, &mut error);
+        let cpu_architecture = take_str(&mut flat, CPU_ARCHITECTURE, &mut error);
+        let cpu_threads = take_u32(&mut flat, CPU_THREADS, &mut error);
+        let cpu_cores = take_u32(&mut flat, CPU_CORES, &mut error);
+        let mem_gib = take_f64(&mut flat, MEM_GIB, &mut error);
+        let storage_gib = take_f64(&mut flat, STORAGE_GIB, &mut error);
+        let subnet = take_str(&mut flat, SUBNET, &mut error);
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Storage {
-    pub gib: f64,
-}
+        let mut payment_platforms = BTreeMap::new();
+        let addresses: Vec<(String, String)> = flat
+            .iter()
+            .filter_map(|(name, value)| {
+                let platform = name
+                    .strip_prefix(PAYMENT_PLATFORM_PREFIX)?
+                    .strip_suffix(".address")?;
+                Some((platform.to_string(), value.as_str()?.to_string()))
+            })
+            .collect();
+        for (name, address) in addresses {
+            if let Some(platform) = PaymentPlatform::parse(&name, address) {
+                flat.remove(&format!("{}{}.address", PAYMENT_PLATFORM_PREFIX, name));
+                payment_platforms.insert(name, platform);
+            }
+        }
 
-// --- Node (node) ---
+        for (name, present) in [
+            (NODE_NAME, node_name.is_some()),
+            (RUNTIME_NAME, runtime_name.is_some()),
+            (CPU_ARCHITECTURE, cpu_architecture.is_some()),
+            (CPU_THREADS, cpu_threads.is_some()),
+        ] {
+            if !present && !error.invalid.iter().any(|(invalid, _)| invalid == name) {
+                error.missing.push(name.to_string());
+            }
+        }
+        match (node_name, runtime_name, cpu_architecture, cpu_threads) {
+            (Some(node_name), Some(runtime_name), Some(cpu_architecture), Some(cpu_threads))
+                if error.invalid.is_empty() =>
+            {
+                Ok(Self {
+                    node_name,
+                    runtime_name,
+                    runtime_version,
+                    cpu_architecture,
+                    cpu_threads,
+                    cpu_cores,
+                    mem_gib,
+                    storage_gib,
+                    subnet,
+                    payment_platforms,
+                    other: flat,
+                })
+            }
+            _ => Err(error),
+        }
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Node {
-    pub debug: Option<NodeDebug>,
-    pub id: NodeName,
-    pub net: NodeNet,
-}
+    /// All properties by dotted name
+    pub fn to_flat(&self) -> BTreeMap<String, Value> {
+        let mut flat = self.other.clone();
+        flat.insert(NODE_NAME.to_string(), self.node_name.clone().into());
+        flat.insert(RUNTIME_NAME.to_string(), self.runtime_name.clone().into());
+        flat.insert(
+            CPU_ARCHITECTURE.to_string(),
+            self.cpu_architecture.clone().into(),
+        );
+        flat.insert(CPU_THREADS.to_string(), self.cpu_threads.into());
+        if let Some(runtime_version) = &self.runtime_version {
+            flat.insert(RUNTIME_VERSION.to_string(), runtime_version.clone().into());
+        }
+        if let Some(cpu_cores) = self.cpu_cores {
+            flat.insert(CPU_CORES.to_string(), cpu_cores.into());
+        }
+        if let Some(mem_gib) = self.mem_gib {
+            flat.insert(MEM_GIB.to_string(), mem_gib.into());
+        }
+        if let Some(storage_gib) = self.storage_gib {
+            flat.insert(STORAGE_GIB.to_string(), storage_gib.into());
+        }
+        if let Some(subnet) = &self.subnet {
+            flat.insert(SUBNET.to_string(), subnet.clone().into());
+        }
+        for (name, platform) in &self.payment_platforms {
+            flat.insert(
+                format!("{}{}.address", PAYMENT_PLATFORM_PREFIX, name),
+                platform.address.clone().into(),
+            );
+        }
+        flat
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct NodeDebug {
-    pub subnet: String,
-}
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.other.get(name)
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct NodeName {
-    pub name: String,
-}
+    /// Capabilities the provider declares as enabled, e.g. `multi-activity`
+    pub fn capabilities(&self) -> Vec<String> {
+        self.other
+            .iter()
+            .filter(|(_, value)| value.as_bool() == Some(true))
+            .filter_map(|(name, _)| name.strip_prefix(CAPABILITY_PREFIX))
+            .map(str::to_string)
+            .collect()
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct NodeNet {
-    #[serde(rename = "is-public")]
-    pub is_public: bool,
+    /// GPUs declared with GAP-35 properties, ordered by device
+    pub fn gpus(&self) -> Vec<Gpu> {
+        let mut gpus: BTreeMap<Option<u32>, Gpu> = BTreeMap::new();
+        for (name, value) in self.other.range(GPU_PREFIX.to_string()..) {
+            let Some(property) = name.strip_prefix(GPU_PREFIX) else {
+                break;
+            };
+            let (device, property) = match property.split_once('.') {
+                Some((device, rest)) if device.starts_with('d') => {
+                    match device[1..].parse::<u32>() {
+                        Ok(index) => (Some(index), rest),
+                        Err(_) => (None, property),
+                    }
+                }
+                _ => (None, property),
+            };
+            let gpu = gpus.entry(device).or_default();
+            match property {
+                "model" => gpu.model = value.as_str().map(str::to_string),
+                "memory.total.gib" => gpu.memory_gib = value.as_f64(),
+                "cuda.cores" => gpu.cuda_cores = value.as_u64().map(|cores| cores as u32),
+                "cuda.version" => gpu.cuda_version = value.as_str().map(str::to_string),
+                _ => {}
+            }
+        }
+        gpus.into_values().collect()
+    }
 }
 
-// --- Runtime (runtime) ---
+impl Serialize for Properties {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        unflatten(&self.to_flat()).serialize(serializer)
+    }
+}
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Runtime {
-    pub name: String,
-    pub version: String,
+impl<'de> Deserialize<'de> for Properties {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Properties::from_value(Value::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
 }
 
-// --- Service (srv) ---
+#[test]
+fn test_properties_round_trip() {
+    let nested = serde_json::json!({
+        "golem": {
+            "com": {
+                "payment": {"platform": {
+                    "erc20-polygon-glm": {"address": "0x01"},
+                    "erc20-holesky-tglm": {"address": "0x02"},
+                    "zksync-mainnet-glm": null,
+                }},
+                "pricing": {"model": {"@tag": "linear", "linear": {"coeffs": [0.1, 0.0]}}},
+            },
+            "inf": {"cpu": {"architecture": "x86_64", "threads": 4, "vendor": "GenuineIntel"}},
+            "!exp": {"gap-35": {"v1": {"inf": {"gpu": {
+                "d0": {"model": "RTX 3090", "memory": {"total": {"gib": 24.0}}},
+                "d1": {"model": "RTX 4090", "cuda": {"cores": 16384}},
+            }}}}},
+            "node": {"id": {"name": "node-1"}},
+            "runtime": {"name": "vm"},
+        }
+    });
+    let properties = Properties::from_value(nested.clone()).unwrap();
+    assert_eq!(properties.cpu_threads, 4);
+    assert_eq!(properties.subnet, None);
+    assert_eq!(properties.mem_gib, None);
+    let gpus = properties.gpus();
+    assert_eq!(gpus.len(), 2);
+    assert_eq!(gpus[0].memory_gib, Some(24.0));
+    assert_eq!(gpus[1].cuda_cores, Some(16384));
+    assert_eq!(
+        properties.payment_platforms["erc20-holesky-tglm"].network,
+        "holesky"
+    );
+    assert_eq!(
+        properties.get("golem.com.pricing.model"),
+        Some(&Value::from("linear"))
+    );
+    assert_eq!(serde_json::to_value(&properties).unwrap(), nested);
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Srv {
-    pub caps: Caps,
-}
+    // dotted names as published by yagna
+    let dotted = Value::Object(flatten(nested));
+    assert_eq!(Properties::from_value(dotted).unwrap(), properties);
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Caps {
-    #[serde(rename = "multi-activity")]
-    pub multi_activity: bool,
-    #[serde(rename = "payload-manifest")]
-    pub payload_manifest: bool,
+    let error = Properties::from_value(serde_json::json!({
+        "golem.node.id.name": "node-1",
+        "golem.inf.cpu.threads": "4",
+    }))
+    .unwrap_err();
+    assert_eq!(
+        error.missing,
+        vec![RUNTIME_NAME.to_string(), CPU_ARCHITECTURE.to_string()]
+    );
+    assert_eq!(
+        error.invalid,
+        vec![(CPU_THREADS.to_string(), "an unsigned integer")]
+    );
 }
@@ -1,99 +1,361 @@
-use crate::state::OfferObj;
-use crate::AppState;
+use crate::allocations::{Allocation, Allocations, LeaseState};
+use crate::config::ReplacedOfferAssignment;
+use crate::model::offer::attributes::OfferFlatAttributes;
+use crate::rest::offer::list_offers::{OfferListEntry, OFFER_LIST_PATH};
+use crate::state::{AppState, DemandObj, Demands, OfferObj, Offers};
 use actix_web::web;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
 
-pub async fn download_offers_from_mirror(data: web::Data<AppState>) -> anyhow::Result<()> {
-    let url = match std::env::var("OFFER_SOURCE_URL") {
-        Ok(url) => url,
-        Err(_) => {
-            log::warn!("INITIAL_OFFERS_URL not set, skipping download offers");
-            return Ok(());
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum OfferInsertStatus {
+    Added,
+    AlreadyRegistered,
+    /// Added in place of an older offer from the same provider
+    Replaced,
+    /// Not added, the provider already has a newer offer
+    Outdated,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferInsertResult {
+    pub status: OfferInsertStatus,
+    pub offer_id: String,
+    /// Older offer of the provider removed in favour of this one
+    pub replaced_offer_id: Option<String>,
+    /// Requestor the replaced offer was assigned to
+    #[schema(value_type = Option<String>)]
+    pub requestor_id: Option<NodeId>,
+    /// Set when the replaced offer was assigned
+    pub assignment: Option<ReplacedOfferAssignment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfferRemoval {
+    Expired,
+    Cleared,
+    /// Superseded by a newer offer of the same provider
+    Replaced,
+    Unsubscribed,
+}
+
+impl OfferRemoval {
+    fn lease_state(&self) -> LeaseState {
+        match self {
+            OfferRemoval::Expired => LeaseState::Expired,
+            OfferRemoval::Cleared => LeaseState::Cleared,
+            OfferRemoval::Replaced => LeaseState::Replaced,
+            OfferRemoval::Unsubscribed => LeaseState::Withdrawn,
         }
-    };
+    }
 
-    log::info!("Downloading initial offers from {}", url);
+    /// Whether the requestor should learn that the offer is gone
+    fn withdraws(&self) -> bool {
+        matches!(self, OfferRemoval::Replaced | OfferRemoval::Unsubscribed)
+    }
+}
 
-    let response = match reqwest::get(&url).await {
-        Ok(resp) => resp,
-        Err(e) => {
-            log::error!("Failed to download offers: {}", e);
-            return Err(e.into());
+/// Remove offers and every reference to them: demand queues lose the ids and the
+/// allocations of assigned offers end. The demand an offer was assigned to stops counting
+/// it as taken and, when the provider withdrew it, records the withdrawal.
+/// All offer removals go through here so the maps stay consistent.
+pub fn remove_offers<'a>(
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    offer_ids: impl IntoIterator<Item = &'a String>,
+    reason: OfferRemoval,
+    now: DateTime<Utc>,
+) -> Vec<OfferObj> {
+    let mut removed = Vec::new();
+    let mut removed_ids = HashSet::new();
+    // keyed by the id of the demand the offer was assigned to
+    let mut withdrawn: HashMap<String, Vec<String>> = HashMap::new();
+    let mut taken: HashMap<String, u64> = HashMap::new();
+    for offer_id in offer_ids {
+        let Some(offer) = offers.remove(offer_id) else {
+            continue;
+        };
+        if offer.requestor_id.is_some() {
+            let ended = allocations.live_allocation(offer_id).cloned();
+            allocations.end(offer_id, reason.lease_state(), now);
+            let owner = ended
+                .as_ref()
+                .and_then(|allocation| allocation.demand_id.clone())
+                .or_else(|| {
+                    demands
+                        .demand_map
+                        .values()
+                        .find(|demand| demand.offer_list.contains(offer_id))
+                        .map(|demand| demand.demand.id.clone())
+                });
+            if let Some(owner) = owner {
+                if ended.is_some_and(|allocation| allocation.state == LeaseState::Taken) {
+                    *taken.entry(owner.clone()).or_default() += 1;
+                }
+                if reason.withdraws() {
+                    withdrawn.entry(owner).or_default().push(offer_id.clone());
+                }
+            }
+        }
+        removed_ids.insert(offer_id.clone());
+        removed.push(offer);
+    }
+    if removed_ids.is_empty() {
+        return removed;
+    }
+    for demand in demands.demand_map.values_mut() {
+        demand
+            .offer_list
+            .retain(|queued| !removed_ids.contains(queued));
+        if let Some(offer_ids) = withdrawn.get(&demand.demand.id) {
+            for offer_id in offer_ids {
+                demand.record_withdrawn(offer_id);
+            }
         }
+        if let Some(count) = taken.get(&demand.demand.id) {
+            demand.taken = demand.taken.saturating_sub(*count);
+        }
+    }
+    log::debug!("Removed {} offers ({:?})", removed.len(), reason);
+    removed
+}
+
+pub fn remove_offer(
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    offer_id: &str,
+    reason: OfferRemoval,
+    now: DateTime<Utc>,
+) -> Option<OfferObj> {
+    let offer_id = offer_id.to_string();
+    remove_offers(offers, demands, allocations, [&offer_id], reason, now).pop()
+}
+
+/// Put offers queued for a demand that is going away back into the pool
+pub fn release_queued_offers(
+    offers: &mut Offers,
+    allocations: &mut Allocations,
+    demand: &DemandObj,
+    now: DateTime<Utc>,
+) -> usize {
+    let node_id = demand.demand.node_id;
+    let mut released = 0;
+    for offer_id in &demand.offer_list {
+        let Some(offer) = offers.offer_map.get_mut(offer_id) else {
+            continue;
+        };
+        if offer.requestor_id != Some(node_id) {
+            continue;
+        }
+        offer.requestor_id = None;
+        released += 1;
+        allocations.end(offer_id, LeaseState::Released, now);
+    }
+    released
+}
+
+/// Take an assigned offer back from its requestor: it leaves every demand queue and its
+/// allocation ends. A demand that already took the offer counts it as withdrawn.
+/// Returns the requestor and the allocation as it was before it ended, `None` when the
+/// offer was not assigned.
+pub fn unassign_offer(
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    offer_id: &str,
+    now: DateTime<Utc>,
+) -> Option<(NodeId, Option<Allocation>)> {
+    let offer = offers.offer_map.get_mut(offer_id)?;
+    let requestor_id = offer.requestor_id.take()?;
+    let ended = allocations.live_allocation(offer_id).cloned();
+    allocations.end(offer_id, LeaseState::Unassigned, now);
+    let taken_by = ended
+        .as_ref()
+        .filter(|allocation| allocation.state == LeaseState::Taken)
+        .and_then(|allocation| allocation.demand_id.clone());
+    for demand in demands.demand_map.values_mut() {
+        demand.offer_list.retain(|queued| queued != offer_id);
+        if taken_by.as_ref() == Some(&demand.demand.id) {
+            demand.taken = demand.taken.saturating_sub(1);
+            demand.record_withdrawn(offer_id);
+        }
+    }
+    Some((requestor_id, ended))
+}
+
+/// Insert an offer keeping one offer per provider: a newer offer supersedes the older one,
+/// an older one is not added.
+pub fn insert_offer(
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    mut offer: OfferObj,
+    policy: ReplacedOfferAssignment,
+    now: DateTime<Utc>,
+) -> OfferInsertResult {
+    let offer_id = offer.offer.id.clone();
+    let mut result = OfferInsertResult {
+        status: OfferInsertStatus::Added,
+        offer_id: offer_id.clone(),
+        replaced_offer_id: None,
+        requestor_id: None,
+        assignment: None,
     };
+    if offers.offer_map.contains_key(&offer_id) {
+        result.status = OfferInsertStatus::AlreadyRegistered;
+        return result;
+    }
 
-    let text = match response.text().await {
-        Ok(t) => t,
-        Err(e) => {
-            log::error!("Failed to read response body: {}", e);
-            return Err(e.into());
+    let provider_id = offer.offer.provider_id;
+    let replaced = match offers.provider_offer(&provider_id) {
+        Some(existing) if existing.offer.timestamp >= offer.offer.timestamp => {
+            result.status = OfferInsertStatus::Outdated;
+            return result;
         }
+        Some(existing) => Some((existing.offer.id.clone(), existing.requestor_id)),
+        None => None,
     };
-    let perf_start = Instant::now();
 
-    let offers: Vec<OfferObj> = match serde_json::from_str::<Vec<OfferObj>>(&text) {
-        Ok(offers) => offers,
+    if let Some((replaced_id, replaced_requestor_id)) = replaced {
+        result.status = OfferInsertStatus::Replaced;
+        result.replaced_offer_id = Some(replaced_id.clone());
+        let migrate = match replaced_requestor_id {
+            Some(requestor_id) => {
+                result.requestor_id = Some(requestor_id);
+                result.assignment = Some(policy);
+                let migrate =
+                    policy == ReplacedOfferAssignment::Migrate && offer.requestor_id.is_none();
+                log::info!(
+                    "Offer {} of provider {} replaced by {}, assignment to {} {}",
+                    replaced_id,
+                    provider_id,
+                    offer_id,
+                    requestor_id,
+                    if migrate { "migrated" } else { "invalidated" }
+                );
+                migrate
+            }
+            None => false,
+        };
+        if migrate {
+            // the assignment moves to the new offer
+            let replaced = offers.remove(&replaced_id);
+            offer.requestor_id = replaced.and_then(|replaced| replaced.requestor_id);
+            allocations.migrate(&replaced_id, &offer, now);
+            for demand in demands.demand_map.values_mut() {
+                for queued in demand.offer_list.iter_mut() {
+                    if *queued == replaced_id {
+                        *queued = offer_id.clone();
+                    }
+                }
+            }
+        } else {
+            remove_offer(
+                offers,
+                demands,
+                allocations,
+                &replaced_id,
+                OfferRemoval::Replaced,
+                now,
+            );
+        }
+    }
+
+    offers.insert(offer);
+    result
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MirrorSyncStats {
+    pub downloaded: usize,
+    pub added: usize,
+    pub already_present: usize,
+    pub removed: usize,
+    pub ignored: usize,
+}
+
+/// The mirror is read without `yagna_offer_client`, which depends on this crate. Path and
+/// entries are the ones the client uses.
+async fn fetch_offer_list(url: &str) -> anyhow::Result<Vec<OfferListEntry>> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    Ok(serde_json::from_str(&response.text().await?)?)
+}
+
+/// Returns `None` when no mirror is configured
+pub async fn download_offers_from_mirror(
+    data: web::Data<AppState>,
+) -> anyhow::Result<Option<MirrorSyncStats>> {
+    let url = match data.config().mirror.source_url.clone() {
+        Some(url) => url,
+        None => {
+            log::warn!("OFFER_SOURCE_URL not set, skipping download offers");
+            return Ok(None);
+        }
+    };
+
+    log::info!("Downloading initial offers from {}", url);
+
+    // OFFER_SOURCE_URL historically pointed at the /offers/list endpoint of the mirror
+    let base_url = url.trim_end_matches('/').trim_end_matches(OFFER_LIST_PATH);
+    let offers = match fetch_offer_list(&format!("{}{}", base_url, OFFER_LIST_PATH)).await {
+        Ok(offers) => offers
+            .into_iter()
+            .map(|entry| entry.offer)
+            .collect::<Vec<_>>(),
         Err(e) => {
-            log::error!("Failed to parse offers: {}", e);
-            return Err(e.into());
+            log::error!("Failed to download offers: {:#}", e);
+            return Err(e);
         }
     };
+    let perf_start = Instant::now();
 
+    let downloaded = offers.len();
     if offers.is_empty() {
         log::warn!("No valid offers downloaded");
-        return Ok(());
+        return Ok(Some(MirrorSyncStats::default()));
     }
 
+    let policy = data.config().matching.replaced_offer_assignment;
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
     let mut lock = data.lock.lock().await;
-
-    //build map of existing by provider_id
-    let mut by_provider_id = HashMap::new();
-
-    for offer in lock.offer_map.iter() {
-        let res = by_provider_id.insert(offer.1.offer.provider_id, offer.1.clone());
-        if res.is_some() {
-            log::warn!(
-                "Multiple existing offers from provider {}",
-                offer.1.offer.provider_id
-            );
-        }
-    }
+    let mut allocations = data.allocations.lock().await;
 
     let mut added = 0;
     let mut removed = 0;
     let mut already_present = 0;
     let mut ignored = 0;
-    for offer in offers {
-        if lock.offer_map.contains_key(&offer.offer.id) {
-            already_present += 1;
-            continue;
-        }
-        let mut to_remove = None;
-
-        if by_provider_id.contains_key(&offer.offer.provider_id) {
-            let by_provider_offer = by_provider_id
-                .get(&offer.offer.provider_id)
-                .expect("Has to contain that");
-            if by_provider_offer.offer.timestamp < offer.offer.timestamp {
-                //great, new offer is newer than older one
-                to_remove = Some(by_provider_offer.offer.id.clone());
-                by_provider_id.insert(offer.offer.provider_id, offer.clone());
-            } else {
-                //skip, older offer
-                ignored += 1;
-                continue;
+    for mut offer in offers {
+        // the mirror can run an older version and groups with its own seed
+        offer.attributes = OfferFlatAttributes::from_gbo(&offer.offer, &data.grouping, now);
+        let provider_id = offer.offer.provider_id;
+        let result = insert_offer(
+            &mut lock,
+            &mut demands,
+            &mut allocations,
+            offer,
+            policy,
+            now,
+        );
+        data.audit.offer_inserted(&result, provider_id);
+        match result.status {
+            OfferInsertStatus::Added => added += 1,
+            OfferInsertStatus::Replaced => {
+                added += 1;
+                removed += 1;
             }
-        } else {
-            by_provider_id.insert(offer.offer.provider_id, offer.clone());
+            OfferInsertStatus::AlreadyRegistered => already_present += 1,
+            OfferInsertStatus::Outdated => ignored += 1,
         }
-
-        if let Some(remove_id) = to_remove {
-            lock.offer_map.remove(&remove_id);
-            removed += 1;
-        }
-        lock.offer_map.insert(offer.offer.id.clone(), offer);
-        added += 1;
     }
     if perf_start.elapsed().as_secs_f64() > 0.01 {
         log::warn!(
@@ -114,5 +376,142 @@ pub async fn download_offers_from_mirror(data: web::Data<AppState>) -> anyhow::R
         removed,
         ignored
     );
-    Ok(())
+    Ok(Some(MirrorSyncStats {
+        downloaded,
+        added,
+        already_present,
+        removed,
+        ignored,
+    }))
+}
+
+#[test]
+fn test_newer_offer_replaces_older_one() {
+    use crate::allocations::AllocationSource;
+    use crate::state::{test_demand_obj, test_offer_obj};
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let offer_obj =
+        |id: &str, minutes: i64| test_offer_obj(id, now + chrono::Duration::minutes(minutes));
+    let requestor_id = NodeId::default();
+    let mut offers = Offers::default();
+    let mut first = offer_obj("first", 0);
+    first.requestor_id = Some(requestor_id);
+    offers.insert(first);
+    let mut demands = Demands::default();
+    let mut demand = test_demand_obj("demand", requestor_id, now);
+    demand.offer_list.push_back("first".to_string());
+    demands.demand_map.insert("demand".to_string(), demand);
+    let mut allocations = Allocations::default();
+    allocations.allocate(
+        &offers.offer_map["first"],
+        requestor_id,
+        Some("demand"),
+        AllocationSource::Matching,
+        LeaseState::Queued,
+        now,
+    );
+
+    let policy = ReplacedOfferAssignment::Migrate;
+    let result = insert_offer(
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        offer_obj("old", -5),
+        policy,
+        now,
+    );
+    assert_eq!(result.status, OfferInsertStatus::Outdated);
+
+    let result = insert_offer(
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        offer_obj("second", 5),
+        policy,
+        now,
+    );
+    assert_eq!(result.status, OfferInsertStatus::Replaced);
+    assert_eq!(result.replaced_offer_id.as_deref(), Some("first"));
+    assert_eq!(result.requestor_id, Some(requestor_id));
+    assert_eq!(offers.offer_map.len(), 1);
+    assert_eq!(offers.offer_map["second"].requestor_id, Some(requestor_id));
+    for demand in demands.demand_map.values() {
+        assert_eq!(demand.offer_list, ["second".to_string()]);
+    }
+
+    let policy = ReplacedOfferAssignment::Invalidate;
+    let result = insert_offer(
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        offer_obj("third", 10),
+        policy,
+        now,
+    );
+    assert_eq!(result.assignment, Some(ReplacedOfferAssignment::Invalidate));
+    assert_eq!(offers.offer_map["third"].requestor_id, None);
+    assert!(demands.demand_map.values().all(|d| d.offer_list.is_empty()));
+    assert!(demands.demand_map["demand"]
+        .withdrawn_offers
+        .contains(&"second".to_string()));
+    assert!(allocations.live_counts().is_empty());
+    assert_eq!(
+        allocations.allocations[1].source,
+        AllocationSource::Migrated
+    );
+    assert_eq!(allocations.allocations[1].state, LeaseState::Replaced);
+    assert!(crate::rest::admin::invariants::check_invariants(&offers, &demands, &allocations).ok);
+}
+
+#[test]
+fn test_removed_offers_leave_queues_and_taken_counts() {
+    use crate::allocations::AllocationSource;
+    use crate::state::{test_demand_obj, test_offer_obj};
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let requestor_id = NodeId::default();
+    let mut offers = Offers::default();
+    let mut demands = Demands::default();
+    let mut allocations = Allocations::default();
+    for (offer_id, demand_id, state) in [
+        ("queued", "a", LeaseState::Queued),
+        ("taken", "b", LeaseState::Taken),
+    ] {
+        let mut offer = test_offer_obj(offer_id, now);
+        offer.requestor_id = Some(requestor_id);
+        allocations.allocate(
+            &offer,
+            requestor_id,
+            Some(demand_id),
+            AllocationSource::Matching,
+            state,
+            now,
+        );
+        offers.insert(offer);
+        let mut demand = test_demand_obj(demand_id, requestor_id, now);
+        match state {
+            LeaseState::Queued => demand.offer_list.push_back(offer_id.to_string()),
+            _ => demand.taken = 1,
+        }
+        demands.demand_map.insert(demand_id.to_string(), demand);
+    }
+
+    let removed = remove_offers(
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        &["queued".to_string(), "taken".to_string()],
+        OfferRemoval::Expired,
+        now,
+    );
+    assert_eq!(removed.len(), 2);
+    assert!(demands.demand_map["a"].offer_list.is_empty());
+    assert_eq!(demands.demand_map["b"].taken, 0);
+    // expiry is not a withdrawal
+    assert!(demands
+        .demand_map
+        .values()
+        .all(|demand| demand.withdrawn_offers.is_empty()));
+    assert_eq!(allocations.live_allocations().count(), 0);
 }
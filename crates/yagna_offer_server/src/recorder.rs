@@ -0,0 +1,188 @@
+use crate::audit::{RequestContext, CALLER_ID_HEADER, REQUEST_ID_HEADER};
+use crate::state::AppState;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::future::{ready, Future, Ready};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// One API request as it reached the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedRequest {
+    /// Arrival time
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    /// Path with the query string
+    pub path: String,
+    pub body: String,
+    /// Status code of the response, `None` when the request failed before producing one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl RecordedRequest {
+    /// Header values to send again when the request is replayed, so the audit trail of the
+    /// replay refers to the same callers and request ids
+    pub fn context_headers(&self) -> Vec<(&'static str, &str)> {
+        let mut headers = Vec::new();
+        if let Some(caller) = &self.caller {
+            headers.push((CALLER_ID_HEADER, caller.as_str()));
+        }
+        if let Some(request_id) = &self.request_id {
+            headers.push((REQUEST_ID_HEADER, request_id.as_str()));
+        }
+        headers
+    }
+}
+
+/// Appends every request to a JSON lines file
+pub struct RequestRecorder {
+    file: Mutex<File>,
+}
+
+impl RequestRecorder {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        log::info!("Recording requests to {}", path.display());
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, request: &RecordedRequest) {
+        let line = match serde_json::to_string(request) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                log::error!("Failed to serialize recorded request: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            log::error!("Failed to write recorded request: {}", e);
+        }
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.file.lock().unwrap().sync_all()
+    }
+}
+
+/// Read a recording, requests are returned in order of arrival
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordedRequest>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+    let mut requests = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = serde_json::from_str::<RecordedRequest>(&line)
+            .with_context(|| format!("Invalid request at {}:{}", path.display(), index + 1))?;
+        requests.push(request);
+    }
+    // lines are written when responses are sent, which can differ from the order of arrival
+    requests.sort_by_key(|request| request.timestamp);
+    Ok(requests)
+}
+
+/// Middleware writing requests to the recorder, passes requests through when there is none.
+/// Has to be installed inside the `RequestContext` middleware to record request ids.
+pub struct RecordRequests {
+    recorder: Option<Arc<RequestRecorder>>,
+}
+
+impl RecordRequests {
+    pub fn new(recorder: Option<Arc<RequestRecorder>>) -> Self {
+        Self { recorder }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RecordRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RecordRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordRequestsMiddleware {
+            service: Rc::new(service),
+            recorder: self.recorder.clone(),
+        }))
+    }
+}
+
+pub struct RecordRequestsMiddleware<S> {
+    service: Rc<S>,
+    recorder: Option<Arc<RequestRecorder>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let recorder = match &self.recorder {
+            // swagger ui assets are not part of the API
+            Some(recorder) if !req.path().starts_with("/docs") => recorder.clone(),
+            _ => return Box::pin(self.service.call(req)),
+        };
+        let service = self.service.clone();
+        Box::pin(async move {
+            let timestamp = req
+                .app_data::<web::Data<AppState>>()
+                .map(|data| data.now())
+                .unwrap_or_else(Utc::now);
+            let body = req.extract::<web::Bytes>().await?;
+            // the body was consumed, handlers read it again from a new payload
+            let (_, mut payload) = actix_http::h1::Payload::create(true);
+            payload.unread_data(body.clone());
+            req.set_payload(payload.into());
+
+            let context = RequestContext::current();
+            let mut recorded = RecordedRequest {
+                timestamp,
+                method: req.method().to_string(),
+                path: req
+                    .uri()
+                    .path_and_query()
+                    .map(|path| path.to_string())
+                    .unwrap_or_else(|| req.path().to_string()),
+                body: String::from_utf8_lossy(&body).into_owned(),
+                status: None,
+                caller: context.as_ref().map(|context| context.caller.clone()),
+                request_id: context.map(|context| context.request_id),
+            };
+            let response = service.call(req).await;
+            if let Ok(response) = &response {
+                recorded.status = Some(response.status().as_u16());
+            }
+            recorder.record(&recorded);
+            response
+        })
+    }
+}
@@ -0,0 +1,311 @@
+use crate::allocations::{prune_allocation_history, Allocation};
+use crate::audit::{AuditLog, RequestContext};
+use crate::clock::{Clock, MockClock};
+use crate::config::ServerConfig;
+use crate::recorder::RecordedRequest;
+use crate::rest::demand::cancel_demand::clean_old_demands;
+use crate::rest::demand::pick_offers_for_all_demands;
+use crate::rest::error::legacy_error_response;
+use crate::rest::offer::clean_old_offers::clean_old_offers;
+use crate::rest::provider::forget_silent_providers;
+use crate::state::AppState;
+use actix_web::dev::Service;
+use actix_web::http::Method;
+use actix_web::{test, web, App};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    /// Audit and recording files are ignored, the replay keeps its audit trail in memory
+    pub config: ServerConfig,
+    /// Virtual time that passes after the last request, so pending matching rounds and
+    /// cleanups still run
+    pub settle_secs: f64,
+}
+
+/// Request whose replayed response status differs from the recorded one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusMismatch {
+    /// Position in the recording
+    pub index: usize,
+    pub method: String,
+    pub path: String,
+    pub recorded: u16,
+    pub replayed: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayReport {
+    pub requests: usize,
+    /// Virtual time of the first request
+    pub started_at: DateTime<Utc>,
+    /// Virtual time the replay stopped at
+    pub finished_at: DateTime<Utc>,
+    pub matching_rounds: u64,
+    pub status_mismatches: Vec<StatusMismatch>,
+    /// Every assignment made during the replay, in order
+    pub allocations: Vec<Allocation>,
+    /// Offer ids queued for each demand at the end
+    pub queues: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeriodicTask {
+    Matching,
+    OfferCleanup,
+    DemandCleanup,
+}
+
+/// Simulated time of the replay, seen by the matcher through a [`MockClock`]. Periodic tasks
+/// run at their intervals of virtual time, interleaved with the requests as the server would
+/// have run them.
+struct VirtualClock {
+    clock: Arc<MockClock>,
+    schedule: Vec<(PeriodicTask, chrono::Duration, DateTime<Utc>)>,
+}
+
+fn duration_secs(secs: f64) -> chrono::Duration {
+    chrono::Duration::try_milliseconds((secs * 1000.0) as i64)
+        .unwrap_or(chrono::Duration::max_value())
+}
+
+/// Intervals too long to end within the representable time range never come due
+fn saturating_add(time: DateTime<Utc>, duration: chrono::Duration) -> DateTime<Utc> {
+    time.checked_add_signed(duration)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+impl VirtualClock {
+    fn new(start: DateTime<Utc>, config: &ServerConfig) -> Self {
+        let mut tasks = vec![
+            (
+                PeriodicTask::OfferCleanup,
+                config.cleanup.offer_interval_secs,
+            ),
+            (
+                PeriodicTask::DemandCleanup,
+                config.cleanup.demand_interval_secs,
+            ),
+        ];
+        if config.matching.enabled {
+            tasks.push((PeriodicTask::Matching, config.matching.pick_interval_secs));
+        }
+        Self {
+            clock: Arc::new(MockClock::new(start)),
+            schedule: tasks
+                .into_iter()
+                .map(|(task, secs)| {
+                    (
+                        task,
+                        duration_secs(secs),
+                        saturating_add(start, duration_secs(secs)),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Earliest task due at or before `until`. The clock moves to its due time and the task
+    /// is scheduled again one interval later.
+    fn next_due(&mut self, until: DateTime<Utc>) -> Option<PeriodicTask> {
+        let (task, interval, due) = self
+            .schedule
+            .iter_mut()
+            .filter(|(_, _, due)| *due <= until)
+            .min_by_key(|(_, _, due)| *due)?;
+        let (task, at) = (*task, *due);
+        *due = saturating_add(*due, *interval);
+        self.advance(at);
+        Some(task)
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    fn advance(&self, until: DateTime<Utc>) {
+        self.clock.set(self.now().max(until));
+    }
+}
+
+async fn run_periodic_task(data: &web::Data<AppState>, task: PeriodicTask) {
+    match task {
+        PeriodicTask::Matching => pick_offers_for_all_demands(data.clone()).await,
+        PeriodicTask::OfferCleanup => {
+            clean_old_offers(data.clone()).await;
+            prune_allocation_history(data.clone()).await;
+            forget_silent_providers(data.clone()).await;
+        }
+        PeriodicTask::DemandCleanup => clean_old_demands(data.clone()).await,
+    }
+}
+
+/// Run the periodic tasks due up to `until` and move the clock there, returns the number of
+/// matching rounds
+async fn run_until(
+    data: &web::Data<AppState>,
+    clock: &mut VirtualClock,
+    until: DateTime<Utc>,
+) -> u64 {
+    let mut matching_rounds = 0;
+    while let Some(task) = clock.next_due(until) {
+        if task == PeriodicTask::Matching {
+            matching_rounds += 1;
+        }
+        run_periodic_task(data, task).await;
+    }
+    clock.advance(until);
+    matching_rounds
+}
+
+/// Feed recorded requests into a fresh matcher running on virtual time and return the
+/// assignments it made. Virtual time starts at the first request.
+pub async fn replay(
+    requests: &[RecordedRequest],
+    options: ReplayOptions,
+) -> anyhow::Result<ReplayReport> {
+    let mut config = options.config;
+    config.audit.path = None;
+    config.recording.path = None;
+    config.validate()?;
+
+    let started_at = requests
+        .first()
+        .map(|request| request.timestamp)
+        .unwrap_or_else(Utc::now);
+    let mut clock = VirtualClock::new(started_at, &config);
+    let audit = AuditLog::open(&config.audit)?;
+    let data = web::Data::new(AppState::with_clock(
+        config.clone(),
+        audit,
+        clock.clock.clone(),
+    ));
+    let app = test::init_service(
+        App::new()
+            .app_data(data.clone())
+            .wrap_fn(|req, srv| {
+                let response = srv.call(req);
+                async move { Ok(legacy_error_response(response.await?)) }
+            })
+            .wrap_fn(|req, srv| {
+                let context = RequestContext::from_request(&req);
+                context.scope(srv.call(req))
+            })
+            .configure(crate::rest::routes::configure),
+    )
+    .await;
+
+    let mut matching_rounds = 0;
+    let mut status_mismatches = Vec::new();
+    for (index, request) in requests.iter().enumerate() {
+        matching_rounds += run_until(&data, &mut clock, request.timestamp).await;
+
+        let method = Method::from_bytes(request.method.as_bytes())
+            .with_context(|| format!("Invalid method of request {}", index))?;
+        let mut test_request = test::TestRequest::default()
+            .method(method)
+            .uri(&request.path)
+            .set_payload(request.body.clone());
+        for header in request.context_headers() {
+            test_request = test_request.insert_header(header);
+        }
+        let status = match app.call(test_request.to_request()).await {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        if let Some(recorded) = request.status {
+            if recorded != status.as_u16() {
+                status_mismatches.push(StatusMismatch {
+                    index,
+                    method: request.method.clone(),
+                    path: request.path.clone(),
+                    recorded,
+                    replayed: status.as_u16(),
+                });
+            }
+        }
+    }
+    let until = saturating_add(clock.now(), duration_secs(options.settle_secs));
+    matching_rounds += run_until(&data, &mut clock, until).await;
+
+    let allocations = data.allocations.lock().await.allocations.clone();
+    let queues = data
+        .demands
+        .lock()
+        .await
+        .demand_map
+        .iter()
+        .map(|(id, demand)| (id.clone(), demand.offer_list.iter().cloned().collect()))
+        .collect();
+    Ok(ReplayReport {
+        requests: requests.len(),
+        started_at,
+        finished_at: clock.now(),
+        matching_rounds,
+        status_mismatches,
+        allocations,
+        queues,
+    })
+}
+
+#[actix_web::test]
+async fn test_replay_assigns_offer() {
+    use crate::allocations::AllocationSource;
+
+    // recorded long after the offer expired in real time
+    let offer = crate::model::offer::base::test_offer();
+    let start = offer.timestamp + chrono::Duration::minutes(1);
+    let demand = serde_json::json!({
+        "id": "demand-1",
+        "properties": "{}",
+        "constraints": "()",
+        "nodeId": "0x0000000000000000000000000000000000000001",
+        "creationTs": start.naive_utc(),
+        "insertionTs": null,
+        "expirationTs": (start + chrono::Duration::hours(1)).naive_utc(),
+        "centralNetAddress": "127.0.0.1",
+    });
+    let request = |secs: i64, path: &str, body: String| RecordedRequest {
+        timestamp: start + chrono::Duration::seconds(secs),
+        method: "POST".to_string(),
+        path: path.to_string(),
+        body,
+        status: Some(200),
+        caller: None,
+        request_id: None,
+    };
+    let requests = vec![
+        request(
+            0,
+            "/provider/offer/new",
+            serde_json::to_string(&offer).unwrap(),
+        ),
+        request(1, "/requestor/demand/new", demand.to_string()),
+    ];
+
+    let report = replay(
+        &requests,
+        ReplayOptions {
+            settle_secs: 60.0,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(report.status_mismatches.is_empty());
+    assert_eq!(report.matching_rounds, 2);
+    assert_eq!(report.allocations.len(), 1);
+    assert_eq!(report.allocations[0].source, AllocationSource::Matching);
+    assert_eq!(
+        report.allocations[0].allocated_at,
+        start + chrono::Duration::seconds(30)
+    );
+    assert_eq!(report.finished_at, start + chrono::Duration::seconds(61));
+    assert_eq!(report.queues["demand-1"], vec![offer.id]);
+}
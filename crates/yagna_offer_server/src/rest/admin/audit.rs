@@ -0,0 +1,97 @@
+use crate::audit::{AuditAction, AuditEntry};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+/// All filters are optional and combined
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+pub struct AuditQuery {
+    /// Entries at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Entries before this time
+    pub to: Option<DateTime<Utc>>,
+    #[param(inline)]
+    pub action: Option<AuditAction>,
+    pub offer_id: Option<String>,
+    pub demand_id: Option<String>,
+    pub provider_id: Option<String>,
+    pub requestor_id: Option<String>,
+    pub caller: Option<String>,
+    pub request_id: Option<String>,
+    /// Most recent matching entries returned, 1000 by default
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let same = |filter: &Option<String>, value: &Option<String>| {
+            filter
+                .as_ref()
+                .map(|filter| value.as_ref() == Some(filter))
+                .unwrap_or(true)
+        };
+        self.from
+            .map(|from| entry.timestamp >= from)
+            .unwrap_or(true)
+            && self.to.map(|to| entry.timestamp < to).unwrap_or(true)
+            && self
+                .action
+                .map(|action| action == entry.action)
+                .unwrap_or(true)
+            && same(&self.offer_id, &entry.offer_id)
+            && same(&self.demand_id, &entry.demand_id)
+            && same(&self.provider_id, &entry.provider_id)
+            && same(&self.requestor_id, &entry.requestor_id)
+            && same(&self.caller, &entry.caller)
+            && same(&self.request_id, &entry.request_id)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit entries matching the query, oldest first", body = Vec<AuditEntry>),
+        (status = 400, description = "Invalid query", body = ApiErrorBody),
+        (status = 500, description = "Audit log could not be read", body = ApiErrorBody),
+    )
+)]
+pub async fn audit_query(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let query = web::Query::<AuditQuery>::from_query(req.query_string())
+        .map_err(|e| {
+            log::error!("Error decoding audit query: {}", e);
+            ApiError::invalid_format(e)
+        })?
+        .into_inner();
+
+    let audit = data.audit.clone();
+    let entries = web::block(move || audit.entries())
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::AuditLogUnavailable, e.to_string()))?
+        .map_err(|e| {
+            log::error!("Failed to read audit log: {}", e);
+            ApiError::new(
+                ErrorCode::AuditLogUnavailable,
+                format!("Failed to read audit log: {}", e),
+            )
+        })?;
+    let mut entries: Vec<AuditEntry> = entries
+        .into_iter()
+        .filter(|entry| query.matches(entry))
+        .collect();
+    let limit = query.limit.unwrap_or(1000);
+    if entries.len() > limit {
+        entries.drain(..entries.len() - limit);
+    }
+    Ok(HttpResponse::Ok().json(entries))
+}
@@ -0,0 +1,110 @@
+use crate::allocations::Allocations;
+use crate::state::{AppState, Demands, Offers};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEntryIssue {
+    pub demand_id: String,
+    pub offer_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationIssue {
+    pub offer_id: String,
+    /// Requestor the offer is assigned to, `None` when the offer is free or gone
+    pub requestor_id: Option<String>,
+    /// Requestor of the live allocation in the ledger
+    pub allocated_to: Option<String>,
+}
+
+/// References between offers, demand queues and the allocation ledger that do not line up
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InvariantReport {
+    pub ok: bool,
+    /// Queued offer ids missing from the offer map
+    pub dangling_queue_entries: Vec<QueueEntryIssue>,
+    /// Queued offers not assigned to the node owning the demand
+    pub misassigned_queue_entries: Vec<QueueEntryIssue>,
+    /// Offers queued more than once, in one or several demands
+    pub duplicate_queue_entries: Vec<QueueEntryIssue>,
+    /// Offer assignments that differ from the live allocations in the ledger
+    pub allocation_mismatches: Vec<AllocationIssue>,
+}
+
+pub fn check_invariants(
+    offers: &Offers,
+    demands: &Demands,
+    allocations: &Allocations,
+) -> InvariantReport {
+    let mut report = InvariantReport::default();
+    let mut queued = HashSet::new();
+    for (demand_id, demand) in &demands.demand_map {
+        for offer_id in &demand.offer_list {
+            let entry = || QueueEntryIssue {
+                demand_id: demand_id.clone(),
+                offer_id: offer_id.clone(),
+            };
+            if !queued.insert(offer_id) {
+                report.duplicate_queue_entries.push(entry());
+            }
+            match offers.offer_map.get(offer_id) {
+                None => report.dangling_queue_entries.push(entry()),
+                Some(offer) if offer.requestor_id != Some(demand.demand.node_id) => {
+                    report.misassigned_queue_entries.push(entry())
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    for offer in offers.offer_map.values() {
+        let allocated_to = allocations
+            .live_allocation(&offer.offer.id)
+            .map(|allocation| allocation.requestor_id);
+        if offer.requestor_id != allocated_to {
+            report.allocation_mismatches.push(AllocationIssue {
+                offer_id: offer.offer.id.clone(),
+                requestor_id: offer.requestor_id.map(|id| id.to_string()),
+                allocated_to: allocated_to.map(|id| id.to_string()),
+            });
+        }
+    }
+    for allocation in allocations.live_allocations() {
+        if !offers.offer_map.contains_key(&allocation.offer_id) {
+            report.allocation_mismatches.push(AllocationIssue {
+                offer_id: allocation.offer_id.clone(),
+                requestor_id: None,
+                allocated_to: Some(allocation.requestor_id.to_string()),
+            });
+        }
+    }
+
+    report.ok = report.dangling_queue_entries.is_empty()
+        && report.misassigned_queue_entries.is_empty()
+        && report.duplicate_queue_entries.is_empty()
+        && report.allocation_mismatches.is_empty();
+    report
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/invariants",
+    tag = "admin",
+    responses((status = 200, description = "Consistency of offers, demand queues and allocations", body = InvariantReport))
+)]
+pub async fn invariants_check(data: web::Data<AppState>) -> HttpResponse {
+    let demands = data.demands.lock().await;
+    let offers = data.lock.lock().await;
+    let allocations = data.allocations.lock().await;
+    let report = check_invariants(&offers, &demands, &allocations);
+    if !report.ok {
+        log::warn!("Invariant check failed: {:?}", report);
+    }
+    HttpResponse::Ok().json(report)
+}
@@ -0,0 +1,96 @@
+use crate::audit::{AuditAction, AuditEntry};
+use crate::jobs::JobStatus;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+fn job_not_found(name: &str) -> ApiError {
+    ApiError::new(ErrorCode::JobNotFound, format!("No job named {}", name))
+        .with_details(serde_json::json!({ "job": name }))
+}
+
+/// Apply `control` to the job and answer with its status
+fn control_job(
+    data: &AppState,
+    name: &str,
+    action: AuditAction,
+    control: impl FnOnce(&str) -> Option<()>,
+) -> Result<HttpResponse, ApiError> {
+    control(name).ok_or_else(|| job_not_found(name))?;
+    log::info!("Job {}: {:?} by administrator", name, action);
+    data.audit.record(
+        AuditEntry::new(action, data.now()).with_details(serde_json::json!({ "job": name })),
+    );
+    let status = data
+        .jobs
+        .status(name, data.now())
+        .ok_or_else(|| job_not_found(name))?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    responses((status = 200, description = "Background jobs with their schedule and run statistics", body = Vec<JobStatus>))
+)]
+pub async fn jobs_list(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.jobs.statuses(data.now()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{name}/pause",
+    tag = "admin",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 200, description = "Job paused, scheduled runs are skipped", body = JobStatus),
+        (status = 404, description = "Job not found", body = ApiErrorBody),
+    )
+)]
+pub async fn job_pause(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    control_job(&data, &path, AuditAction::JobPaused, |name| {
+        data.jobs.pause(name)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{name}/resume",
+    tag = "admin",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 200, description = "Job resumed", body = JobStatus),
+        (status = 404, description = "Job not found", body = ApiErrorBody),
+    )
+)]
+pub async fn job_resume(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    control_job(&data, &path, AuditAction::JobResumed, |name| {
+        data.jobs.resume(name)
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{name}/run",
+    tag = "admin",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 200, description = "Run requested, it starts in the background also when the job is paused", body = JobStatus),
+        (status = 404, description = "Job not found", body = ApiErrorBody),
+    )
+)]
+pub async fn job_run_now(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    control_job(&data, &path, AuditAction::JobRunRequested, |name| {
+        data.jobs.run_now(name)
+    })
+}
@@ -0,0 +1,98 @@
+pub mod audit;
+pub mod invariants;
+pub mod jobs;
+pub mod offers;
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::config::ServerConfig;
+use crate::offers::{download_offers_from_mirror, MirrorSyncStats};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::{AppState, Snapshot};
+use actix_web::{web, HttpResponse};
+
+#[utoipa::path(
+    post,
+    path = "/admin/mirror/sync",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Offers downloaded from the mirror", body = MirrorSyncStats),
+        (status = 409, description = "OFFER_SOURCE_URL is not set", body = ApiErrorBody),
+        (status = 502, description = "Mirror could not be reached", body = ApiErrorBody),
+    )
+)]
+pub async fn mirror_sync_now(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    match download_offers_from_mirror(data).await {
+        Ok(Some(stats)) => Ok(HttpResponse::Ok().json(stats)),
+        Ok(None) => Err(ApiError::new(
+            ErrorCode::MirrorNotConfigured,
+            "OFFER_SOURCE_URL not set, no mirror to synchronize with",
+        )),
+        Err(e) => Err(ApiError::new(
+            ErrorCode::MirrorSyncFailed,
+            format!("Mirror synchronization failed: {}", e),
+        )),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    responses((status = 200, description = "Effective configuration after merging file, environment and command line", body = ServerConfig))
+)]
+pub async fn config_show(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.config().as_ref())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/snapshot",
+    tag = "admin",
+    responses((status = 200, description = "Offers and demands held by the server", body = Snapshot))
+)]
+pub async fn snapshot_export(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.snapshot().await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/snapshot",
+    tag = "admin",
+    request_body(content = Snapshot, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Server state replaced with the snapshot", body = String),
+        (status = 400, description = "Invalid snapshot format", body = ApiErrorBody),
+    )
+)]
+pub async fn snapshot_import(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let snapshot = serde_json::from_str::<Snapshot>(&body).map_err(|e| {
+        log::error!("Error decoding snapshot: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+
+    let (offer_count, demand_count) = (
+        snapshot.offers.offer_map.len(),
+        snapshot.demands.demand_map.len(),
+    );
+    log::info!(
+        "Importing snapshot with {} offers and {} demands",
+        offer_count,
+        demand_count
+    );
+    data.restore(snapshot).await;
+    data.audit.record(
+        AuditEntry::new(AuditAction::SnapshotImported, data.now()).with_details(
+            serde_json::json!({
+                "offers": offer_count,
+                "demands": demand_count,
+            }),
+        ),
+    );
+    Ok(HttpResponse::Ok().body(format!(
+        "Imported {} offers and {} demands",
+        offer_count, demand_count
+    )))
+}
@@ -0,0 +1,506 @@
+use crate::allocations::{Allocation, AllocationSource, Allocations, LeaseState};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::health::ensure_accepting_assignments;
+use crate::offers::unassign_offer;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::rest::provider::ProviderLivenessInfo;
+use crate::state::{AppState, DemandObj, Demands, OfferObj, Offers};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+/// Audit entries of an offer returned by `/admin/offer/{id}`
+const OFFER_AUDIT_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuePosition {
+    pub demand_id: String,
+    /// 0 is taken first
+    pub position: usize,
+}
+
+/// Everything the server knows about one offer
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferInspection {
+    #[serde(flatten)]
+    pub offer: OfferObj,
+    pub liveness: ProviderLivenessInfo,
+    /// Demands whose queue holds the offer, normally at most one
+    pub queued_in: Vec<QueuePosition>,
+    /// Allocations of the offer still in the ledger, oldest first
+    pub allocations: Vec<Allocation>,
+    /// Most recent audit entries of the offer, oldest first
+    pub audit: Vec<AuditEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferReassign {
+    pub demand_id: String,
+}
+
+/// Offers of a provider, offers held by a requestor, or the offers of a provider held by a
+/// requestor when both are given
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferSelector {
+    #[schema(value_type = Option<String>)]
+    pub provider_id: Option<NodeId>,
+    #[schema(value_type = Option<String>)]
+    pub requestor_id: Option<NodeId>,
+}
+
+impl OfferSelector {
+    fn validate(&self) -> Result<(), ApiError> {
+        match (self.provider_id, self.requestor_id) {
+            (None, None) => Err(ApiError::new(
+                ErrorCode::InvalidFormat,
+                "Invalid format, providerId or requestorId is required",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn select(&self, offers: &Offers) -> Vec<String> {
+        offers
+            .offer_map
+            .values()
+            .filter(|offer| {
+                self.provider_id
+                    .map(|id| id == offer.offer.provider_id)
+                    .unwrap_or(true)
+                    && self
+                        .requestor_id
+                        .map(|id| Some(id) == offer.requestor_id)
+                        .unwrap_or(true)
+            })
+            .map(|offer| offer.offer.id.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkReassign {
+    #[serde(flatten)]
+    pub selector: OfferSelector,
+    pub demand_id: String,
+}
+
+/// Where an offer was assigned before and after an administrative change
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferAssignmentChange {
+    pub offer_id: String,
+    #[schema(value_type = Option<String>)]
+    pub previous_requestor_id: Option<NodeId>,
+    pub previous_demand_id: Option<String>,
+    /// `None` when the offer went back to the pool
+    #[schema(value_type = Option<String>)]
+    pub requestor_id: Option<NodeId>,
+    pub demand_id: Option<String>,
+}
+
+fn offer_not_found(offer_id: &str) -> ApiError {
+    ApiError::new(ErrorCode::OfferNotFound, "Offer not found")
+        .with_details(serde_json::json!({ "offerId": offer_id }))
+}
+
+fn demand_not_found(demand_id: &str) -> ApiError {
+    ApiError::new(ErrorCode::DemandNotFound, "Demand not found")
+        .with_details(serde_json::json!({ "demandId": demand_id }))
+}
+
+/// Return an offer to the pool and record it, `None` when it was not assigned
+fn unassign(
+    data: &AppState,
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    offer_id: &str,
+    now: DateTime<Utc>,
+) -> Option<OfferAssignmentChange> {
+    let (requestor_id, allocation) = unassign_offer(offers, demands, allocations, offer_id, now)?;
+    let provider_id = offers.offer_map[offer_id].offer.provider_id;
+    let demand_id = allocation.as_ref().and_then(|a| a.demand_id.clone());
+    let mut entry = AuditEntry::new(AuditAction::OfferUnassigned, now)
+        .offer(offer_id)
+        .provider(provider_id)
+        .requestor(requestor_id)
+        .with_details(serde_json::json!({
+            "allocationId": allocation.as_ref().map(|a| a.id),
+            "state": allocation.as_ref().map(|a| a.state),
+        }));
+    if let Some(demand_id) = &demand_id {
+        entry = entry.demand(demand_id);
+    }
+    data.audit.record(entry);
+    Some(OfferAssignmentChange {
+        offer_id: offer_id.to_string(),
+        previous_requestor_id: Some(requestor_id),
+        previous_demand_id: demand_id,
+        requestor_id: None,
+        demand_id: None,
+    })
+}
+
+/// Move an offer to the end of the queue of `demand_id`, wherever it was before
+fn reassign(
+    data: &AppState,
+    offers: &mut Offers,
+    demands: &mut Demands,
+    allocations: &mut Allocations,
+    offer_id: &str,
+    demand_id: &str,
+    now: DateTime<Utc>,
+) -> OfferAssignmentChange {
+    let previous = unassign(data, offers, demands, allocations, offer_id, now);
+    let demand_obj: &mut DemandObj = demands.demand_map.get_mut(demand_id).unwrap();
+    let offer = offers.offer_map.get_mut(offer_id).unwrap();
+    offer.requestor_id = Some(demand_obj.demand.node_id);
+    demand_obj.offer_list.push_back(offer_id.to_string());
+    let allocation = allocations.allocate(
+        offer,
+        demand_obj.demand.node_id,
+        Some(demand_id),
+        AllocationSource::Admin,
+        LeaseState::Queued,
+        now,
+    );
+    data.audit.record(AuditEntry::assigned(allocation));
+    OfferAssignmentChange {
+        offer_id: offer_id.to_string(),
+        previous_requestor_id: previous.as_ref().and_then(|p| p.previous_requestor_id),
+        previous_demand_id: previous.and_then(|p| p.previous_demand_id),
+        requestor_id: Some(demand_obj.demand.node_id),
+        demand_id: Some(demand_id.to_string()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/offer/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Offer id")),
+    responses(
+        (status = 200, description = "Offer with its owner, queues, allocations and audit trail", body = OfferInspection),
+        (status = 404, description = "Offer not found", body = ApiErrorBody),
+    )
+)]
+pub async fn offer_inspect(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let offer_id = path.into_inner();
+    let inspection = {
+        let demands = data.demands.lock().await;
+        let offers = data.lock.lock().await;
+        let allocations = data.allocations.lock().await;
+        let providers = data.providers.lock().await;
+        let offer = offers
+            .offer_map
+            .get(&offer_id)
+            .ok_or_else(|| offer_not_found(&offer_id))?;
+        OfferInspection {
+            offer: offer.clone(),
+            liveness: ProviderLivenessInfo::new(
+                &data.config().liveness,
+                &providers,
+                &offer.offer.provider_id,
+                data.now(),
+            ),
+            queued_in: demands
+                .demand_map
+                .values()
+                .filter_map(|demand| {
+                    let position = demand.offer_list.iter().position(|id| *id == offer_id)?;
+                    Some(QueuePosition {
+                        demand_id: demand.demand.id.clone(),
+                        position,
+                    })
+                })
+                .collect(),
+            allocations: allocations
+                .allocations
+                .iter()
+                .filter(|allocation| allocation.offer_id == offer_id)
+                .cloned()
+                .collect(),
+            audit: Vec::new(),
+        }
+    };
+
+    let audit = data.audit.clone();
+    let mut inspection = inspection;
+    match web::block(move || audit.entries()).await {
+        Ok(Ok(entries)) => {
+            let mut entries: Vec<AuditEntry> = entries
+                .into_iter()
+                .filter(|entry| entry.offer_id.as_ref() == Some(&offer_id))
+                .collect();
+            let skip = entries.len().saturating_sub(OFFER_AUDIT_ENTRIES);
+            inspection.audit = entries.split_off(skip);
+        }
+        // the offer state is still worth showing during an incident
+        Ok(Err(e)) => log::warn!("Failed to read audit log: {}", e),
+        Err(e) => log::warn!("Failed to read audit log: {}", e),
+    }
+    Ok(HttpResponse::Ok().json(inspection))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/offer/{id}/unassign",
+    tag = "admin",
+    params(("id" = String, Path, description = "Offer id")),
+    responses(
+        (status = 200, description = "Offer returned to the pool", body = OfferAssignmentChange),
+        (status = 404, description = "Offer not found", body = ApiErrorBody),
+        (status = 409, description = "Offer is not assigned", body = ApiErrorBody),
+    )
+)]
+pub async fn offer_unassign(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let offer_id = path.into_inner();
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
+    let mut offers = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    if !offers.offer_map.contains_key(&offer_id) {
+        return Err(offer_not_found(&offer_id));
+    }
+    let change = unassign(
+        &data,
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        &offer_id,
+        now,
+    )
+    .ok_or_else(|| {
+        ApiError::new(ErrorCode::OfferNotAssigned, "Offer is not assigned")
+            .with_details(serde_json::json!({ "offerId": offer_id }))
+    })?;
+    log::info!("Offer {} unassigned by administrator", offer_id);
+    Ok(HttpResponse::Ok().json(change))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/offer/{id}/reassign",
+    tag = "admin",
+    params(("id" = String, Path, description = "Offer id")),
+    request_body(content = OfferReassign, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offer queued for the demand", body = OfferAssignmentChange),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Offer or demand not found", body = ApiErrorBody),
+        (status = 503, description = "Server is shutting down", body = ApiErrorBody),
+    )
+)]
+pub async fn offer_reassign(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let offer_id = path.into_inner();
+    let request = serde_json::from_str::<OfferReassign>(&body).map_err(|e| {
+        log::error!("Error decoding offer reassignment: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    ensure_accepting_assignments(&data)?;
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
+    let mut offers = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    if !offers.offer_map.contains_key(&offer_id) {
+        return Err(offer_not_found(&offer_id));
+    }
+    if !demands.demand_map.contains_key(&request.demand_id) {
+        return Err(demand_not_found(&request.demand_id));
+    }
+    let change = reassign(
+        &data,
+        &mut offers,
+        &mut demands,
+        &mut allocations,
+        &offer_id,
+        &request.demand_id,
+        now,
+    );
+    log::info!(
+        "Offer {} reassigned to demand {} by administrator",
+        offer_id,
+        request.demand_id
+    );
+    Ok(HttpResponse::Ok().json(change))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/offers/unassign",
+    tag = "admin",
+    request_body(content = OfferSelector, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Assigned offers of the provider or requestor returned to the pool", body = Vec<OfferAssignmentChange>),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+    )
+)]
+pub async fn offers_unassign(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let selector = serde_json::from_str::<OfferSelector>(&body).map_err(|e| {
+        log::error!("Error decoding offer selector: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    selector.validate()?;
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
+    let mut offers = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    let changes: Vec<OfferAssignmentChange> = selector
+        .select(&offers)
+        .iter()
+        .filter_map(|offer_id| {
+            unassign(
+                &data,
+                &mut offers,
+                &mut demands,
+                &mut allocations,
+                offer_id,
+                now,
+            )
+        })
+        .collect();
+    log::info!("{} offers unassigned by administrator", changes.len());
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/offers/reassign",
+    tag = "admin",
+    request_body(content = BulkReassign, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offers of the provider or requestor queued for the demand", body = Vec<OfferAssignmentChange>),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found", body = ApiErrorBody),
+        (status = 503, description = "Server is shutting down", body = ApiErrorBody),
+    )
+)]
+pub async fn offers_reassign(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let request = serde_json::from_str::<BulkReassign>(&body).map_err(|e| {
+        log::error!("Error decoding bulk reassignment: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    request.selector.validate()?;
+    ensure_accepting_assignments(&data)?;
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
+    let mut offers = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    if !demands.demand_map.contains_key(&request.demand_id) {
+        return Err(demand_not_found(&request.demand_id));
+    }
+    let changes: Vec<OfferAssignmentChange> = request
+        .selector
+        .select(&offers)
+        .iter()
+        .map(|offer_id| {
+            reassign(
+                &data,
+                &mut offers,
+                &mut demands,
+                &mut allocations,
+                offer_id,
+                &request.demand_id,
+                now,
+            )
+        })
+        .collect();
+    log::info!(
+        "{} offers reassigned to demand {} by administrator",
+        changes.len(),
+        request.demand_id
+    );
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+#[actix_web::test]
+async fn test_unassign_and_reassign_keep_queues_consistent() {
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+
+    let (data, now) = test_state_at_fixture_time();
+    let requestor_a = NodeId::from([1; 20]);
+    let requestor_b = NodeId::from([2; 20]);
+    {
+        let mut demands = data.demands.lock().await;
+        let mut offers = data.lock.lock().await;
+        let mut allocations = data.allocations.lock().await;
+        let expiration = now + chrono::Duration::hours(1);
+        let mut demand_a = test_demand_obj("demand-a", requestor_a, expiration);
+        demands.demand_map.insert(
+            "demand-b".to_string(),
+            test_demand_obj("demand-b", requestor_b, expiration),
+        );
+        for id in ["offer-1", "offer-2"] {
+            let mut offer = test_offer_obj(id, now);
+            offer.requestor_id = Some(requestor_a);
+            demand_a.offer_list.push_back(id.to_string());
+            allocations.allocate(
+                &offer,
+                requestor_a,
+                Some("demand-a"),
+                AllocationSource::Matching,
+                LeaseState::Queued,
+                now,
+            );
+            offers.insert(offer);
+        }
+        // the requestor already took the first offer
+        demand_a.offer_list.pop_front();
+        demand_a.taken = 1;
+        allocations.mark_taken("offer-1");
+        demands.demand_map.insert("demand-a".to_string(), demand_a);
+    }
+
+    let response = offer_unassign(data.clone(), web::Path::from("offer-1".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = offers_reassign(
+        data.clone(),
+        serde_json::json!({ "requestorId": requestor_a, "demandId": "demand-b" }).to_string(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let demands = data.demands.lock().await;
+    let offers = data.lock.lock().await;
+    let allocations = data.allocations.lock().await;
+    let demand_a = &demands.demand_map["demand-a"];
+    assert!(demand_a.offer_list.is_empty());
+    assert_eq!(demand_a.taken, 0);
+    assert_eq!(demand_a.withdrawn_offers, vec!["offer-1"]);
+    assert_eq!(demands.demand_map["demand-b"].offer_list, vec!["offer-2"]);
+    assert_eq!(offers.offer_map["offer-1"].requestor_id, None);
+    assert_eq!(offers.offer_map["offer-2"].requestor_id, Some(requestor_b));
+    let live = allocations.live_counts();
+    assert_eq!(live.get(&requestor_a), None);
+    assert_eq!(live.get(&requestor_b), Some(&1));
+    assert_eq!(
+        allocations.live_allocation("offer-2").unwrap().source,
+        AllocationSource::Admin
+    );
+}
@@ -0,0 +1,175 @@
+use crate::allocations::{Allocation, AllocationSource, LeaseState};
+use crate::clock::checked_sub_secs;
+use crate::rest::error::{ApiError, ApiErrorBody};
+use crate::state::AppState;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
+use ya_client_model::NodeId;
+
+/// All filters are optional and combined
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+pub struct AllocationFilter {
+    #[param(value_type = Option<String>)]
+    pub requestor_id: Option<NodeId>,
+    #[param(value_type = Option<String>)]
+    pub provider_id: Option<NodeId>,
+    pub demand_id: Option<String>,
+    pub offer_id: Option<String>,
+    #[param(inline)]
+    pub source: Option<AllocationSource>,
+    #[param(inline)]
+    pub state: Option<LeaseState>,
+    /// Only live (`true`) or only ended (`false`) allocations
+    pub live: Option<bool>,
+    /// Allocated at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Newest allocations returned, 1000 by default; counts cover all matches
+    pub limit: Option<usize>,
+    /// Window of the allocation rate, 3600 seconds by default
+    pub window_secs: Option<f64>,
+}
+
+impl AllocationFilter {
+    pub fn matches(&self, allocation: &Allocation) -> bool {
+        self.requestor_id
+            .map(|id| id == allocation.requestor_id)
+            .unwrap_or(true)
+            && self
+                .provider_id
+                .map(|id| id == allocation.provider_id)
+                .unwrap_or(true)
+            && self
+                .demand_id
+                .as_ref()
+                .map(|id| allocation.demand_id.as_ref() == Some(id))
+                .unwrap_or(true)
+            && self
+                .offer_id
+                .as_ref()
+                .map(|id| *id == allocation.offer_id)
+                .unwrap_or(true)
+            && self
+                .source
+                .map(|source| source == allocation.source)
+                .unwrap_or(true)
+            && self
+                .state
+                .map(|state| state == allocation.state)
+                .unwrap_or(true)
+            && self
+                .live
+                .map(|live| live == allocation.state.is_live())
+                .unwrap_or(true)
+            && self
+                .since
+                .map(|since| allocation.allocated_at >= since)
+                .unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestorAllocationCounts {
+    /// Offers the requestor holds now
+    pub live: u64,
+    /// Allocations still in the ledger
+    pub total: u64,
+    /// Allocations made within the window
+    pub in_window: u64,
+    /// `inWindow` per minute
+    pub per_minute: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationListing {
+    pub window_secs: f64,
+    /// Counts of the matching allocations by requestor id
+    pub counts: BTreeMap<String, RequestorAllocationCounts>,
+    /// Matching allocations, newest first
+    pub allocations: Vec<Allocation>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/allocations",
+    tag = "allocations",
+    params(AllocationFilter),
+    responses(
+        (status = 200, description = "Allocations of offers to requestors and counts derived from them", body = AllocationListing),
+        (status = 400, description = "Invalid filter", body = ApiErrorBody),
+    )
+)]
+pub async fn list_allocations(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let filter = web::Query::<AllocationFilter>::from_query(req.query_string())
+        .map_err(|e| {
+            log::error!("Error decoding allocation filter: {}", e);
+            ApiError::invalid_format(e)
+        })?
+        .into_inner();
+    let window_secs = filter.window_secs.unwrap_or(3600.0);
+    if !window_secs.is_finite() || window_secs <= 0.0 {
+        return Err(ApiError::invalid_format("windowSecs has to be positive"));
+    }
+    // a window reaching before the representable time range covers everything
+    let window_start =
+        checked_sub_secs(data.now(), window_secs).unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+    let ledger = data.allocations.lock().await;
+    let mut counts: BTreeMap<String, RequestorAllocationCounts> = BTreeMap::new();
+    let mut allocations = Vec::new();
+    for allocation in ledger.allocations.iter().rev() {
+        if !filter.matches(allocation) {
+            continue;
+        }
+        let entry = counts
+            .entry(allocation.requestor_id.to_string())
+            .or_default();
+        entry.total += 1;
+        if allocation.state.is_live() {
+            entry.live += 1;
+        }
+        if allocation.allocated_at >= window_start {
+            entry.in_window += 1;
+        }
+        if allocations.len() < filter.limit.unwrap_or(1000) {
+            allocations.push(allocation.clone());
+        }
+    }
+    for entry in counts.values_mut() {
+        entry.per_minute = entry.in_window as f64 * 60.0 / window_secs;
+    }
+    Ok(HttpResponse::Ok().json(AllocationListing {
+        window_secs,
+        counts,
+        allocations,
+    }))
+}
+
+#[actix_web::test]
+async fn test_huge_windows_and_retention_do_not_overflow() {
+    use crate::state::test_state_at_fixture_time;
+
+    let (data, _) = test_state_at_fixture_time();
+    let req =
+        actix_web::test::TestRequest::with_uri("/allocations?windowSecs=1e15").to_http_request();
+    let response = list_allocations(data.clone(), req).await.unwrap();
+    assert_eq!(response.status(), actix_web::http::StatusCode::OK);
+
+    let mut config = (*data.config()).clone();
+    config.cleanup.offer_expiry_grace_secs = 1e15;
+    config.cleanup.allocation_history_secs = 1e15;
+    config.liveness.forget_after_secs = 1e15;
+    data.set_config(config);
+    crate::rest::offer::clean_old_offers::clean_old_offers(data.clone()).await;
+    crate::allocations::prune_allocation_history(data.clone()).await;
+    crate::rest::provider::forget_silent_providers(data.clone()).await;
+}
@@ -1,70 +1,78 @@
-use crate::state::{AppState, DemandObj};
+use crate::allocations::{AllocationSource, LeaseState};
+use crate::audit::AuditEntry;
+use crate::health::ensure_accepting_assignments;
+use crate::rest::demand::find_demand;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
-use ya_client_model::NodeId;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AddOfferToDemand {
     pub demand_id: String,
     pub offer_id: String,
 }
 
-pub async fn add_offer_to_demand(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<AddOfferToDemand>(&body);
-    let add_offer = match decoded {
-        Ok(filer) => filer,
-        Err(e) => {
-            log::error!("Error decoding add offer to demand: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/append-offer",
+    tag = "requestor",
+    request_body(content = AddOfferToDemand, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offer appended to the demand queue", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Offer or demand not found", body = ApiErrorBody),
+        (status = 409, description = "Offer is already taken, or node id given for a node with several demands", body = ApiErrorBody),
+        (status = 422, description = "Demand id is neither a demand nor a node id", body = ApiErrorBody),
+        (status = 503, description = "Server is shutting down", body = ApiErrorBody),
+    )
+)]
+pub async fn add_offer_to_demand(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let add_offer = serde_json::from_str::<AddOfferToDemand>(&body).map_err(|e| {
+        log::error!("Error decoding add offer to demand: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    ensure_accepting_assignments(&data)?;
     let demand_id = add_offer.demand_id;
     let offer_id = add_offer.offer_id;
 
     let mut lock = data.demands.lock().await;
     let mut offers_lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
 
     let offer = offers_lock.offer_map.get_mut(&offer_id);
 
     let offer = match offer {
         Some(offer) => offer,
         None => {
-            return HttpResponse::NotFound().body("Offer not found");
-        }
-    };
-
-    let get_demand = match lock.demand_map.contains_key(&demand_id) {
-        true => lock.demand_map.get_mut(&demand_id),
-        false => {
-            let node_id = match NodeId::from_str(&demand_id) {
-                Ok(id) => id,
-                Err(_) => {
-                    return HttpResponse::BadRequest().body("Invalid offer ID format or not found");
-                }
-            };
-            let mut get_demand: Option<&mut DemandObj> = None;
-            for (_, v) in lock.demand_map.iter_mut() {
-                if v.demand.node_id == node_id {
-                    get_demand = Some(v);
-                    break;
-                }
-            }
-            get_demand
+            return Err(ApiError::new(ErrorCode::OfferNotFound, "Offer not found")
+                .with_details(serde_json::json!({ "offerId": offer_id })));
         }
     };
 
-    let demand_obj = match get_demand {
-        Some(demand) => demand,
-        None => {
-            return HttpResponse::NotFound().body("Demand not found");
-        }
-    };
+    let demand_obj = find_demand(&mut lock, &demand_id)?;
     if offer.requestor_id.is_some() {
-        return HttpResponse::Conflict().body("Offer is already taken");
+        return Err(
+            ApiError::new(ErrorCode::OfferAlreadyTaken, "Offer is already taken").with_details(
+                serde_json::json!({ "offerId": offer_id, "requestorId": offer.requestor_id }),
+            ),
+        );
     }
     offer.requestor_id = Some(demand_obj.demand.node_id);
     demand_obj.offer_list.push_back(offer.offer.id.clone());
-    HttpResponse::Ok().body("Offer added to demand successfully")
+    let allocation = allocations.allocate(
+        offer,
+        demand_obj.demand.node_id,
+        Some(&demand_obj.demand.id),
+        AllocationSource::AppendOffer,
+        LeaseState::Queued,
+        data.now(),
+    );
+    data.audit.record(AuditEntry::assigned(allocation));
+    Ok(HttpResponse::Ok().body("Offer added to demand successfully"))
 }
@@ -1,22 +1,140 @@
+use crate::audit::{AuditAction, AuditEntry};
 use crate::model::demand::base::DemandCancellation;
+use crate::offers::release_queued_offers;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
 use crate::state::AppState;
 use actix_web::{web, HttpResponse};
 
-pub async fn demand_cancel(data: web::Data<AppState>, item: String) -> HttpResponse {
-    let decode = serde_json::from_str::<DemandCancellation>(&item);
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/cancel",
+    tag = "requestor",
+    request_body(content = DemandCancellation, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Demand cancelled, queued offers returned to the pool", body = String),
+        (status = 400, description = "Invalid cancellation format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found", body = ApiErrorBody),
+    )
+)]
+pub async fn demand_cancel(
+    data: web::Data<AppState>,
+    item: String,
+) -> Result<HttpResponse, ApiError> {
+    let cancellation = serde_json::from_str::<DemandCancellation>(&item).map_err(|e| {
+        log::error!("Error decoding demand cancellation: {}", e);
+        ApiError::new(
+            ErrorCode::InvalidFormat,
+            format!("Invalid cancellation format {}", e),
+        )
+        .with_details(serde_json::json!({ "error": e.to_string() }))
+    })?;
 
-    let cancellation = match decode {
-        Ok(filer) => filer,
-        Err(e) => {
-            log::error!("Error decoding demand cancellation: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid cancellation format {}", e));
-        }
+    let mut lock = data.demands.lock().await;
+    let Some(demand_obj) = lock.demand_map.remove(&cancellation.demand_id) else {
+        return Err(ApiError::new(ErrorCode::DemandNotFound, "Demand not found")
+            .with_details(serde_json::json!({ "demandId": cancellation.demand_id })));
     };
+    let mut offers_lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    let released =
+        release_queued_offers(&mut offers_lock, &mut allocations, &demand_obj, data.now());
+    data.audit.record(
+        AuditEntry::new(AuditAction::DemandCancelled, data.now())
+            .demand(&demand_obj.demand.id)
+            .requestor(demand_obj.demand.node_id)
+            .with_details(serde_json::json!({ "releasedOffers": released })),
+    );
+    log::info!(
+        "Demand {} cancelled, {} queued offers returned to the pool",
+        demand_obj.demand.id,
+        released
+    );
+    Ok(HttpResponse::Ok().body(format!(
+        "Demand cancelled successfully, {released} queued offers returned to the pool"
+    )))
+}
 
+/// Remove expired demands, their queued offers go back to the pool
+pub async fn clean_old_demands(data: web::Data<AppState>) {
+    let now = data.now();
     let mut lock = data.demands.lock().await;
-    if lock.demand_map.remove(&cancellation.demand_id).is_some() {
-        HttpResponse::Ok().body("Demand cancelled successfully")
-    } else {
-        HttpResponse::NotFound().body("Demand not found")
+    let expired: Vec<String> = lock
+        .demand_map
+        .iter()
+        .filter(|(_, demand_obj)| demand_obj.demand.expiration_ts.and_utc() <= now)
+        .map(|(id, _)| id.clone())
+        .collect();
+    if expired.is_empty() {
+        return;
+    }
+    let mut offers_lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    let mut released = 0;
+    for demand_id in &expired {
+        if let Some(demand_obj) = lock.demand_map.remove(demand_id) {
+            let demand_released =
+                release_queued_offers(&mut offers_lock, &mut allocations, &demand_obj, now);
+            data.audit.record(
+                AuditEntry::new(AuditAction::DemandExpired, now)
+                    .demand(demand_id)
+                    .requestor(demand_obj.demand.node_id)
+                    .with_details(serde_json::json!({ "releasedOffers": demand_released })),
+            );
+            released += demand_released;
+        }
     }
+    log::info!(
+        "Removed {} expired demands, {} queued offers returned to the pool",
+        expired.len(),
+        released
+    );
+}
+
+#[actix_web::test]
+async fn test_expired_demand_releases_queued_offers() {
+    use crate::allocations::{AllocationSource, LeaseState};
+    use crate::clock::MockClock;
+    use crate::state::{test_demand_obj, test_offer_obj, test_state};
+    use std::sync::Arc;
+    use ya_client_model::NodeId;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(now));
+    let data = test_state(clock.clone());
+    let requestor_id = NodeId::default();
+    let mut demand = test_demand_obj("demand", requestor_id, now + chrono::Duration::minutes(10));
+    let mut offer = test_offer_obj("offer", now);
+    offer.requestor_id = Some(requestor_id);
+    demand.offer_list.push_back("offer".to_string());
+    data.allocations.lock().await.allocate(
+        &offer,
+        requestor_id,
+        Some("demand"),
+        AllocationSource::Matching,
+        LeaseState::Queued,
+        now,
+    );
+    data.lock
+        .lock()
+        .await
+        .offer_map
+        .insert("offer".to_string(), offer);
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert("demand".to_string(), demand);
+
+    clock.advance(chrono::Duration::minutes(9));
+    clean_old_demands(data.clone()).await;
+    assert_eq!(data.demands.lock().await.demand_map.len(), 1);
+
+    clock.advance(chrono::Duration::minutes(1));
+    clean_old_demands(data.clone()).await;
+    assert!(data.demands.lock().await.demand_map.is_empty());
+    assert_eq!(data.lock.lock().await.offer_map["offer"].requestor_id, None);
+    assert_eq!(
+        data.allocations.lock().await.allocations[0].state,
+        LeaseState::Released
+    );
 }
@@ -1,53 +1,167 @@
+use crate::audit::{AuditAction, AuditEntry};
 use crate::model::demand::base::DemandSubscription;
+use crate::rest::demand::{validate_max_per_tick, validate_weight};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
 use crate::state::{AppState, DemandObj};
 use actix_web::{web, HttpResponse};
 use std::collections::VecDeque;
 
-pub async fn demand_new(data: web::Data<AppState>, item: String) -> HttpResponse {
-    let decode = serde_json::from_str::<DemandSubscription>(&item);
-
-    let demand = match decode {
-        Ok(filer) => filer,
-        Err(e) => {
-            log::error!("Error decoding demand: {}", e);
-            log::error!("Received demand: {}", item);
-            return HttpResponse::BadRequest().body(format!("Invalid filter format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/new",
+    tag = "requestor",
+    request_body(content = DemandSubscription, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Demand registered", body = DemandSubscription),
+        (status = 400, description = "Invalid demand format", body = ApiErrorBody),
+        (status = 409, description = "Demand with the same id already exists", body = ApiErrorBody),
+    )
+)]
+pub async fn demand_new(data: web::Data<AppState>, item: String) -> Result<HttpResponse, ApiError> {
+    let demand = serde_json::from_str::<DemandSubscription>(&item).map_err(|e| {
+        log::error!("Error decoding demand: {}", e);
+        log::error!("Received demand: {}", item);
+        ApiError::new(
+            ErrorCode::InvalidFormat,
+            format!("Invalid filter format {}", e),
+        )
+        .with_details(serde_json::json!({ "error": e.to_string() }))
+    })?;
+    validate_weight(demand.weight)?;
+    validate_max_per_tick(demand.max_per_tick)?;
     let mut lock = data.demands.lock().await;
 
     if lock.demand_map.contains_key(&demand.id) {
-        return HttpResponse::Conflict().body("Demand with the same id already exists");
+        return Err(ApiError::new(
+            ErrorCode::DemandAlreadyExists,
+            "Demand with the same id already exists",
+        )
+        .with_details(serde_json::json!({ "demandId": demand.id })));
     }
 
-    // find existing demand from the same node
-    let last_demand = lock
+    // A node can run several workloads at once. The same workload subscribed again
+    // (requestor restart) replaces the old demand and inherits its queue and the offers it
+    // took, so the target counts what the workload already holds.
+    let resubscribed: Vec<String> = lock
         .demand_map
         .values()
-        .find(|v| v.demand.node_id == demand.node_id);
+        .filter(|v| v.demand.node_id == demand.node_id && v.demand.same_workload(&demand))
+        .map(|v| v.demand.id.clone())
+        .collect();
 
     let mut copy_offer_list = VecDeque::new();
-    if let Some(existing_demand) = last_demand {
-        log::warn!(
-            "Replacing existing demand {} from node {} with new demand {}",
-            existing_demand.demand.id,
-            existing_demand.demand.node_id,
-            demand.id
-        );
-        copy_offer_list = existing_demand.offer_list.clone();
+    let mut taken = 0;
+    for existing_id in &resubscribed {
+        if let Some(existing_demand) = lock.demand_map.remove(existing_id) {
+            log::warn!(
+                "Replacing existing demand {} from node {} with new demand {}",
+                existing_demand.demand.id,
+                existing_demand.demand.node_id,
+                demand.id
+            );
+            data.audit.record(
+                AuditEntry::new(AuditAction::DemandReplaced, data.now())
+                    .demand(existing_id)
+                    .requestor(existing_demand.demand.node_id)
+                    .with_details(serde_json::json!({
+                        "replacedBy": demand.id,
+                        "queuedOffers": existing_demand.offer_list.len(),
+                        "takenOffers": existing_demand.taken,
+                    })),
+            );
+            copy_offer_list.extend(existing_demand.offer_list);
+            taken += existing_demand.taken;
+        }
+    }
+    if !resubscribed.is_empty() {
+        let mut allocations = data.allocations.lock().await;
+        for existing_id in &resubscribed {
+            allocations.move_demand(existing_id, &demand.id);
+        }
     }
 
-    // Remove existing demand from the same node, including last_demand found above.
-    lock.demand_map
-        .retain(|_, v| v.demand.node_id != demand.node_id);
-
+    data.audit.record(
+        AuditEntry::new(AuditAction::DemandCreated, data.now())
+            .demand(&demand.id)
+            .requestor(demand.node_id)
+            .with_details(serde_json::json!({
+                "replaced": resubscribed,
+                "inheritedOffers": copy_offer_list.len(),
+                "inheritedTaken": taken,
+            })),
+    );
     let _ = lock.demand_map.insert(
         demand.id.clone(),
         DemandObj {
+            withdrawn_offers: VecDeque::new(),
+            taken,
             demand: demand.clone(),
             offer_list: copy_offer_list,
         },
     );
 
-    HttpResponse::Ok().json(demand)
+    Ok(HttpResponse::Ok().json(demand))
+}
+
+#[actix_web::test]
+async fn test_resubscribed_workload_replaces_demand() {
+    use crate::allocations::{AllocationSource, LeaseState};
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    let subscription = |id: &str, expiration: i64, package: &str| {
+        let mut demand = test_demand_obj(id, NodeId::default(), now).demand;
+        demand.properties = serde_json::json!({
+            "golem.node.debug.subnet": "public",
+            "golem.srv.comp.expiration": expiration,
+            "golem.srv.comp.task_package": package,
+        })
+        .to_string();
+        serde_json::to_string(&demand).unwrap()
+    };
+    demand_new(data.clone(), subscription("old", 1, "hash:a"))
+        .await
+        .unwrap();
+    for (offer_id, state) in [("offer", LeaseState::Queued), ("held", LeaseState::Taken)] {
+        data.allocations.lock().await.allocate(
+            &test_offer_obj(offer_id, now),
+            NodeId::default(),
+            Some("old"),
+            AllocationSource::Matching,
+            state,
+            now,
+        );
+    }
+    {
+        let mut demands = data.demands.lock().await;
+        let old = demands.demand_map.get_mut("old").unwrap();
+        old.offer_list.push_back("offer".to_string());
+        old.taken = 1;
+    }
+
+    // restarted requestor, only the expiration differs
+    demand_new(data.clone(), subscription("new", 2, "hash:a"))
+        .await
+        .unwrap();
+    // another workload of the same node
+    demand_new(data.clone(), subscription("other", 2, "hash:b"))
+        .await
+        .unwrap();
+    let demands = data.demands.lock().await;
+    assert!(!demands.demand_map.contains_key("old"));
+    assert_eq!(demands.demand_map["new"].offer_list, ["offer".to_string()]);
+    assert_eq!(demands.demand_map["new"].taken, 1);
+    assert!(demands.demand_map.contains_key("other"));
+    let allocations = data.allocations.lock().await;
+    for offer_id in ["offer", "held"] {
+        assert_eq!(
+            allocations
+                .live_allocation(offer_id)
+                .unwrap()
+                .demand_id
+                .as_deref(),
+            Some("new")
+        );
+    }
 }
@@ -1,8 +1,77 @@
-use crate::state::{AppState, DemandObj};
+use crate::state::{AppState, DemandObj, DemandProgress};
 use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DemandListEntry {
+    #[serde(flatten)]
+    pub demand: DemandObj,
+    pub progress: DemandProgress,
+}
+
+#[utoipa::path(
+    get,
+    path = "/requestor/demands/list",
+    tag = "requestor",
+    responses((status = 200, description = "All active demands with progress toward their target", body = Vec<DemandListEntry>))
+)]
 pub async fn list_demands(data: web::Data<AppState>) -> HttpResponse {
     let lock = data.demands.lock().await;
-    let demands: Vec<&DemandObj> = lock.demand_map.values().collect();
+    let demands: Vec<DemandListEntry> = lock
+        .demand_map
+        .values()
+        .map(|demand| DemandListEntry {
+            demand: demand.clone(),
+            progress: demand.progress(),
+        })
+        .collect();
     HttpResponse::Ok().json(demands)
 }
+
+#[actix_web::test]
+async fn test_matching_stops_at_target_count() {
+    use crate::rest::demand::pick_offers_for_all_demands;
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    for i in 0..4 {
+        data.lock.lock().await.insert(test_offer_obj(
+            &format!("offer-{}", i),
+            now - chrono::Duration::minutes(i + 1),
+        ));
+    }
+    let mut demand = test_demand_obj(
+        "demand",
+        NodeId::default(),
+        now + chrono::Duration::hours(1),
+    );
+    demand.demand.central_net_address = Some("127.0.0.1".to_string());
+    demand.demand.target_count = Some(3);
+    demand.demand.max_per_tick = Some(2);
+    demand.taken = 1;
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert("demand".to_string(), demand);
+
+    for _ in 0..3 {
+        pick_offers_for_all_demands(data.clone()).await;
+    }
+    let response = list_demands(data.clone()).await;
+    let demands: Vec<DemandListEntry> = serde_json::from_slice(
+        &actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(demands.len(), 1);
+    let progress = &demands[0].progress;
+    assert_eq!(progress.queued, 2);
+    assert_eq!(progress.taken, 1);
+    assert_eq!(progress.target_count, Some(3));
+    assert_eq!(progress.remaining, Some(0));
+    assert!(progress.complete);
+}
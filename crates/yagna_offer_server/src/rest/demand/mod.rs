@@ -4,22 +4,90 @@ pub mod demand_new;
 pub mod list_demands;
 pub mod pick_offer_to_demand;
 pub mod take_offer_from_queue;
+pub mod update_demand;
 
-use crate::rest::demand::pick_offer_to_demand::{local_pick_offer_to_demand, PickOfferToDemand};
-use crate::state::{AppState, DemandObj};
+use crate::config::Preemption;
+use crate::rest::demand::pick_offer_to_demand::{
+    local_pick_offer_to_demand, local_preempt_offer_to_demand, PickOfferToDemand,
+};
+use crate::rest::error::{ApiError, ErrorCode};
+use crate::state::{AppState, DemandObj, Demands};
 use actix_web::web;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::env;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicI32, AtomicI64};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TakeOfferFromQueue {
     pub demand_id: String,
     pub take_at_once: Option<usize>,
 }
 
+/// Weights divide the share of given offers, so they have to be positive
+pub(crate) fn validate_weight(weight: Option<f64>) -> Result<(), ApiError> {
+    match weight {
+        Some(weight) if !(weight.is_finite() && weight > 0.0) => Err(ApiError::new(
+            ErrorCode::InvalidFormat,
+            "Invalid format, weight has to be positive",
+        )
+        .with_details(serde_json::json!({ "weight": weight }))),
+        _ => Ok(()),
+    }
+}
+
+/// A demand allowed no offers per tick would never be served
+pub(crate) fn validate_max_per_tick(max_per_tick: Option<u64>) -> Result<(), ApiError> {
+    match max_per_tick {
+        Some(0) => Err(ApiError::new(
+            ErrorCode::InvalidFormat,
+            "Invalid format, maxPerTick has to be at least 1",
+        )
+        .with_details(serde_json::json!({ "maxPerTick": 0 }))),
+        _ => Ok(()),
+    }
+}
+
+/// Demand with id `demand_id` or, as older requestors send, the only demand of the node
+/// with that id
+pub(crate) fn find_demand<'a>(
+    demands: &'a mut Demands,
+    demand_id: &str,
+) -> Result<&'a mut DemandObj, ApiError> {
+    if demands.demand_map.contains_key(demand_id) {
+        return Ok(demands.demand_map.get_mut(demand_id).unwrap());
+    }
+    let node_id = NodeId::from_str(demand_id).map_err(|_| {
+        ApiError::new(
+            ErrorCode::InvalidDemandId,
+            "Invalid offer ID format or not found",
+        )
+        .with_details(serde_json::json!({ "demandId": demand_id }))
+    })?;
+    let candidates: Vec<String> = demands
+        .demand_map
+        .values()
+        .filter(|demand| demand.demand.node_id == node_id)
+        .map(|demand| demand.demand.id.clone())
+        .collect();
+    match candidates.as_slice() {
+        [] => Err(ApiError::new(ErrorCode::DemandNotFound, "Demand not found")
+            .with_details(serde_json::json!({ "demandId": demand_id }))),
+        [found] => Ok(demands.demand_map.get_mut(found).unwrap()),
+        _ => Err(ApiError::new(
+            ErrorCode::AmbiguousDemand,
+            "Node has several demands, use a demand id",
+        )
+        .with_details(serde_json::json!({
+            "demandId": demand_id,
+            "candidateDemandIds": candidates,
+        }))),
+    }
+}
+
 static NO_PICKED_OFFERS: AtomicI32 = AtomicI32::new(0);
 static LAST_LOG_TIME: AtomicI64 = AtomicI64::new(0);
 static LAST_CENTRAL_NET: AtomicI64 = AtomicI64::new(0);
@@ -31,12 +99,9 @@ pub async fn pick_offers_for_all_demands(data: web::Data<AppState>) {
     };
 
     let mut central_nets = HashMap::new();
-    {
-        let _lock = data.offers_given_to_node.lock().await;
-        for demand in demands.iter() {
-            if let Some(net_address) = &demand.demand.central_net_address {
-                central_nets.insert(net_address.clone(), true);
-            }
+    for demand in demands.iter() {
+        if let Some(net_address) = &demand.demand.central_net_address {
+            central_nets.insert(net_address.clone(), true);
         }
     }
 
@@ -54,9 +119,12 @@ pub async fn pick_offers_for_all_demands(data: web::Data<AppState>) {
         net_id.clone()
     };
 
+    let matching = data.config().matching.clone();
+
+    // (demand id, offers the node holds, picks this tick, priority, weight)
     let mut sort_by_given = Vec::new();
     {
-        let lock = data.offers_given_to_node.lock().await;
+        let live_counts = data.allocations.lock().await.live_counts();
         for demand in demands.iter() {
             if demand
                 .demand
@@ -67,56 +135,211 @@ pub async fn pick_offers_for_all_demands(data: web::Data<AppState>) {
             {
                 continue;
             }
-            if let Some(count) = lock.get(&demand.demand.node_id.to_string()) {
-                sort_by_given.push((demand.demand.id.clone(), *count));
-            } else {
-                sort_by_given.push((demand.demand.id.clone(), 0));
+            if demand.remaining() == Some(0) {
+                // target reached
+                continue;
             }
+            let picks = demand
+                .demand
+                .max_per_tick
+                .unwrap_or(1)
+                .min(demand.remaining().unwrap_or(u64::MAX));
+            let schedule = matching.schedule(&demand.demand);
+            let given = live_counts
+                .get(&demand.demand.node_id)
+                .copied()
+                .unwrap_or(0);
+            sort_by_given.push((
+                demand.demand.id.clone(),
+                given,
+                picks,
+                schedule.priority,
+                schedule.weight,
+            ));
         }
     }
 
-    sort_by_given.sort_by_key(|k| k.1);
+    // higher priority first, then the smallest weighted share of live offers
+    sort_by_given.sort_by(|a, b| {
+        b.3.cmp(&a.3)
+            .then_with(|| ((a.1 + 1) as f64 / a.4).total_cmp(&((b.1 + 1) as f64 / b.4)))
+            .then_with(|| a.0.cmp(&b.0))
+    });
 
-    let log_every_sec: f64 = env::var("LOG_EVERY_SEC")
-        .unwrap_or_else(|_| "10".to_string())
-        .parse()
-        .unwrap_or(10.0);
+    let log_every_sec = matching.log_every_secs;
 
     let no_picked_offers = &NO_PICKED_OFFERS;
     if let Some(pair) = sort_by_given.first() {
-        let pick_offer = PickOfferToDemand {
-            demand_id: pair.0.clone(),
-        };
-        log::debug!(
-            "Picking offer for node {}, that already received: {} offers",
-            pair.0,
-            pair.1
-        );
         let last_log_time = LAST_LOG_TIME.load(std::sync::atomic::Ordering::SeqCst);
         let val = no_picked_offers.load(std::sync::atomic::Ordering::SeqCst);
 
-        let current_time = chrono::Utc::now().timestamp_millis();
+        let current_time = data.now().timestamp_millis();
         if current_time - last_log_time > (log_every_sec * 1000.0) as i64 {
             log::info!(
-                "Picked offers for {} demands so far, currently at node {} that received {} offers",
+                "Picked offers for {} demands so far, currently at node {} that holds {} offers",
                 val,
                 pair.0,
                 pair.1
             );
             LAST_LOG_TIME.store(current_time, std::sync::atomic::Ordering::SeqCst);
         }
-        match local_pick_offer_to_demand(data.clone(), pick_offer, Some(&net_selected)).await {
-            Ok(found) => {
-                if !found {
-                    log::debug!("No available offers found to pick for demand {}", pair.0);
-                } else {
-                    log::info!("Offer found for central net id: {}", &net_selected);
-                    no_picked_offers.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    // a demand no free offer meets must not block the demands sorted after it
+    for pair in sort_by_given.iter() {
+        let pick_offer = PickOfferToDemand {
+            demand_id: pair.0.clone(),
+        };
+        log::debug!(
+            "Picking offer for node {}, that currently holds: {} offers",
+            pair.0,
+            pair.1
+        );
+        let mut picked = 0;
+        for _ in 0..pair.2 {
+            match local_pick_offer_to_demand(data.clone(), pick_offer.clone(), Some(&net_selected))
+                .await
+            {
+                Ok(found) => {
+                    if !found {
+                        log::debug!("No available offers found to pick for demand {}", pair.0);
+                        break;
+                    } else {
+                        log::info!("Offer found for central net id: {}", &net_selected);
+                        no_picked_offers.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        picked += 1;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Failed to pick offer for demand: {}", e);
+                    break;
                 }
             }
+        }
+        if picked > 0 {
+            return;
+        }
+    }
+    if matching.preemption != Preemption::QueuedOffers {
+        return;
+    }
+
+    // free offers ran out, take queued offers from lower priority demands
+    for pair in sort_by_given.iter() {
+        let pick_offer = PickOfferToDemand {
+            demand_id: pair.0.clone(),
+        };
+        match local_preempt_offer_to_demand(data.clone(), pick_offer, Some(&net_selected)).await {
+            Ok(true) => {
+                no_picked_offers.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                return;
+            }
+            Ok(false) => {}
             Err(e) => {
-                log::warn!("Failed to pick offer for demand: {}", e);
+                log::warn!("Failed to preempt offer for demand: {}", e);
             }
         }
     }
 }
+
+#[actix_web::test]
+async fn test_unsatisfiable_demand_does_not_block_others() {
+    use crate::model::offer::attributes::ResourceRequirements;
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+    use std::str::FromStr;
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    let offer = test_offer_obj("offer", now - chrono::Duration::minutes(1));
+    data.lock
+        .lock()
+        .await
+        .offer_map
+        .insert("offer".to_string(), offer);
+    let expiration = now + chrono::Duration::hours(1);
+    // sorted first, it holds no offers and its id is smaller
+    let mut unsatisfiable = test_demand_obj("a-demand", NodeId::default(), expiration);
+    unsatisfiable.demand.requirements = Some(ResourceRequirements {
+        gpu_count_min: Some(64),
+        ..Default::default()
+    });
+    let satisfiable = test_demand_obj(
+        "b-demand",
+        NodeId::from_str("0x0000000000000000000000000000000000000001").unwrap(),
+        expiration,
+    );
+    {
+        let mut demands = data.demands.lock().await;
+        for mut demand in [unsatisfiable, satisfiable] {
+            demand.demand.central_net_address = Some("127.0.0.1".to_string());
+            demands.demand_map.insert(demand.demand.id.clone(), demand);
+        }
+    }
+
+    pick_offers_for_all_demands(data.clone()).await;
+    let demands = data.demands.lock().await;
+    assert!(demands.demand_map["a-demand"].offer_list.is_empty());
+    assert_eq!(
+        demands.demand_map["b-demand"].offer_list,
+        ["offer".to_string()]
+    );
+}
+
+#[actix_web::test]
+async fn test_priority_and_weighted_share_order_picks() {
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+    use std::str::FromStr;
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    {
+        let mut offers = data.lock.lock().await;
+        for i in 0..8 {
+            offers.insert(test_offer_obj(
+                &format!("offer-{}", i),
+                now - chrono::Duration::minutes(i + 1),
+            ));
+        }
+    }
+    let demand = |id: &str, node: u8, priority: Option<i32>, weight: Option<f64>| {
+        let node_id = NodeId::from_str(&format!("0x{:040x}", node)).unwrap();
+        let mut demand = test_demand_obj(id, node_id, now + chrono::Duration::hours(1));
+        demand.demand.central_net_address = Some("127.0.0.1".to_string());
+        demand.demand.priority = priority;
+        demand.demand.weight = weight;
+        demand
+    };
+    let queued = |data: &web::Data<AppState>, id: &str| {
+        let data = data.clone();
+        let id = id.to_string();
+        async move { data.demands.lock().await.demand_map[&id].offer_list.len() }
+    };
+
+    // sorted last by id, but served first
+    let urgent = demand("z-urgent", 3, Some(5), None);
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert(urgent.demand.id.clone(), urgent);
+    for (id, node, weight) in [("a-light", 1, 1.0), ("b-heavy", 2, 3.0)] {
+        let demand = demand(id, node, None, Some(weight));
+        data.demands
+            .lock()
+            .await
+            .demand_map
+            .insert(id.to_string(), demand);
+    }
+    pick_offers_for_all_demands(data.clone()).await;
+    assert_eq!(queued(&data, "z-urgent").await, 1);
+    assert_eq!(queued(&data, "a-light").await, 0);
+    assert_eq!(queued(&data, "b-heavy").await, 0);
+    data.demands.lock().await.demand_map.remove("z-urgent");
+
+    // one demand is served per tick, by the smallest (live + 1) / weight
+    for _ in 0..7 {
+        pick_offers_for_all_demands(data.clone()).await;
+    }
+    assert_eq!(queued(&data, "a-light").await, 2);
+    assert_eq!(queued(&data, "b-heavy").await, 5);
+}
@@ -1,62 +1,56 @@
-use crate::state::{AppState, DemandObj};
+use crate::allocations::{AllocationSource, LeaseState};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::config::LivenessConfig;
+use crate::health::ensure_accepting_assignments;
+use crate::rest::demand::find_demand;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::{AppState, DemandObj, Demands, OfferObj, Providers};
 use actix_web::{web, HttpResponse};
-use anyhow::bail;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::env;
 use std::ops::Sub;
-use std::str::FromStr;
 use std::time::Instant;
-use ya_client_model::NodeId;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PickOfferToDemand {
     pub demand_id: String,
 }
 
-pub async fn pick_offer_to_demand(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<PickOfferToDemand>(&body);
-
-    let add_offer = match decoded {
-        Ok(filer) => filer,
-        Err(e) => {
-            log::error!("Error decoding pick offer to demand: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/append-any-offer",
+    tag = "requestor",
+    request_body(content = PickOfferToDemand, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offer appended to the demand queue", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found or no available offers", body = ApiErrorBody),
+        (status = 409, description = "Node id given for a node with several demands", body = ApiErrorBody),
+        (status = 422, description = "Demand id is neither a demand nor a node id", body = ApiErrorBody),
+        (status = 503, description = "Server is shutting down", body = ApiErrorBody),
+    )
+)]
+pub async fn pick_offer_to_demand(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let add_offer = serde_json::from_str::<PickOfferToDemand>(&body).map_err(|e| {
+        log::error!("Error decoding pick offer to demand: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    ensure_accepting_assignments(&data)?;
     let demand_id = add_offer.demand_id;
 
     let mut lock = data.demands.lock().await;
     let mut offers_lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
 
-    let get_demand = match lock.demand_map.contains_key(&demand_id) {
-        true => lock.demand_map.get_mut(&demand_id),
-        false => {
-            let node_id = match NodeId::from_str(&demand_id) {
-                Ok(id) => id,
-                Err(_) => {
-                    return HttpResponse::BadRequest().body("Invalid offer ID format or not found");
-                }
-            };
-            let mut get_demand: Option<&mut DemandObj> = None;
-            for (_, v) in lock.demand_map.iter_mut() {
-                if v.demand.node_id == node_id {
-                    get_demand = Some(v);
-                    break;
-                }
-            }
-            get_demand
-        }
-    };
-
-    let demand_obj = match get_demand {
-        Some(demand) => demand,
-        None => {
-            return HttpResponse::NotFound().body("Demand not found");
-        }
-    };
+    let demand_obj = find_demand(&mut lock, &demand_id)?;
 
+    let liveness = data.config().liveness.clone();
+    let providers = data.providers.lock().await;
     let mut name_filter = "";
     let mut selected_offer_id = None;
 
@@ -65,21 +59,20 @@ pub async fn pick_offer_to_demand(data: web::Data<AppState>, body: String) -> Ht
         name_filter = addr.split(".").next().unwrap_or("N/A");
     }
 
+    let criteria = demand_obj.demand.criteria();
     for offer_pair in offers_lock.offer_map.iter_mut() {
         let offer = offer_pair.1;
 
-        if !offer
-            .offer
-            .properties
-            .golem
-            .node
-            .id
-            .name
-            .contains(name_filter)
+        if !offer.offer.properties.node_name.contains(name_filter)
+            || !criteria.accepts(&offer.attributes)
         {
             continue;
         }
 
+        if !liveness.is_alive(providers.last_seen(&offer.offer.provider_id), data.now()) {
+            continue;
+        }
+
         if offer.requestor_id.is_none() {
             selected_offer_id = Some(offer);
             break;
@@ -89,13 +82,121 @@ pub async fn pick_offer_to_demand(data: web::Data<AppState>, body: String) -> Ht
     let offer = match selected_offer_id {
         Some(offer) => offer,
         None => {
-            return HttpResponse::NotFound().body("No available offers found");
+            return Err(ApiError::new(
+                ErrorCode::NoAvailableOffers,
+                "No available offers found",
+            ));
         }
     };
 
     offer.requestor_id = Some(demand_obj.demand.node_id);
     demand_obj.offer_list.push_back(offer.offer.id.clone());
-    HttpResponse::Ok().body("Offer added to demand successfully")
+    let allocation = allocations.allocate(
+        offer,
+        demand_obj.demand.node_id,
+        Some(&demand_obj.demand.id),
+        AllocationSource::AppendAnyOffer,
+        LeaseState::Queued,
+        data.now(),
+    );
+    data.audit.record(AuditEntry::assigned(allocation));
+    Ok(HttpResponse::Ok().body("Offer added to demand successfully"))
+}
+
+/// `matching.offer_group`, a regex or, when it does not compile, a node name part
+enum OfferGroup {
+    Pattern(regex::Regex),
+    Name(String),
+}
+
+impl OfferGroup {
+    fn new(group: &str) -> Self {
+        match regex::Regex::new(group) {
+            Ok(pattern) => OfferGroup::Pattern(pattern),
+            Err(_) => OfferGroup::Name(format!("{}-", group)),
+        }
+    }
+
+    fn is_match(&self, node_name: &str) -> bool {
+        match self {
+            OfferGroup::Pattern(pattern) => pattern.is_match(node_name),
+            OfferGroup::Name(name) => node_name.contains(name.as_str()),
+        }
+    }
+}
+
+/// Conditions an offer has to meet to be handed out by periodic matching
+struct PickFilter {
+    liveness: LivenessConfig,
+    offer_group: Option<OfferGroup>,
+    central_net_filter: Option<String>,
+    now: DateTime<Utc>,
+}
+
+impl PickFilter {
+    fn new(data: &AppState, central_net_filter: Option<&String>) -> Self {
+        let config = data.config();
+        Self {
+            liveness: config.liveness.clone(),
+            offer_group: config.matching.offer_group.as_deref().map(OfferGroup::new),
+            central_net_filter: central_net_filter.cloned(),
+            now: data.now(),
+        }
+    }
+
+    /// Assignment is not checked
+    fn accepts(&self, offer: &OfferObj, providers: &Providers) -> bool {
+        if offer.offer.expiration < self.now {
+            // expired
+            return false;
+        }
+        if !self
+            .liveness
+            .is_alive(providers.last_seen(&offer.offer.provider_id), self.now)
+        {
+            return false;
+        }
+        let name_group = offer
+            .attributes
+            .node_name
+            .split("-")
+            .next()
+            .unwrap_or("N/A");
+
+        //used in integration tests
+        if let Some(group) = self.offer_group.as_ref() {
+            if !group.is_match(&offer.attributes.node_name) {
+                return false;
+            }
+        } else if let Some(central_net_filter) = self.central_net_filter.as_ref() {
+            if !central_net_filter.contains("127.0.0.1") && !central_net_filter.contains(name_group)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn find_demand_mut<'a>(
+    demands: &'a mut Demands,
+    demand_id: &str,
+) -> anyhow::Result<&'a mut DemandObj> {
+    find_demand(demands, demand_id).map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+fn log_pick_time(perf_start: Instant) {
+    if perf_start.elapsed().as_secs_f64() > 0.01 {
+        log::warn!(
+            "Pick offer took too long: {:.2} ms",
+            perf_start.elapsed().as_secs_f64() * 1000.0
+        );
+    } else {
+        log::debug!(
+            "Pick offer took: {:.2} ms",
+            perf_start.elapsed().as_secs_f64() * 1000.0
+        );
+    }
 }
 
 pub async fn local_pick_offer_to_demand(
@@ -104,91 +205,35 @@ pub async fn local_pick_offer_to_demand(
     central_net_filter: Option<&String>,
 ) -> anyhow::Result<bool> {
     let perf_start = Instant::now();
+    let filter = PickFilter::new(&data, central_net_filter);
     {
-        let demand_id = pick_offer_to_demand.demand_id;
-
         let mut lock = data.demands.lock().await;
         let mut offers_lock = data.lock.lock().await;
-        let mut given_lock = data.offers_given_to_node.lock().await;
-
-        let get_demand = match lock.demand_map.contains_key(&demand_id) {
-            true => lock.demand_map.get_mut(&demand_id),
-            false => {
-                let node_id = match NodeId::from_str(&demand_id) {
-                    Ok(id) => id,
-                    Err(_) => {
-                        bail!("Invalid offer ID format or not found");
-                    }
-                };
-                let mut get_demand: Option<&mut DemandObj> = None;
-                for (_, v) in lock.demand_map.iter_mut() {
-                    if v.demand.node_id == node_id {
-                        get_demand = Some(v);
-                        break;
-                    }
-                }
-                get_demand
-            }
-        };
+        let mut allocations = data.allocations.lock().await;
+        let providers = data.providers.lock().await;
 
-        let demand_obj = match get_demand {
-            Some(demand) => demand,
-            None => {
-                bail!("Demand not found");
-            }
-        };
+        let demand_obj = find_demand_mut(&mut lock, &pick_offer_to_demand.demand_id)?;
+        if demand_obj.remaining() == Some(0) {
+            log::debug!("Demand {} reached its target", demand_obj.demand.id);
+            return Ok(false);
+        }
         // most recent, unexpired, and unassigned offer from the collection.
         // The use of newest_one as a baseline timestamp ensures that only the most recent valid
         // offer is chosen during the iteration.
         let mut selected_offer_id = None;
-        let mut newest_one = Utc::now().sub(chrono::Duration::days(365 * 100));
+        let mut newest_one = filter.now.sub(chrono::Duration::days(365 * 100));
+        let criteria = demand_obj.demand.criteria();
         for offer_pair in offers_lock.offer_map.iter_mut() {
             let offer = offer_pair.1;
-            if offer.offer.expiration.naive_utc() < Utc::now().naive_utc() {
-                // expired
-                continue;
-            }
             if offer.requestor_id.is_some() {
                 // already assigned
                 continue;
             }
-            let name_group = offer
-                .attributes
-                .node_name
-                .split("-")
-                .next()
-                .unwrap_or("N/A");
-
-            //used in integration tests
-            let group = env::var("OFFER_GROUP").ok();
-
-            if let Some(group) = group.as_ref() {
-                // treat OFFER_GROUP as a regex pattern; if invalid, fall back to previous contains logic
-                match regex::Regex::new(group.as_str()) {
-                    Ok(re) => {
-                        if !re.is_match(&offer.attributes.node_name) {
-                            continue;
-                        }
-                    }
-                    Err(_) => {
-                        if !offer
-                            .attributes
-                            .node_name
-                            .contains((group.to_string() + "-").as_str())
-                        {
-                            continue;
-                        }
-                    }
-                }
-            } else if let Some(central_net_filter) = central_net_filter.as_ref() {
-                if !central_net_filter.contains("127.0.0.1")
-                    && !central_net_filter.contains(name_group)
-                {
-                    continue;
-                }
+            if !filter.accepts(offer, &providers) || !criteria.accepts(&offer.attributes) {
+                continue;
             }
 
-            if offer.offer.timestamp > newest_one && offer.offer.timestamp < Utc::now() {
+            if offer.offer.timestamp > newest_one && offer.offer.timestamp < filter.now {
                 // new good candidate
                 newest_one = offer.offer.timestamp;
                 selected_offer_id = Some(offer);
@@ -204,28 +249,266 @@ pub async fn local_pick_offer_to_demand(
 
         offer.requestor_id = Some(demand_obj.demand.node_id);
         demand_obj.offer_list.push_back(offer.offer.id.clone());
-        let val = given_lock
-            .get(&demand_obj.demand.node_id.to_string())
-            .cloned();
-        match val {
-            Some(count) => {
-                given_lock.insert(demand_obj.demand.node_id.to_string(), count + 1);
+        let allocation = allocations.allocate(
+            offer,
+            demand_obj.demand.node_id,
+            Some(&demand_obj.demand.id),
+            AllocationSource::Matching,
+            LeaseState::Queued,
+            filter.now,
+        );
+        data.audit.record(AuditEntry::assigned(allocation));
+    }
+    log_pick_time(perf_start);
+    Ok(true)
+}
+
+/// Move an offer waiting in the queue of a lower priority demand to this demand.
+/// The lowest priority demand loses its most recently queued matching offer.
+pub async fn local_preempt_offer_to_demand(
+    data: web::Data<AppState>,
+    pick_offer_to_demand: PickOfferToDemand,
+    central_net_filter: Option<&String>,
+) -> anyhow::Result<bool> {
+    let perf_start = Instant::now();
+    let filter = PickFilter::new(&data, central_net_filter);
+    let matching = data.config().matching.clone();
+    {
+        let mut lock = data.demands.lock().await;
+        let mut offers_lock = data.lock.lock().await;
+        let mut allocations = data.allocations.lock().await;
+        let providers = data.providers.lock().await;
+
+        let demand_obj = find_demand_mut(&mut lock, &pick_offer_to_demand.demand_id)?;
+        if demand_obj.remaining() == Some(0) {
+            return Ok(false);
+        }
+        let demand_id = demand_obj.demand.id.clone();
+        let node_id = demand_obj.demand.node_id;
+        let priority = matching.schedule(&demand_obj.demand).priority;
+        let criteria = demand_obj.demand.criteria();
+
+        // (victim priority, victim demand id, position in its queue)
+        let mut victim: Option<(i32, String, usize)> = None;
+        for other in lock.demand_map.values() {
+            let other_priority = matching.schedule(&other.demand).priority;
+            if other_priority >= priority
+                || victim
+                    .as_ref()
+                    .is_some_and(|(victim_priority, _, _)| *victim_priority <= other_priority)
+            {
+                continue;
             }
-            None => {
-                given_lock.insert(demand_obj.demand.node_id.to_string(), 1);
+            let position = other.offer_list.iter().rposition(|offer_id| {
+                offers_lock.offer_map.get(offer_id).is_some_and(|offer| {
+                    filter.accepts(offer, &providers) && criteria.accepts(&offer.attributes)
+                })
+            });
+            if let Some(position) = position {
+                victim = Some((other_priority, other.demand.id.clone(), position));
             }
         }
-    }
-    if perf_start.elapsed().as_secs_f64() > 0.01 {
-        log::warn!(
-            "Pick offer took too long: {:.2} ms",
-            perf_start.elapsed().as_secs_f64() * 1000.0
-        );
-    } else {
-        log::debug!(
-            "Pick offer took: {:.2} ms",
-            perf_start.elapsed().as_secs_f64() * 1000.0
+        let Some((_, victim_id, position)) = victim else {
+            return Ok(false);
+        };
+
+        let victim_obj = lock.demand_map.get_mut(&victim_id).unwrap();
+        let offer_id = victim_obj.offer_list.remove(position).unwrap();
+        victim_obj.record_withdrawn(&offer_id);
+        if let Some(preempted) = allocations.end(&offer_id, LeaseState::Preempted, filter.now) {
+            data.audit.record(
+                AuditEntry::new(AuditAction::OfferPreempted, filter.now)
+                    .offer(&offer_id)
+                    .demand(&victim_id)
+                    .provider(preempted.provider_id)
+                    .requestor(preempted.requestor_id)
+                    .with_details(serde_json::json!({ "preemptedBy": demand_id })),
+            );
+        }
+
+        if let Some(offer) = offers_lock.offer_map.get_mut(&offer_id) {
+            offer.requestor_id = Some(node_id);
+            let allocation = allocations.allocate(
+                offer,
+                node_id,
+                Some(&demand_id),
+                AllocationSource::Preemption,
+                LeaseState::Queued,
+                filter.now,
+            );
+            data.audit.record(AuditEntry::assigned(allocation));
+        }
+        let demand_obj = lock.demand_map.get_mut(&demand_id).unwrap();
+        demand_obj.offer_list.push_back(offer_id.clone());
+        log::info!(
+            "Offer {} preempted from demand {} for higher priority demand {}",
+            offer_id,
+            victim_id,
+            demand_id
         );
     }
+    log_pick_time(perf_start);
     Ok(true)
 }
+
+#[actix_web::test]
+async fn test_pick_skips_expired_future_and_silent_offers() {
+    use crate::clock::{Clock, MockClock};
+    use crate::state::{test_demand_obj, test_offer_obj, test_state, ProviderLiveness};
+    use std::sync::Arc;
+    use ya_client_model::NodeId;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(now));
+    let data = test_state(clock.clone());
+    {
+        let mut offers = data.lock.lock().await;
+        for offer in [
+            test_offer_obj("stale", now - chrono::Duration::hours(2)),
+            test_offer_obj("future", now + chrono::Duration::minutes(5)),
+        ] {
+            offers.insert(offer);
+        }
+    }
+    let demand = test_demand_obj(
+        "demand",
+        NodeId::default(),
+        now + chrono::Duration::hours(1),
+    );
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert("demand".to_string(), demand);
+    let pick = || PickOfferToDemand {
+        demand_id: "demand".to_string(),
+    };
+    assert!(!local_pick_offer_to_demand(data.clone(), pick(), None)
+        .await
+        .unwrap());
+
+    clock.advance(chrono::Duration::minutes(6));
+    let mut config = (*data.config()).clone();
+    config.liveness.require_heartbeat = true;
+    data.set_config(config);
+    let provider_id = data.lock.lock().await.offer_map["future"].offer.provider_id;
+    let heartbeat = |last_seen| ProviderLiveness {
+        first_seen: now,
+        last_seen,
+        heartbeats: 1,
+    };
+    // four missed beats of 30 seconds
+    data.providers.lock().await.provider_map.insert(
+        provider_id,
+        heartbeat(clock.now() - chrono::Duration::minutes(2)),
+    );
+    assert!(!local_pick_offer_to_demand(data.clone(), pick(), None)
+        .await
+        .unwrap());
+
+    data.providers.lock().await.provider_map.insert(
+        provider_id,
+        heartbeat(clock.now() - chrono::Duration::minutes(1)),
+    );
+    assert!(local_pick_offer_to_demand(data.clone(), pick(), None)
+        .await
+        .unwrap());
+    assert_eq!(
+        data.demands.lock().await.demand_map["demand"].offer_list,
+        ["future".to_string()]
+    );
+}
+
+#[test]
+fn test_offer_group_falls_back_to_name_match() {
+    let group = OfferGroup::new("brick-[0-9]+");
+    assert!(group.is_match("brick-12-provider"));
+    assert!(!group.is_match("wall-12-provider"));
+
+    let group = OfferGroup::new("brick(");
+    assert!(group.is_match("testnet-brick(-provider"));
+    assert!(!group.is_match("brick-provider"));
+}
+
+#[actix_web::test]
+async fn test_preemption_takes_queued_offer_of_lowest_priority_demand() {
+    use crate::config::Preemption;
+    use crate::rest::demand::pick_offers_for_all_demands;
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+    use std::str::FromStr;
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    let mut config = (*data.config()).clone();
+    config.matching.preemption = Preemption::QueuedOffers;
+    data.set_config(config);
+    let demand = |id: &str, node: u8, priority: i32| {
+        let node_id = NodeId::from_str(&format!("0x{:040x}", node)).unwrap();
+        let mut demand = test_demand_obj(id, node_id, now + chrono::Duration::hours(1));
+        demand.demand.central_net_address = Some("127.0.0.1".to_string());
+        demand.demand.priority = Some(priority);
+        demand
+    };
+    // each of the lower priority demands gets one offer while it is alone
+    for (offer_id, demand) in [
+        ("offer-low", demand("low", 1, -1)),
+        ("offer-mid", demand("mid", 2, 0)),
+    ] {
+        data.lock
+            .lock()
+            .await
+            .insert(test_offer_obj(offer_id, now - chrono::Duration::minutes(1)));
+        data.demands
+            .lock()
+            .await
+            .demand_map
+            .insert(demand.demand.id.clone(), demand);
+        pick_offers_for_all_demands(data.clone()).await;
+    }
+    let high = demand("high", 3, 5);
+    let high_node = high.demand.node_id;
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert("high".to_string(), high);
+    pick_offers_for_all_demands(data.clone()).await;
+
+    let demands = data.demands.lock().await;
+    assert_eq!(demands.demand_map["high"].offer_list, ["offer-low"]);
+    assert!(demands.demand_map["low"].offer_list.is_empty());
+    assert_eq!(demands.demand_map["low"].withdrawn_offers, ["offer-low"]);
+    assert_eq!(demands.demand_map["mid"].offer_list, ["offer-mid"]);
+    assert_eq!(
+        data.lock.lock().await.offer_map["offer-low"].requestor_id,
+        Some(high_node)
+    );
+    let allocations = data.allocations.lock().await;
+    let history: Vec<_> = allocations
+        .allocations
+        .iter()
+        .filter(|allocation| allocation.offer_id == "offer-low")
+        .map(|allocation| {
+            (
+                allocation.demand_id.as_deref(),
+                allocation.source,
+                allocation.state,
+            )
+        })
+        .collect();
+    assert_eq!(
+        history,
+        [
+            (
+                Some("low"),
+                AllocationSource::Matching,
+                LeaseState::Preempted
+            ),
+            (
+                Some("high"),
+                AllocationSource::Preemption,
+                LeaseState::Queued
+            ),
+        ]
+    );
+}
@@ -1,17 +1,19 @@
-use crate::rest::demand::TakeOfferFromQueue;
-use crate::state::{AppState, DemandObj};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::rest::demand::{find_demand, TakeOfferFromQueue};
+use crate::rest::error::{ApiError, ApiErrorBody};
+use crate::state::AppState;
 use actix_web::{web, HttpResponse};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use serde_json::{Map, Value};
-use std::str::FromStr;
+use utoipa::ToSchema;
 use ya_client_model::NodeId;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct ModelOffer {
     pub id: String,
     pub properties: String,
     pub constraints: String,
+    #[schema(value_type = String)]
     pub node_id: NodeId,
     // Database information telling if we are the owner of the Offer.
     // None means that we don't have this information yet (for example in case when
@@ -25,79 +27,34 @@ pub struct ModelOffer {
     /// Time when Offer expires; set by Provider.
     pub expiration_ts: NaiveDateTime,
 }
-pub fn flatten(value: Value) -> Map<String, Value> {
-    let mut map = Map::new();
-    flatten_inner(String::new(), &mut map, value);
-    map
-}
-pub const PROPERTY_TAG: &str = "@tag";
-
-fn flatten_inner(prefix: String, result: &mut Map<String, Value>, value: Value) {
-    match value {
-        Value::Object(m) => {
-            if m.is_empty() {
-                // Important to keep this value in case we want to un-flatten later
-                // and get the same structure.
-                result.insert(prefix, Value::Object(Map::new()));
-            } else {
-                for (k, v) in m.into_iter() {
-                    if k.as_str() == PROPERTY_TAG {
-                        result.insert(prefix.clone(), v);
-                        continue;
-                    }
-                    let p = match prefix.is_empty() {
-                        true => k,
-                        _ => format!("{}.{}", prefix, k),
-                    };
-                    flatten_inner(p, result, v);
-                }
-            }
-        }
-        v => {
-            result.insert(prefix, v);
-        }
-    }
-}
-
-pub async fn take_offer_from_queue(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<TakeOfferFromQueue>(&body);
-    let take_offer = match decoded {
-        Ok(filer) => filer,
-        Err(e) => {
-            log::error!("Error decoding take offer from queue: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/take-from-queue",
+    tag = "requestor",
+    request_body(content = TakeOfferFromQueue, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offers taken from the demand queue", body = Vec<ModelOffer>),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found", body = ApiErrorBody),
+        (status = 409, description = "Node id given for a node with several demands", body = ApiErrorBody),
+        (status = 422, description = "Demand id is neither a demand nor a node id", body = ApiErrorBody),
+    )
+)]
+pub async fn take_offer_from_queue(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let take_offer = serde_json::from_str::<TakeOfferFromQueue>(&body).map_err(|e| {
+        log::error!("Error decoding take offer from queue: {}", e);
+        ApiError::invalid_format(e)
+    })?;
     let demand_id = take_offer.demand_id;
 
     let mut lock = data.demands.lock().await;
     let offers_lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
 
-    let get_demand = match lock.demand_map.contains_key(&demand_id) {
-        true => lock.demand_map.get_mut(&demand_id),
-        false => {
-            let node_id = match NodeId::from_str(&demand_id) {
-                Ok(id) => id,
-                Err(_) => {
-                    return HttpResponse::BadRequest().body("Invalid offer ID format or not found");
-                }
-            };
-            let mut get_demand: Option<&mut DemandObj> = None;
-            for (_, v) in lock.demand_map.iter_mut() {
-                if v.demand.node_id == node_id {
-                    get_demand = Some(v);
-                    break;
-                }
-            }
-            get_demand
-        }
-    };
-    let demand_obj = match get_demand {
-        Some(demand) => demand,
-        None => {
-            return HttpResponse::NotFound().body("Demand not found");
-        }
-    };
+    let demand_obj = find_demand(&mut lock, &demand_id)?;
     let mut resp = Vec::new();
     let limit_size = take_offer.take_at_once.unwrap_or(50);
     loop {
@@ -109,12 +66,11 @@ pub async fn take_offer_from_queue(data: web::Data<AppState>, body: String) -> H
                 let offer = offers_lock.offer_map.get(&offer_id);
                 match offer {
                     Some(offer) => {
+                        allocations.mark_taken(&offer_id);
                         let converted_offer = ModelOffer {
                             id: offer.offer.id.clone(),
-                            properties: serde_json::to_string(&flatten(
-                                serde_json::to_value(offer.offer.properties.clone()).unwrap(),
-                            ))
-                            .unwrap(),
+                            properties: serde_json::to_string(&offer.offer.properties.to_flat())
+                                .unwrap(),
                             constraints: offer.offer.constraints.clone(),
                             node_id: offer.offer.provider_id,
                             owned: None,
@@ -125,16 +81,83 @@ pub async fn take_offer_from_queue(data: web::Data<AppState>, body: String) -> H
 
                         resp.push(converted_offer);
                     }
-                    None => break,
+                    None => {
+                        // should not happen since removals purge the queues, skip it anyway
+                        log::warn!(
+                            "Queued offer {} of demand {} no longer exists",
+                            offer_id,
+                            demand_obj.demand.id
+                        );
+                    }
                 }
             }
             None => break,
         }
     }
+    demand_obj.taken += resp.len() as u64;
+    if !resp.is_empty() {
+        let offer_ids: Vec<&String> = resp.iter().map(|offer| &offer.id).collect();
+        data.audit.record(
+            AuditEntry::new(AuditAction::OffersTaken, data.now())
+                .demand(&demand_obj.demand.id)
+                .requestor(demand_obj.demand.node_id)
+                .with_details(serde_json::json!({ "offerIds": offer_ids })),
+        );
+    }
     log::info!(
         "Taken {} offers from queue for demand {}",
         resp.len(),
         demand_obj.demand.id
     );
-    HttpResponse::Ok().json(resp)
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+#[actix_web::test]
+async fn test_node_id_with_several_demands_is_ambiguous() {
+    use crate::rest::error::ErrorCode;
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+
+    let (data, now) = test_state_at_fixture_time();
+    let node_id = NodeId::default();
+    data.lock.lock().await.insert(test_offer_obj("offer", now));
+    {
+        let mut demands = data.demands.lock().await;
+        for id in ["first", "second"] {
+            let mut demand = test_demand_obj(id, node_id, now + chrono::Duration::hours(1));
+            if id == "second" {
+                demand.offer_list.push_back("offer".to_string());
+            }
+            demands.demand_map.insert(id.to_string(), demand);
+        }
+    }
+    let take = |demand_id: String| {
+        take_offer_from_queue(
+            data.clone(),
+            serde_json::json!({ "demandId": demand_id }).to_string(),
+        )
+    };
+
+    let error = take(node_id.to_string()).await.unwrap_err();
+    assert_eq!(error.code, ErrorCode::AmbiguousDemand);
+    assert_eq!(
+        error.details.unwrap()["candidateDemandIds"],
+        serde_json::json!(["first", "second"])
+    );
+    assert_eq!(
+        data.demands.lock().await.demand_map["second"]
+            .offer_list
+            .len(),
+        1
+    );
+
+    let response = take("second".to_string()).await.unwrap();
+    let offers: Vec<ModelOffer> = serde_json::from_slice(
+        &actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(offers.len(), 1);
+    assert_eq!(offers[0].id, "offer");
+    assert_eq!(data.demands.lock().await.demand_map["second"].taken, 1);
 }
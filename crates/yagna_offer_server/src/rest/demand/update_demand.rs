@@ -0,0 +1,159 @@
+use crate::audit::{AuditAction, AuditEntry};
+use crate::model::demand::base::{DemandRefresh, DemandSubscription, DemandUpdate};
+use crate::rest::demand::{validate_max_per_tick, validate_weight};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+fn demand_not_found(demand_id: &str) -> ApiError {
+    ApiError::new(ErrorCode::DemandNotFound, "Demand not found")
+        .with_details(serde_json::json!({ "demandId": demand_id }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/refresh",
+    tag = "requestor",
+    request_body(content = DemandRefresh, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Demand expiration extended", body = DemandSubscription),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found", body = ApiErrorBody),
+        (status = 422, description = "Expiration is in the past", body = ApiErrorBody),
+    )
+)]
+pub async fn demand_refresh(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let refresh = serde_json::from_str::<DemandRefresh>(&body).map_err(|e| {
+        log::error!("Error decoding demand refresh: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    if refresh.expiration_ts.and_utc() <= data.now() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidExpiration,
+            "Expiration has to be in the future",
+        )
+        .with_details(serde_json::json!({
+            "demandId": refresh.demand_id,
+            "expirationTs": refresh.expiration_ts,
+        })));
+    }
+
+    let mut lock = data.demands.lock().await;
+    let demand_obj = lock
+        .demand_map
+        .get_mut(&refresh.demand_id)
+        .ok_or_else(|| demand_not_found(&refresh.demand_id))?;
+    demand_obj.demand.expiration_ts = refresh.expiration_ts;
+    data.audit.record(
+        AuditEntry::new(AuditAction::DemandRefreshed, data.now())
+            .demand(&refresh.demand_id)
+            .requestor(demand_obj.demand.node_id)
+            .with_details(serde_json::json!({ "expirationTs": refresh.expiration_ts })),
+    );
+    log::info!(
+        "Demand {} refreshed until {}",
+        refresh.demand_id,
+        refresh.expiration_ts
+    );
+    Ok(HttpResponse::Ok().json(&demand_obj.demand))
+}
+
+#[utoipa::path(
+    post,
+    path = "/requestor/demand/update",
+    tag = "requestor",
+    request_body(content = DemandUpdate, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Demand updated", body = DemandSubscription),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "Demand not found", body = ApiErrorBody),
+    )
+)]
+pub async fn demand_update(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let update = serde_json::from_str::<DemandUpdate>(&body).map_err(|e| {
+        log::error!("Error decoding demand update: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    let details = serde_json::to_value(&update).unwrap_or_default();
+    validate_weight(update.weight)?;
+    validate_max_per_tick(update.max_per_tick)?;
+
+    let mut lock = data.demands.lock().await;
+    let demand_obj = lock
+        .demand_map
+        .get_mut(&update.demand_id)
+        .ok_or_else(|| demand_not_found(&update.demand_id))?;
+    if let Some(properties) = update.properties {
+        demand_obj.demand.properties = properties;
+    }
+    if let Some(constraints) = update.constraints {
+        demand_obj.demand.constraints = constraints;
+    }
+    if let Some(central_net_address) = update.central_net_address {
+        demand_obj.demand.central_net_address = Some(central_net_address);
+    }
+    if let Some(target_count) = update.target_count {
+        demand_obj.demand.target_count = Some(target_count);
+    }
+    if let Some(max_per_tick) = update.max_per_tick {
+        demand_obj.demand.max_per_tick = Some(max_per_tick);
+    }
+    if let Some(priority) = update.priority {
+        demand_obj.demand.priority = Some(priority);
+    }
+    if let Some(weight) = update.weight {
+        demand_obj.demand.weight = Some(weight);
+    }
+    if let Some(requirements) = update.requirements {
+        demand_obj.demand.requirements = Some(requirements);
+    }
+    if let Some(payment_platforms) = update.payment_platforms {
+        demand_obj.demand.payment_platforms = Some(payment_platforms);
+    }
+    data.audit.record(
+        AuditEntry::new(AuditAction::DemandUpdated, data.now())
+            .demand(&update.demand_id)
+            .requestor(demand_obj.demand.node_id)
+            .with_details(details),
+    );
+    log::info!("Demand {} updated", update.demand_id);
+    Ok(HttpResponse::Ok().json(&demand_obj.demand))
+}
+
+#[actix_web::test]
+async fn test_zero_max_per_tick_rejected() {
+    use crate::rest::demand::demand_new::demand_new;
+    use crate::state::{test_demand_obj, test_state_at_fixture_time};
+    use ya_client_model::NodeId;
+
+    let (data, now) = test_state_at_fixture_time();
+    let mut demand = test_demand_obj("demand", NodeId::default(), now).demand;
+    demand.max_per_tick = Some(0);
+    let refused = demand_new(data.clone(), serde_json::to_string(&demand).unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code, ErrorCode::InvalidFormat);
+
+    demand.max_per_tick = Some(2);
+    demand_new(data.clone(), serde_json::to_string(&demand).unwrap())
+        .await
+        .unwrap();
+    let update = |max_per_tick: u64| {
+        serde_json::json!({ "demandId": "demand", "maxPerTick": max_per_tick }).to_string()
+    };
+    let refused = demand_update(data.clone(), update(0)).await.unwrap_err();
+    assert_eq!(refused.code, ErrorCode::InvalidFormat);
+    demand_update(data.clone(), update(3)).await.unwrap();
+    assert_eq!(
+        data.demands.lock().await.demand_map["demand"]
+            .demand
+            .max_per_tick,
+        Some(3)
+    );
+}
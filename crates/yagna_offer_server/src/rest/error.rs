@@ -0,0 +1,211 @@
+use crate::state::AppState;
+use actix_web::body::EitherBody;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Stable, machine readable error codes returned in the `code` field of error responses.
+/// Clients should match on these instead of the human readable message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidFormat,
+    InvalidDemandId,
+    InvalidExpiration,
+    OfferNotFound,
+    DemandNotFound,
+    NoAvailableOffers,
+    OfferAlreadyTaken,
+    OfferNotAssigned,
+    DemandAlreadyExists,
+    /// A node id was given where the node has several demands
+    AmbiguousDemand,
+    TestAlreadyStarted,
+    TestAlreadyFinished,
+    TestNotStarted,
+    TestNotFinished,
+    TestGroupsExceeded,
+    MirrorNotConfigured,
+    MirrorSyncFailed,
+    AuditLogUnavailable,
+    ShuttingDown,
+    JobNotFound,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidFormat => StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidDemandId
+            | ErrorCode::InvalidExpiration
+            | ErrorCode::TestGroupsExceeded => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::OfferNotFound
+            | ErrorCode::DemandNotFound
+            | ErrorCode::NoAvailableOffers
+            | ErrorCode::JobNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::OfferAlreadyTaken
+            | ErrorCode::OfferNotAssigned
+            | ErrorCode::DemandAlreadyExists
+            | ErrorCode::AmbiguousDemand
+            | ErrorCode::TestAlreadyStarted
+            | ErrorCode::TestAlreadyFinished
+            | ErrorCode::TestNotStarted
+            | ErrorCode::TestNotFinished
+            | ErrorCode::MirrorNotConfigured => StatusCode::CONFLICT,
+            ErrorCode::MirrorSyncFailed => StatusCode::BAD_GATEWAY,
+            ErrorCode::AuditLogUnavailable => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Status code used before structured errors were introduced
+    pub fn legacy_status(&self) -> StatusCode {
+        match self {
+            ErrorCode::OfferNotFound | ErrorCode::DemandNotFound | ErrorCode::NoAvailableOffers => {
+                StatusCode::NOT_FOUND
+            }
+            ErrorCode::OfferAlreadyTaken | ErrorCode::DemandAlreadyExists => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<Value>,
+    // some endpoints answered with a different status than the one implied by the code
+    legacy_status: Option<StatusCode>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+            legacy_status: None,
+        }
+    }
+
+    pub fn invalid_format(error: impl fmt::Display) -> Self {
+        let error = error.to_string();
+        Self::new(
+            ErrorCode::InvalidFormat,
+            format!("Invalid format {}", error),
+        )
+        .with_details(serde_json::json!({ "error": error }))
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_legacy_status(mut self, status: StatusCode) -> Self {
+        self.legacy_status = Some(status);
+        self
+    }
+
+    pub fn body(&self) -> ApiErrorBody {
+        ApiErrorBody {
+            code: self.code,
+            message: self.message.clone(),
+            details: self.details.clone(),
+        }
+    }
+
+    /// Plain text with the status code used by older versions of the server
+    pub fn legacy_response(&self) -> HttpResponse {
+        HttpResponse::build(self.legacy_status.unwrap_or(self.code.legacy_status()))
+            .body(self.message.clone())
+    }
+}
+
+/// When `legacy_error_responses` is enabled in the configuration of the app, API errors are
+/// rewritten to their legacy form. Used with `App::wrap_fn`.
+pub fn legacy_error_response<B>(res: ServiceResponse<B>) -> ServiceResponse<EitherBody<B>> {
+    let legacy = res
+        .request()
+        .app_data::<web::Data<AppState>>()
+        .is_some_and(|data| data.config().legacy_error_responses);
+    let api_error = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<ApiError>())
+        .filter(|_| legacy)
+        .map(ApiError::legacy_response);
+    match api_error {
+        Some(response) => res.into_response(response).map_into_right_body(),
+        None => res.map_into_left_body(),
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+#[actix_web::test]
+async fn test_structured_and_legacy_error_responses() {
+    use crate::state::test_state_at_fixture_time;
+    use actix_web::dev::Service;
+    use actix_web::{test, App};
+
+    let (data, _) = test_state_at_fixture_time();
+    let app = test::init_service(
+        App::new()
+            .app_data(data.clone())
+            .wrap_fn(|req, srv| {
+                let response = srv.call(req);
+                async move { Ok(legacy_error_response(response.await?)) }
+            })
+            .route(
+                "/take",
+                web::post().to(|| async {
+                    Err::<HttpResponse, _>(
+                        ApiError::new(ErrorCode::NoAvailableOffers, "No available offers")
+                            .with_legacy_status(StatusCode::OK),
+                    )
+                }),
+            ),
+    )
+    .await;
+    let take = || test::TestRequest::post().uri("/take").to_request();
+
+    let response = test::call_service(&app, take()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: ApiErrorBody = test::read_body_json(response).await;
+    assert_eq!(body.code, ErrorCode::NoAvailableOffers);
+
+    let mut config = (*data.config()).clone();
+    config.legacy_error_responses = true;
+    data.set_config(config);
+    let response = test::call_service(&app, take()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(test::read_body(response).await, "No available offers");
+}
@@ -1,3 +1,9 @@
+pub mod admin;
+pub mod allocations;
 pub mod demand;
+pub mod error;
 pub mod offer;
+pub mod openapi;
+pub mod provider;
+pub mod routes;
 pub mod test;
@@ -1,17 +1,96 @@
+use crate::clock::checked_sub_secs;
+use crate::offers::{remove_offers, OfferRemoval};
 use crate::state::AppState;
 use actix_web::{web, HttpResponse};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 pub async fn clean_old_offers(data: web::Data<AppState>) {
+    let now = data.now();
+    let expired_before = checked_sub_secs(now, data.config().cleanup.offer_expiry_grace_secs)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let mut demands = data.demands.lock().await;
     let mut lock = data.lock.lock().await;
-    let now = Utc::now();
-    lock.offer_map.retain(|_id, offer_obj| {
-        offer_obj.offer.expiration > (now - chrono::Duration::minutes(60))
-    });
+    let mut allocations = data.allocations.lock().await;
+    let expired: Vec<String> = lock
+        .offer_map
+        .values()
+        .filter(|offer_obj| offer_obj.offer.expiration <= expired_before)
+        .map(|offer_obj| offer_obj.offer.id.clone())
+        .collect();
+    let removed = remove_offers(
+        &mut lock,
+        &mut demands,
+        &mut allocations,
+        &expired,
+        OfferRemoval::Expired,
+        now,
+    );
+    data.audit.offers_removed(&removed, OfferRemoval::Expired);
+    if !removed.is_empty() {
+        log::info!("Removed {} expired offers", removed.len());
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/offers/clear",
+    tag = "offers",
+    responses((status = 200, description = "All offers deleted", body = String))
+)]
 pub async fn delete_all_offers(data: web::Data<AppState>) -> HttpResponse {
+    let mut demands = data.demands.lock().await;
     let mut lock = data.lock.lock().await;
-    lock.offer_map.clear();
+    let mut allocations = data.allocations.lock().await;
+    let all: Vec<String> = lock.offer_map.keys().cloned().collect();
+    let removed = remove_offers(
+        &mut lock,
+        &mut demands,
+        &mut allocations,
+        &all,
+        OfferRemoval::Cleared,
+        data.now(),
+    );
+    data.audit.offers_removed(&removed, OfferRemoval::Cleared);
     HttpResponse::Ok().body("All offers deleted successfully")
 }
+
+#[actix_web::test]
+async fn test_expired_offers_removed_after_grace() {
+    use crate::allocations::{AllocationSource, LeaseState};
+    use crate::clock::{Clock, MockClock};
+    use crate::state::{test_offer_obj, test_state};
+    use std::sync::Arc;
+    use ya_client_model::NodeId;
+
+    let published = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(published));
+    let data = test_state(clock.clone());
+    let mut offer = test_offer_obj("offer", published);
+    offer.requestor_id = Some(NodeId::default());
+    let expiration = offer.offer.expiration;
+    data.allocations.lock().await.allocate(
+        &offer,
+        NodeId::default(),
+        None,
+        AllocationSource::OfferTake,
+        LeaseState::Taken,
+        published,
+    );
+    data.lock
+        .lock()
+        .await
+        .offer_map
+        .insert("offer".to_string(), offer);
+
+    // expired, but within the default grace of an hour
+    clock.set(expiration + chrono::Duration::minutes(59));
+    clean_old_offers(data.clone()).await;
+    assert_eq!(data.lock.lock().await.offer_map.len(), 1);
+
+    clock.set(expiration + chrono::Duration::minutes(60));
+    clean_old_offers(data.clone()).await;
+    assert!(data.lock.lock().await.offer_map.is_empty());
+    let allocations = data.allocations.lock().await;
+    assert_eq!(allocations.allocations[0].state, LeaseState::Expired);
+    assert_eq!(allocations.allocations[0].ended_at, Some(clock.now()));
+}
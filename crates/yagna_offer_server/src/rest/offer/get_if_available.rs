@@ -0,0 +1,227 @@
+use crate::allocations::{AllocationSource, LeaseState};
+use crate::audit::AuditEntry;
+use crate::health::ensure_accepting_assignments;
+use crate::model::offer::attributes::{ResourceRequirements, RuntimeSpec};
+use crate::model::offer::base::GolemBaseOffer;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FilterAttributes {
+    ///for which requestor the offer is being requested
+    #[schema(value_type = String)]
+    pub requestor_id: NodeId,
+
+    pub exe_name: Option<String>,
+    pub cpu_threads_min: Option<u32>,
+    pub cpu_threads_max: Option<u32>,
+    pub provider_group_min: Option<u32>,
+    pub provider_group_max: Option<u32>,
+    pub id_group_min: Option<u32>,
+    pub id_group_max: Option<u32>,
+    #[schema(value_type = Option<String>)]
+    pub node_id: Option<NodeId>,
+    pub subnet: Option<String>,
+    pub cpu_architecture: Option<String>,
+    pub cpu_cores_min: Option<u32>,
+    pub memory_gib_min: Option<f64>,
+    pub storage_gib_min: Option<f64>,
+    pub gpu_count_min: Option<u32>,
+    /// Video memory each of the `gpu_count_min` GPUs needs
+    pub gpu_memory_gib_min: Option<f64>,
+    /// Part of the GPU model name, case insensitive
+    pub gpu_model: Option<String>,
+    /// Runtime name optionally followed by a semver range, e.g. `ya-runtime-vm >=0.4`
+    #[schema(value_type = Option<String>)]
+    pub runtime: Option<RuntimeSpec>,
+    /// Capabilities the provider has to enable, e.g. `multi-activity`
+    pub capabilities: Option<Vec<String>>,
+    /// Offer has to accept one of these payment platforms
+    pub payment_platforms: Option<Vec<String>>,
+}
+
+impl FilterAttributes {
+    pub fn requirements(&self) -> ResourceRequirements {
+        ResourceRequirements {
+            cpu_cores_min: self.cpu_cores_min,
+            memory_gib_min: self.memory_gib_min,
+            storage_gib_min: self.storage_gib_min,
+            gpu_count_min: self.gpu_count_min,
+            gpu_memory_gib_min: self.gpu_memory_gib_min,
+            gpu_model: self.gpu_model.clone(),
+            runtime: self.runtime.clone(),
+            capabilities: self.capabilities.clone(),
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/offer/take",
+    tag = "offers",
+    request_body(content = FilterAttributes, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Matching offer assigned to the requestor", body = GolemBaseOffer),
+        (status = 400, description = "Invalid filter format", body = ApiErrorBody),
+        (status = 404, description = "No available offers", body = ApiErrorBody),
+        (status = 503, description = "Server is shutting down", body = ApiErrorBody),
+    )
+)]
+pub async fn get_if_available(
+    data: web::Data<AppState>,
+    item: String,
+) -> Result<HttpResponse, ApiError> {
+    let filer = serde_json::from_str::<FilterAttributes>(&item).map_err(|e| {
+        log::error!("Error decoding filter: {}", e);
+        ApiError::new(
+            ErrorCode::InvalidFormat,
+            format!("Invalid filter format {}", e),
+        )
+        .with_details(serde_json::json!({ "error": e.to_string() }))
+    })?;
+    ensure_accepting_assignments(&data)?;
+    let requirements = filer.requirements();
+    let liveness = data.config().liveness.clone();
+    let now = data.now();
+    let mut lock = data.lock.lock().await;
+    // groups filtered on have to be those of the current epoch
+    data.grouping.regroup(&mut lock, now);
+    let mut allocations = data.allocations.lock().await;
+    let providers = data.providers.lock().await;
+    for (_id, offer_obj) in lock.offer_map.iter_mut() {
+        if !liveness.is_alive(providers.last_seen(&offer_obj.offer.provider_id), now) {
+            continue;
+        }
+        if let Some(filter_exe_name) = &filer.exe_name {
+            if &offer_obj.attributes.exe_name != filter_exe_name {
+                continue;
+            }
+        }
+        if let Some(filter_cpu_threads_min) = filer.cpu_threads_min {
+            if offer_obj.attributes.cpu_threads < filter_cpu_threads_min {
+                continue;
+            }
+        }
+        if let Some(filter_cpu_threads_max) = filer.cpu_threads_max {
+            if offer_obj.attributes.cpu_threads > filter_cpu_threads_max {
+                continue;
+            }
+        }
+        if let Some(filter_node_id) = &filer.node_id {
+            if &offer_obj.offer.provider_id != filter_node_id {
+                continue;
+            }
+        }
+        if let Some(filter_subnet) = &filer.subnet {
+            if &offer_obj.attributes.subnet != filter_subnet {
+                continue;
+            }
+        }
+        if let Some(filter_provider_group_min) = filer.provider_group_min {
+            if offer_obj.attributes.node_id_group < filter_provider_group_min {
+                continue;
+            }
+        }
+        if let Some(filter_provider_group_max) = filer.provider_group_max {
+            if offer_obj.attributes.node_id_group > filter_provider_group_max {
+                continue;
+            }
+        }
+        if let Some(filter_id_group_min) = filer.id_group_min {
+            if offer_obj.attributes.offer_id_group < filter_id_group_min {
+                continue;
+            }
+        }
+        if let Some(filter_id_group_max) = filer.id_group_max {
+            if offer_obj.attributes.offer_id_group > filter_id_group_max {
+                continue;
+            }
+        }
+        if let Some(filter_cpu_architecture) = &filer.cpu_architecture {
+            if &offer_obj.attributes.cpu_architecture != filter_cpu_architecture {
+                continue;
+            }
+        }
+        if !offer_obj.attributes.meets(&requirements) {
+            continue;
+        }
+        if let Some(payment_platforms) = &filer.payment_platforms {
+            if !offer_obj
+                .attributes
+                .payment_platforms
+                .iter()
+                .any(|platform| payment_platforms.contains(platform))
+            {
+                continue;
+            }
+        }
+
+        if offer_obj.requestor_id.is_none() {
+            offer_obj.requestor_id = Some(filer.requestor_id);
+            let allocation = allocations.allocate(
+                offer_obj,
+                filer.requestor_id,
+                None,
+                AllocationSource::OfferTake,
+                LeaseState::Taken,
+                now,
+            );
+            data.audit.record(AuditEntry::assigned(allocation));
+            let offer = &offer_obj.offer;
+            return Ok(HttpResponse::Ok().json(offer));
+        }
+    }
+    // older clients expect 200 with a text body when nothing matches
+    Err(
+        ApiError::new(ErrorCode::NoAvailableOffers, "No available offers")
+            .with_legacy_status(StatusCode::OK),
+    )
+}
+
+#[actix_web::test]
+async fn test_filter_attributes() {
+    use crate::state::{test_offer_obj, test_state_at_fixture_time};
+    use actix_web::ResponseError;
+
+    let (data, now) = test_state_at_fixture_time();
+    let offer = test_offer_obj("offer", now);
+    let attributes = offer.attributes.clone();
+    data.lock.lock().await.insert(offer);
+    let requestor_id = NodeId::default();
+    let take = |filter: serde_json::Value| {
+        let mut filter = filter;
+        filter["requestor_id"] = serde_json::json!(requestor_id);
+        get_if_available(data.clone(), filter.to_string())
+    };
+
+    for filter in [
+        serde_json::json!({ "exe_name": "other" }),
+        serde_json::json!({ "cpu_threads_min": attributes.cpu_threads + 1 }),
+        serde_json::json!({ "subnet": format!("{}-other", attributes.subnet) }),
+    ] {
+        let error = take(filter.clone()).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::NoAvailableOffers, "{}", filter);
+        // a miss used to be 200 with a text body, that stays behind legacy_error_responses
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    let filter = serde_json::json!({
+        "exe_name": attributes.exe_name,
+        "cpu_threads_min": attributes.cpu_threads,
+        "cpu_threads_max": attributes.cpu_threads,
+        "subnet": attributes.subnet,
+    });
+    assert!(take(filter.clone()).await.is_ok());
+    assert_eq!(
+        data.lock.lock().await.offer_map["offer"].requestor_id,
+        Some(requestor_id)
+    );
+    // the offer is assigned now
+    let error = take(filter).await.unwrap_err();
+    assert_eq!(error.code, ErrorCode::NoAvailableOffers);
+}
@@ -1,28 +1,115 @@
+use crate::rest::error::{ApiError, ApiErrorBody};
+use crate::rest::provider::ProviderLivenessInfo;
 use crate::state::{AppState, OfferObj};
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-pub async fn list_offers(data: web::Data<AppState>) -> impl Responder {
-    let lock = data.lock.lock().await;
-    let offers: Vec<&OfferObj> = lock.offer_map.values().collect();
-    HttpResponse::Ok().json(offers)
+/// Served by every matcher, mirrors download the offers of their source from here
+pub const OFFER_LIST_PATH: &str = "/offers/list";
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OfferListEntry {
+    #[serde(flatten)]
+    pub offer: OfferObj,
+    /// Missing when listed by an older server
+    #[serde(default)]
+    pub liveness: Option<ProviderLivenessInfo>,
 }
 
-pub async fn list_taken_offers(data: web::Data<AppState>) -> impl Responder {
-    let lock = data.lock.lock().await;
-    let offers: Vec<&OfferObj> = lock
-        .offer_map
-        .values()
-        .filter(|offer_obj| offer_obj.requestor_id.is_some())
-        .collect();
-    HttpResponse::Ok().json(offers)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+pub struct OfferListQuery {
+    /// Only offers accepting this payment platform, e.g. `erc20-hoodi-tglm`
+    pub payment_platform: Option<String>,
+}
+
+impl OfferListQuery {
+    pub fn matches(&self, offer_obj: &OfferObj) -> bool {
+        self.payment_platform
+            .as_ref()
+            .map(|platform| offer_obj.attributes.payment_platforms.contains(platform))
+            .unwrap_or(true)
+    }
 }
 
-pub async fn list_available_offers(data: web::Data<AppState>) -> impl Responder {
+async fn list_offers_with_liveness(
+    data: web::Data<AppState>,
+    req: &HttpRequest,
+    filter: impl Fn(&OfferObj) -> bool,
+) -> Result<HttpResponse, ApiError> {
+    let query = web::Query::<OfferListQuery>::from_query(req.query_string())
+        .map_err(ApiError::invalid_format)?
+        .into_inner();
+    let liveness = data.config().liveness.clone();
+    let now = data.now();
     let lock = data.lock.lock().await;
-    let offers: Vec<&OfferObj> = lock
+    let providers = data.providers.lock().await;
+    let offers: Vec<OfferListEntry> = lock
         .offer_map
         .values()
-        .filter(|offer_obj| offer_obj.requestor_id.is_none())
+        .filter(|offer_obj| filter(offer_obj) && query.matches(offer_obj))
+        .map(|offer_obj| OfferListEntry {
+            offer: offer_obj.clone(),
+            liveness: Some(ProviderLivenessInfo::new(
+                &liveness,
+                &providers,
+                &offer_obj.offer.provider_id,
+                now,
+            )),
+        })
         .collect();
-    HttpResponse::Ok().json(offers)
+    Ok(HttpResponse::Ok().json(offers))
+}
+
+#[utoipa::path(
+    get,
+    path = "/offers/list",
+    tag = "offers",
+    params(OfferListQuery),
+    responses(
+        (status = 200, description = "All offers", body = Vec<OfferListEntry>),
+        (status = 400, description = "Invalid query", body = ApiErrorBody),
+    )
+)]
+pub async fn list_offers(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    list_offers_with_liveness(data, &req, |_| true).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/offers/list/taken",
+    tag = "offers",
+    params(OfferListQuery),
+    responses(
+        (status = 200, description = "Offers assigned to a requestor", body = Vec<OfferListEntry>),
+        (status = 400, description = "Invalid query", body = ApiErrorBody),
+    )
+)]
+pub async fn list_taken_offers(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    list_offers_with_liveness(data, &req, |offer_obj| offer_obj.requestor_id.is_some()).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/offers/list/available",
+    tag = "offers",
+    params(OfferListQuery),
+    responses(
+        (status = 200, description = "Offers not assigned yet", body = Vec<OfferListEntry>),
+        (status = 400, description = "Invalid query", body = ApiErrorBody),
+    )
+)]
+pub async fn list_available_offers(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    list_offers_with_liveness(data, &req, |offer_obj| offer_obj.requestor_id.is_none()).await
 }
@@ -1,3 +1,5 @@
 pub mod clean_old_offers;
+pub mod get_if_available;
 pub mod list_offers;
 pub mod push_offer;
+pub mod unsubscribe_offer;
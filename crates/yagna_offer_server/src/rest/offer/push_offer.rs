@@ -1,33 +1,56 @@
 use crate::model::offer::attributes::OfferFlatAttributes;
 use crate::model::offer::base::GolemBaseOffer;
+use crate::offers::{insert_offer, OfferInsertResult};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
 use crate::state::{AppState, OfferObj};
-use actix_web::{web, HttpResponse, Responder};
-use chrono::Utc;
+use actix_web::{web, HttpResponse};
 
-pub async fn push_offer(data: web::Data<AppState>, item: String) -> impl Responder {
-    let decode = serde_json::from_str::<GolemBaseOffer>(&item);
-    let offer = match decode {
-        Ok(offer) => offer,
-        Err(e) => {
-            log::error!("Error decoding offer: {}", e);
-            return HttpResponse::BadRequest().body("Invalid offer format");
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/provider/offer/new",
+    tag = "provider",
+    request_body(content = GolemBaseOffer, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offer added, replaced an older offer of the provider, or was ignored", body = OfferInsertResult),
+        (status = 400, description = "Invalid offer format", body = ApiErrorBody),
+    )
+)]
+pub async fn push_offer(data: web::Data<AppState>, item: String) -> Result<HttpResponse, ApiError> {
+    let offer = serde_json::from_str::<GolemBaseOffer>(&item).map_err(|e| {
+        log::error!("Error decoding offer: {}", e);
+        let missing = serde_json::from_str(&item)
+            .map(|value| GolemBaseOffer::missing_fields(&value))
+            .unwrap_or_default();
+        let message = match missing.is_empty() {
+            true => "Invalid offer format".to_string(),
+            false => format!("Invalid offer format, missing {}", missing.join(", ")),
+        };
+        ApiError::new(ErrorCode::InvalidFormat, message).with_details(serde_json::json!({
+            "error": e.to_string(),
+            "missingFields": missing,
+        }))
+    })?;
 
+    let policy = data.config().matching.replaced_offer_assignment;
+    let now = data.now();
+    let mut demands = data.demands.lock().await;
     let mut lock = data.lock.lock().await;
-    if lock.offer_map.contains_key(&offer.id) {
-        let id = &offer.id;
-        return HttpResponse::Ok().body(format!("Offer {id} already registered"));
-    }
-    let attributes = OfferFlatAttributes::from_gbo(&offer);
-    lock.offer_map.insert(
-        offer.id.clone(),
+    let mut allocations = data.allocations.lock().await;
+    let attributes = OfferFlatAttributes::from_gbo(&offer, &data.grouping, now);
+    let provider_id = offer.provider_id;
+    let result = insert_offer(
+        &mut lock,
+        &mut demands,
+        &mut allocations,
         OfferObj {
             offer,
-            pushed_at: Utc::now(),
+            pushed_at: now,
             requestor_id: None,
             attributes,
         },
+        policy,
+        now,
     );
-    HttpResponse::Ok().body("Offer added to the queue")
+    data.audit.offer_inserted(&result, provider_id);
+    Ok(HttpResponse::Ok().json(result))
 }
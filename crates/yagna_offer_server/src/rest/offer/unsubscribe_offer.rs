@@ -0,0 +1,170 @@
+use crate::offers::{remove_offer, OfferRemoval};
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+/// Exactly one of `offerId` and `providerId` has to be given
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferUnsubscribe {
+    pub offer_id: Option<String>,
+    /// Remove all offers of this provider
+    #[schema(value_type = Option<String>)]
+    pub provider_id: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferUnsubscribeResult {
+    pub removed_offer_ids: Vec<String>,
+    /// Requestors the removed offers were assigned to
+    #[schema(value_type = Vec<String>)]
+    pub notified_requestors: Vec<NodeId>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/provider/offer/unsubscribe",
+    tag = "provider",
+    request_body(content = OfferUnsubscribe, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Offers removed", body = OfferUnsubscribeResult),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 404, description = "No matching offer", body = ApiErrorBody),
+    )
+)]
+pub async fn unsubscribe_offer(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let unsubscribe = serde_json::from_str::<OfferUnsubscribe>(&body).map_err(|e| {
+        log::error!("Error decoding offer unsubscribe: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+
+    let mut demands = data.demands.lock().await;
+    let mut lock = data.lock.lock().await;
+    let mut allocations = data.allocations.lock().await;
+    let offer_ids: Vec<String> = match (&unsubscribe.offer_id, &unsubscribe.provider_id) {
+        (Some(offer_id), None) => vec![offer_id.clone()],
+        (None, Some(provider_id)) => lock
+            .offer_map
+            .values()
+            .filter(|offer| offer.offer.provider_id == *provider_id)
+            .map(|offer| offer.offer.id.clone())
+            .collect(),
+        _ => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidFormat,
+                "Invalid format, expected exactly one of offerId and providerId",
+            ))
+        }
+    };
+
+    let mut result = OfferUnsubscribeResult {
+        removed_offer_ids: Vec::new(),
+        notified_requestors: Vec::new(),
+    };
+    for offer_id in offer_ids {
+        if let Some(removed) = remove_offer(
+            &mut lock,
+            &mut demands,
+            &mut allocations,
+            &offer_id,
+            OfferRemoval::Unsubscribed,
+            data.now(),
+        ) {
+            log::info!(
+                "Provider {} unsubscribed offer {}",
+                removed.offer.provider_id,
+                offer_id
+            );
+            if let Some(requestor_id) = removed.requestor_id {
+                if !result.notified_requestors.contains(&requestor_id) {
+                    result.notified_requestors.push(requestor_id);
+                }
+            }
+            data.audit
+                .offers_removed(&[removed], OfferRemoval::Unsubscribed);
+            result.removed_offer_ids.push(offer_id);
+        }
+    }
+    if result.removed_offer_ids.is_empty() {
+        return Err(
+            ApiError::new(ErrorCode::OfferNotFound, "Offer not found").with_details(
+                serde_json::json!({
+                    "offerId": unsubscribe.offer_id,
+                    "providerId": unsubscribe.provider_id,
+                }),
+            ),
+        );
+    }
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[actix_web::test]
+async fn test_unsubscribe_notifies_owning_demand() {
+    use crate::allocations::{AllocationSource, LeaseState};
+    use crate::state::{test_demand_obj, test_offer_obj, test_state_at_fixture_time};
+
+    let (data, now) = test_state_at_fixture_time();
+    let requestor_id = NodeId::default();
+    let mut offer = test_offer_obj("offer", now);
+    offer.requestor_id = Some(requestor_id);
+    let provider_id = offer.offer.provider_id;
+    data.allocations.lock().await.allocate(
+        &offer,
+        requestor_id,
+        Some("owner"),
+        AllocationSource::Matching,
+        LeaseState::Queued,
+        now,
+    );
+    data.lock
+        .lock()
+        .await
+        .offer_map
+        .insert("offer".to_string(), offer);
+    {
+        let mut demands = data.demands.lock().await;
+        let mut owner = test_demand_obj("owner", requestor_id, now);
+        owner.offer_list.push_back("offer".to_string());
+        demands.demand_map.insert("owner".to_string(), owner);
+        // another workload of the same requestor
+        demands.demand_map.insert(
+            "other".to_string(),
+            test_demand_obj("other", requestor_id, now),
+        );
+    }
+
+    let refused = unsubscribe_offer(data.clone(), serde_json::json!({}).to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code, ErrorCode::InvalidFormat);
+    unsubscribe_offer(
+        data.clone(),
+        serde_json::json!({ "providerId": provider_id }).to_string(),
+    )
+    .await
+    .unwrap();
+    assert!(data.lock.lock().await.offer_map.is_empty());
+    let demands = data.demands.lock().await;
+    assert!(demands.demand_map["owner"].offer_list.is_empty());
+    assert_eq!(
+        demands.demand_map["owner"].withdrawn_offers,
+        ["offer".to_string()]
+    );
+    assert!(demands.demand_map["other"].withdrawn_offers.is_empty());
+    drop(demands);
+
+    let missing = unsubscribe_offer(
+        data.clone(),
+        serde_json::json!({ "offerId": "offer" }).to_string(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(missing.code, ErrorCode::OfferNotFound);
+}
@@ -0,0 +1,192 @@
+use crate::allocations::{Allocation, AllocationSource, Allocations, LeaseState};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::config::{
+    AuditConfig, CleanupConfig, GroupingConfig, HttpConfig, JobConfig, LivenessConfig,
+    MatchingConfig, MirrorConfig, Preemption, RecordingConfig, ReplacedOfferAssignment,
+    RequestorSchedule, ServerConfig, StorageConfig,
+};
+use crate::grouping::GroupingEpoch;
+use crate::health::{HealthReport, HealthStatus, Readiness};
+use crate::jobs::{JobStats, JobStatus};
+use crate::model::demand::base::{
+    DemandCancellation, DemandRefresh, DemandSubscription, DemandUpdate,
+};
+use crate::model::offer::attributes::{OfferFlatAttributes, ResourceRequirements};
+use crate::model::offer::base::GolemBaseOffer;
+use crate::model::offer::properties::{Gpu, PaymentPlatform};
+use crate::offers::{MirrorSyncStats, OfferInsertResult, OfferInsertStatus};
+use crate::rest::admin::invariants::{AllocationIssue, InvariantReport, QueueEntryIssue};
+use crate::rest::admin::offers::{
+    BulkReassign, OfferAssignmentChange, OfferInspection, OfferReassign, OfferSelector,
+    QueuePosition,
+};
+use crate::rest::allocations::{AllocationListing, RequestorAllocationCounts};
+use crate::rest::demand::add_offer_to_demand::AddOfferToDemand;
+use crate::rest::demand::list_demands::DemandListEntry;
+use crate::rest::demand::pick_offer_to_demand::PickOfferToDemand;
+use crate::rest::demand::take_offer_from_queue::ModelOffer;
+use crate::rest::demand::TakeOfferFromQueue;
+use crate::rest::error::{ApiErrorBody, ErrorCode};
+use crate::rest::offer::get_if_available::FilterAttributes;
+use crate::rest::offer::list_offers::OfferListEntry;
+use crate::rest::offer::unsubscribe_offer::{OfferUnsubscribe, OfferUnsubscribeResult};
+use crate::rest::provider::{ProviderEntry, ProviderHeartbeat, ProviderLivenessInfo};
+use crate::rest::test::report::{GroupOutcome, GroupReport, ReportFormat, TestReport};
+use crate::rest::test::{
+    TestFinishArguments, TestInitializeArguments, TestRerunArguments, TestStartArguments,
+};
+use crate::state::{
+    DemandObj, DemandProgress, Demands, IntegrationTest, IntegrationTestGroup, OfferObj, Offers,
+    ProviderLiveness, Providers, Snapshot,
+};
+use actix_web::HttpResponse;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Yagna offer server",
+        description = "Offer matcher for Golem Yagna"
+    ),
+    paths(
+        crate::rest::offer::push_offer::push_offer,
+        crate::rest::offer::unsubscribe_offer::unsubscribe_offer,
+        crate::rest::provider::provider_heartbeat,
+        crate::rest::provider::list_providers,
+        crate::rest::offer::list_offers::list_offers,
+        crate::rest::offer::list_offers::list_taken_offers,
+        crate::rest::offer::list_offers::list_available_offers,
+        crate::grouping::grouping_epoch,
+        crate::rest::offer::clean_old_offers::delete_all_offers,
+        crate::rest::offer::get_if_available::get_if_available,
+        crate::rest::allocations::list_allocations,
+        crate::rest::demand::demand_new::demand_new,
+        crate::rest::demand::cancel_demand::demand_cancel,
+        crate::rest::demand::update_demand::demand_refresh,
+        crate::rest::demand::update_demand::demand_update,
+        crate::rest::demand::list_demands::list_demands,
+        crate::rest::demand::add_offer_to_demand::add_offer_to_demand,
+        crate::rest::demand::pick_offer_to_demand::pick_offer_to_demand,
+        crate::rest::demand::take_offer_from_queue::take_offer_from_queue,
+        crate::rest::test::test_initialize,
+        crate::rest::test::test_start,
+        crate::rest::test::test_finish,
+        crate::rest::test::test_status,
+        crate::rest::test::ok_if_finished,
+        crate::rest::test::test_rerun,
+        crate::rest::test::report::test_report,
+        crate::rest::admin::config_show,
+        crate::rest::admin::invariants::invariants_check,
+        crate::rest::admin::audit::audit_query,
+        crate::rest::admin::offers::offer_inspect,
+        crate::rest::admin::offers::offer_unassign,
+        crate::rest::admin::offers::offer_reassign,
+        crate::rest::admin::offers::offers_unassign,
+        crate::rest::admin::offers::offers_reassign,
+        crate::rest::admin::jobs::jobs_list,
+        crate::rest::admin::jobs::job_pause,
+        crate::rest::admin::jobs::job_resume,
+        crate::rest::admin::jobs::job_run_now,
+        crate::rest::admin::mirror_sync_now,
+        crate::rest::admin::snapshot_export,
+        crate::rest::admin::snapshot_import,
+        crate::rest::routes::version,
+        crate::health::health,
+        crate::health::ready,
+        openapi_json,
+    ),
+    components(schemas(
+        GolemBaseOffer,
+        PaymentPlatform,
+        Gpu,
+        OfferFlatAttributes,
+        ResourceRequirements,
+        OfferObj,
+        DemandSubscription,
+        DemandCancellation,
+        DemandRefresh,
+        DemandListEntry,
+        DemandProgress,
+        DemandUpdate,
+        DemandObj,
+        AddOfferToDemand,
+        PickOfferToDemand,
+        TakeOfferFromQueue,
+        ModelOffer,
+        FilterAttributes,
+        TestInitializeArguments,
+        TestStartArguments,
+        TestFinishArguments,
+        TestRerunArguments,
+        TestReport,
+        GroupReport,
+        GroupOutcome,
+        ReportFormat,
+        IntegrationTest,
+        IntegrationTestGroup,
+        Offers,
+        Demands,
+        Snapshot,
+        ServerConfig,
+        HttpConfig,
+        MirrorConfig,
+        MatchingConfig,
+        CleanupConfig,
+        ReplacedOfferAssignment,
+        Preemption,
+        RequestorSchedule,
+        MirrorSyncStats,
+        HealthReport,
+        HealthStatus,
+        Readiness,
+        JobStatus,
+        JobStats,
+        InvariantReport,
+        QueueEntryIssue,
+        AllocationIssue,
+        OfferInspection,
+        QueuePosition,
+        OfferReassign,
+        OfferSelector,
+        BulkReassign,
+        OfferAssignmentChange,
+        Allocation,
+        AllocationSource,
+        LeaseState,
+        Allocations,
+        AllocationListing,
+        RequestorAllocationCounts,
+        OfferInsertResult,
+        OfferInsertStatus,
+        OfferUnsubscribe,
+        OfferListEntry,
+        ProviderHeartbeat,
+        ProviderLiveness,
+        ProviderLivenessInfo,
+        ProviderEntry,
+        Providers,
+        LivenessConfig,
+        GroupingConfig,
+        GroupingEpoch,
+        AuditConfig,
+        StorageConfig,
+        JobConfig,
+        RecordingConfig,
+        AuditEntry,
+        AuditAction,
+        OfferUnsubscribeResult,
+        ApiErrorBody,
+        ErrorCode,
+    ))
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses((status = 200, description = "OpenAPI 3 specification of this server"))
+)]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
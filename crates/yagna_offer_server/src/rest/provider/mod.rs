@@ -0,0 +1,149 @@
+use crate::clock::checked_sub_secs;
+use crate::config::LivenessConfig;
+use crate::rest::error::{ApiError, ApiErrorBody};
+use crate::state::{AppState, ProviderLiveness, Providers};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use ya_client_model::NodeId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderHeartbeat {
+    #[schema(value_type = String)]
+    pub node_id: NodeId,
+}
+
+/// Liveness of a provider as seen at the time of the request
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderLivenessInfo {
+    /// `None` when the provider never sent a heartbeat
+    pub last_seen: Option<DateTime<Utc>>,
+    pub missed_beats: Option<u64>,
+    /// Whether offers of the provider are handed out
+    pub alive: bool,
+}
+
+impl ProviderLivenessInfo {
+    pub fn new(
+        config: &LivenessConfig,
+        providers: &Providers,
+        provider_id: &NodeId,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let last_seen = providers.last_seen(provider_id);
+        Self {
+            last_seen,
+            missed_beats: last_seen.map(|last_seen| config.missed_beats(last_seen, now)),
+            alive: config.is_alive(last_seen, now),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderEntry {
+    #[schema(value_type = String)]
+    pub node_id: NodeId,
+    #[serde(flatten)]
+    pub heartbeat: ProviderLiveness,
+    pub liveness: ProviderLivenessInfo,
+}
+
+#[utoipa::path(
+    post,
+    path = "/provider/heartbeat",
+    tag = "provider",
+    request_body(content = ProviderHeartbeat, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Heartbeat recorded", body = ProviderLiveness),
+        (status = 400, description = "Invalid heartbeat format", body = ApiErrorBody),
+    )
+)]
+pub async fn provider_heartbeat(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let heartbeat = serde_json::from_str::<ProviderHeartbeat>(&body).map_err(|e| {
+        log::error!("Error decoding provider heartbeat: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+
+    let now = data.now();
+    let mut providers = data.providers.lock().await;
+    let liveness = providers
+        .provider_map
+        .entry(heartbeat.node_id)
+        .or_insert_with(|| {
+            log::info!("First heartbeat from provider {}", heartbeat.node_id);
+            ProviderLiveness {
+                first_seen: now,
+                last_seen: now,
+                heartbeats: 0,
+            }
+        });
+    liveness.last_seen = now;
+    liveness.heartbeats += 1;
+    Ok(HttpResponse::Ok().json(liveness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/providers/list",
+    tag = "provider",
+    responses((status = 200, description = "Providers that sent a heartbeat", body = Vec<ProviderEntry>))
+)]
+pub async fn list_providers(data: web::Data<AppState>) -> HttpResponse {
+    let config = data.config();
+    let now = data.now();
+    let providers = data.providers.lock().await;
+    let entries: Vec<ProviderEntry> = providers
+        .provider_map
+        .iter()
+        .map(|(node_id, heartbeat)| ProviderEntry {
+            node_id: *node_id,
+            heartbeat: heartbeat.clone(),
+            liveness: ProviderLivenessInfo::new(&config.liveness, &providers, node_id, now),
+        })
+        .collect();
+    HttpResponse::Ok().json(entries)
+}
+
+/// Drop providers silent for longer than `liveness.forget_after_secs`
+pub async fn forget_silent_providers(data: web::Data<AppState>) {
+    let seen_after = checked_sub_secs(data.now(), data.config().liveness.forget_after_secs)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let mut providers = data.providers.lock().await;
+    providers
+        .provider_map
+        .retain(|_, liveness| liveness.last_seen > seen_after);
+}
+
+#[actix_web::test]
+async fn test_silent_providers_forgotten() {
+    use crate::clock::MockClock;
+    use crate::state::test_state;
+    use std::sync::Arc;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(now));
+    let data = test_state(clock.clone());
+    provider_heartbeat(
+        data.clone(),
+        serde_json::json!({ "nodeId": NodeId::default() }).to_string(),
+    )
+    .await
+    .unwrap();
+
+    clock.advance(chrono::Duration::hours(23));
+    forget_silent_providers(data.clone()).await;
+    let providers = data.providers.lock().await;
+    assert_eq!(providers.last_seen(&NodeId::default()), Some(now));
+    drop(providers);
+
+    clock.advance(chrono::Duration::hours(1));
+    forget_silent_providers(data.clone()).await;
+    assert!(data.providers.lock().await.provider_map.is_empty());
+}
@@ -0,0 +1,170 @@
+use crate::grouping::grouping_epoch;
+use crate::health::{health, ready};
+use crate::rest::admin::audit::audit_query;
+use crate::rest::admin::invariants::invariants_check;
+use crate::rest::admin::jobs::{job_pause, job_resume, job_run_now, jobs_list};
+use crate::rest::admin::offers::{
+    offer_inspect, offer_reassign, offer_unassign, offers_reassign, offers_unassign,
+};
+use crate::rest::admin::{config_show, mirror_sync_now, snapshot_export, snapshot_import};
+use crate::rest::allocations::list_allocations;
+use crate::rest::demand::add_offer_to_demand::add_offer_to_demand;
+use crate::rest::demand::cancel_demand::demand_cancel;
+use crate::rest::demand::demand_new::demand_new;
+use crate::rest::demand::list_demands::list_demands;
+use crate::rest::demand::pick_offer_to_demand::pick_offer_to_demand;
+use crate::rest::demand::take_offer_from_queue::take_offer_from_queue;
+use crate::rest::demand::update_demand::{demand_refresh, demand_update};
+use crate::rest::offer::clean_old_offers::delete_all_offers;
+use crate::rest::offer::get_if_available::get_if_available;
+use crate::rest::offer::list_offers::{
+    list_available_offers, list_offers, list_taken_offers, OFFER_LIST_PATH,
+};
+use crate::rest::offer::push_offer::push_offer;
+use crate::rest::offer::unsubscribe_offer::unsubscribe_offer;
+use crate::rest::openapi::openapi_json;
+use crate::rest::provider::{list_providers, provider_heartbeat};
+use crate::rest::test::report::test_report;
+use crate::rest::test::{
+    ok_if_finished, test_finish, test_initialize, test_rerun, test_start, test_status,
+};
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, Handler, HttpResponse, Responder, Route};
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+pub struct ApiRoute {
+    pub method: Method,
+    pub path: &'static str,
+    pub route: Route,
+}
+
+fn api_route<F, Args>(method: Method, path: &'static str, handler: F) -> ApiRoute
+where
+    F: Handler<Args>,
+    Args: FromRequest + 'static,
+    F::Output: Responder + 'static,
+{
+    ApiRoute {
+        route: web::method(method.clone()).to(handler),
+        method,
+        path,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "meta",
+    responses((status = 200, description = "Server version", body = String))
+)]
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().body(env!("CARGO_PKG_VERSION"))
+}
+
+/// Every API endpoint served by the matcher. The OpenAPI document has to describe exactly
+/// these routes, which is checked by a test.
+pub fn api_routes() -> Vec<ApiRoute> {
+    vec![
+        api_route(Method::POST, "/provider/offer/new", push_offer),
+        api_route(
+            Method::POST,
+            "/provider/offer/unsubscribe",
+            unsubscribe_offer,
+        ),
+        api_route(Method::POST, "/provider/heartbeat", provider_heartbeat),
+        api_route(Method::GET, "/providers/list", list_providers),
+        api_route(Method::GET, OFFER_LIST_PATH, list_offers),
+        api_route(Method::POST, "/offers/clear", delete_all_offers),
+        api_route(Method::GET, "/offers/list/taken", list_taken_offers),
+        api_route(Method::GET, "/offers/list/available", list_available_offers),
+        api_route(Method::GET, "/offers/grouping", grouping_epoch),
+        api_route(Method::POST, "/offer/take", get_if_available),
+        api_route(Method::GET, "/allocations", list_allocations),
+        api_route(Method::GET, "/version", version),
+        api_route(Method::GET, "/health", health),
+        api_route(Method::GET, "/ready", ready),
+        api_route(Method::POST, "/requestor/demand/new", demand_new),
+        api_route(Method::POST, "/requestor/demand/cancel", demand_cancel),
+        api_route(Method::POST, "/requestor/demand/refresh", demand_refresh),
+        api_route(Method::POST, "/requestor/demand/update", demand_update),
+        api_route(Method::GET, "/requestor/demands/list", list_demands),
+        api_route(
+            Method::POST,
+            "/requestor/demand/append-offer",
+            add_offer_to_demand,
+        ),
+        api_route(
+            Method::POST,
+            "/requestor/demand/append-any-offer",
+            pick_offer_to_demand,
+        ),
+        api_route(
+            Method::POST,
+            "/requestor/demand/take-from-queue",
+            take_offer_from_queue,
+        ),
+        api_route(Method::POST, "/test/initialize", test_initialize),
+        api_route(Method::POST, "/test/start", test_start),
+        api_route(Method::POST, "/test/finish", test_finish),
+        api_route(Method::GET, "/test/status", test_status),
+        api_route(Method::GET, "/test/finished/check", ok_if_finished),
+        api_route(Method::POST, "/test/rerun", test_rerun),
+        api_route(Method::GET, "/test/report", test_report),
+        api_route(Method::GET, "/admin/config", config_show),
+        api_route(Method::GET, "/admin/invariants", invariants_check),
+        api_route(Method::GET, "/admin/audit", audit_query),
+        api_route(Method::GET, "/admin/offer/{id}", offer_inspect),
+        api_route(Method::POST, "/admin/offer/{id}/unassign", offer_unassign),
+        api_route(Method::POST, "/admin/offer/{id}/reassign", offer_reassign),
+        api_route(Method::POST, "/admin/offers/unassign", offers_unassign),
+        api_route(Method::POST, "/admin/offers/reassign", offers_reassign),
+        api_route(Method::GET, "/admin/jobs", jobs_list),
+        api_route(Method::POST, "/admin/jobs/{name}/pause", job_pause),
+        api_route(Method::POST, "/admin/jobs/{name}/resume", job_resume),
+        api_route(Method::POST, "/admin/jobs/{name}/run", job_run_now),
+        api_route(Method::POST, "/admin/mirror/sync", mirror_sync_now),
+        api_route(Method::GET, "/admin/snapshot", snapshot_export),
+        api_route(Method::POST, "/admin/snapshot", snapshot_import),
+        api_route(Method::GET, "/openapi.json", openapi_json),
+    ]
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    for api_route in api_routes() {
+        cfg.route(api_route.path, api_route.route);
+    }
+    cfg.service(SwaggerUi::new("/docs/{_:.*}").config(Config::new(["/openapi.json"])));
+}
+
+#[test]
+fn test_routes_match_openapi_spec() {
+    use crate::rest::openapi::ApiDoc;
+    use std::collections::BTreeSet;
+    use utoipa::OpenApi;
+
+    let served: BTreeSet<(String, String)> = api_routes()
+        .into_iter()
+        .map(|r| (r.method.as_str().to_lowercase(), r.path.to_string()))
+        .collect();
+
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let mut documented = BTreeSet::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        for method in item.as_object().unwrap().keys() {
+            documented.insert((method.clone(), path.clone()));
+        }
+    }
+
+    let undocumented: Vec<_> = served.difference(&documented).collect();
+    let not_served: Vec<_> = documented.difference(&served).collect();
+    assert!(
+        undocumented.is_empty(),
+        "Routes missing from OpenAPI spec: {:?}",
+        undocumented
+    );
+    assert!(
+        not_served.is_empty(),
+        "OpenAPI paths without a route: {:?}",
+        not_served
+    );
+}
@@ -1,152 +1,455 @@
+pub mod report;
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::jobs;
+use crate::rest::error::{ApiError, ApiErrorBody, ErrorCode};
 use crate::rest::offer::clean_old_offers::delete_all_offers;
-use crate::state::{AppState, IntegrationTestGroup};
+use crate::state::{AppState, IntegrationTest, IntegrationTestGroup};
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TestInitializeArguments {
     pub number_of_groups: usize,
+    /// Pause the matching job until the first group starts, so offers are assigned only
+    /// after providers have pushed them
+    #[serde(default)]
+    pub pause_matching: bool,
+    /// Fail the whole test this long after initialization
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
+    /// Fail groups still running this long after they started
+    #[serde(default)]
+    pub group_timeout_secs: Option<f64>,
+}
+
+fn check_timeout(name: &str, timeout_secs: Option<f64>) -> Result<(), ApiError> {
+    match timeout_secs {
+        Some(secs) if !(secs.is_finite() && secs > 0.0) => Err(ApiError::new(
+            ErrorCode::InvalidFormat,
+            format!("Invalid format, {} has to be positive", name),
+        )
+        .with_details(serde_json::json!({ name: secs }))),
+        _ => Ok(()),
+    }
+}
+
+/// Fail groups and the test past their timeouts before the state is looked at
+fn apply_timeouts(data: &AppState, test: &mut IntegrationTest) {
+    let was_finished = test.finished_at.is_some();
+    let now = data.now();
+    for group in test.apply_timeouts(now) {
+        log::warn!("Test group {} timed out", group);
+        data.audit.record(
+            AuditEntry::new(AuditAction::TestGroupTimedOut, now)
+                .with_details(serde_json::json!({ "group": group })),
+        );
+    }
+    if !was_finished && test.timed_out {
+        log::warn!("Test timed out");
+    }
 }
 
-pub async fn test_initialize(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<TestInitializeArguments>(&body);
-    let test_initialize_args = match decoded {
-        Ok(t) => t,
-        Err(e) => {
+#[utoipa::path(
+    post,
+    path = "/test/initialize",
+    tag = "test",
+    request_body(content = TestInitializeArguments, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Test initialized and all offers removed", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+    )
+)]
+pub async fn test_initialize(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let test_initialize_args =
+        serde_json::from_str::<TestInitializeArguments>(&body).map_err(|e| {
             log::error!("Error decoding test initialize arguments: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+            ApiError::invalid_format(e)
+        })?;
+    check_timeout("timeoutSecs", test_initialize_args.timeout_secs)?;
+    check_timeout("groupTimeoutSecs", test_initialize_args.group_timeout_secs)?;
 
     {
         let mut lock = data.test.lock().await;
-        lock.started_at = Some(chrono::Utc::now());
-        lock.finished_at = None;
-        lock.number_of_groups = test_initialize_args.number_of_groups;
-        lock.groups.clear();
+        *lock = IntegrationTest {
+            started_at: Some(data.now()),
+            number_of_groups: test_initialize_args.number_of_groups,
+            timeout_secs: test_initialize_args.timeout_secs,
+            group_timeout_secs: test_initialize_args.group_timeout_secs,
+            // a pause of the previous run is taken over
+            matching_paused: lock.matching_paused,
+            ..Default::default()
+        };
+        if test_initialize_args.pause_matching && !data.jobs.is_paused(jobs::MATCHING) {
+            data.jobs.pause(jobs::MATCHING);
+            lock.matching_paused = true;
+            log::info!("Matching paused until the first test group starts");
+        } else if !test_initialize_args.pause_matching && lock.matching_paused {
+            data.jobs.resume(jobs::MATCHING);
+            lock.matching_paused = false;
+        }
     }
+    data.audit.record(
+        AuditEntry::new(AuditAction::TestInitialized, data.now()).with_details(serde_json::json!({
+            "numberOfGroups": test_initialize_args.number_of_groups,
+            "pauseMatching": test_initialize_args.pause_matching,
+            "timeoutSecs": test_initialize_args.timeout_secs,
+            "groupTimeoutSecs": test_initialize_args.group_timeout_secs,
+        })),
+    );
     delete_all_offers(data.clone()).await;
-    HttpResponse::Ok().body("New test initialized successfully")
+    Ok(HttpResponse::Ok().body("New test initialized successfully"))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TestStartArguments {
     pub group: String,
+    /// Overrides the group timeout of the test for this group
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
 }
 
-pub async fn test_start(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<TestStartArguments>(&body);
-    let test_start_args = match decoded {
-        Ok(t) => t,
-        Err(e) => {
-            log::error!("Error decoding test start arguments: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/test/start",
+    tag = "test",
+    request_body(content = TestStartArguments, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Test group started", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 409, description = "Test already in progress for this group", body = ApiErrorBody),
+        (status = 422, description = "Number of test groups exceeded", body = ApiErrorBody),
+    )
+)]
+pub async fn test_start(data: web::Data<AppState>, body: String) -> Result<HttpResponse, ApiError> {
+    let test_start_args = serde_json::from_str::<TestStartArguments>(&body).map_err(|e| {
+        log::error!("Error decoding test start arguments: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+    check_timeout("timeoutSecs", test_start_args.timeout_secs)?;
 
     let mut lock = data.test.lock().await;
+    apply_timeouts(&data, &mut lock);
+    // checked before the group is added, a refused start leaves the test as it was
+    if !lock.groups.contains_key(&test_start_args.group)
+        && lock.groups.len() >= lock.number_of_groups
+    {
+        return Err(groups_exceeded(lock.number_of_groups));
+    }
     {
         let entry = lock
             .groups
-            .entry(test_start_args.group)
+            .entry(test_start_args.group.clone())
             .or_insert_with(IntegrationTestGroup::default);
 
         if entry.started_at.is_some() {
-            return HttpResponse::BadRequest().body("Test already in progress for this group");
+            return Err(ApiError::new(
+                ErrorCode::TestAlreadyStarted,
+                "Test already in progress for this group",
+            ));
         }
-        entry.started_at = Some(chrono::Utc::now());
+        entry.started_at = Some(data.now());
         entry.finished_at = None;
+        entry.timeout_secs = test_start_args.timeout_secs;
+        entry.attempts += 1;
     }
-    if lock.groups.len() > lock.number_of_groups {
-        return HttpResponse::BadRequest()
-            .body("Number of test groups exceeded the initialized number");
+    if lock.matching_paused {
+        lock.matching_paused = false;
+        data.jobs.resume(jobs::MATCHING);
+        log::info!(
+            "Matching resumed, test group {} started",
+            test_start_args.group
+        );
     }
 
-    HttpResponse::Ok().body("Test started successfully")
+    data.audit.record(
+        AuditEntry::new(AuditAction::TestGroupStarted, data.now())
+            .with_details(serde_json::json!({ "group": test_start_args.group })),
+    );
+    Ok(HttpResponse::Ok().body("Test started successfully"))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TestFinishArguments {
     pub group: String,
     pub success: bool,
+    /// Free-form values shown in the report, e.g. offers taken or tasks computed
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub metrics: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
-pub async fn test_finish(data: web::Data<AppState>, body: String) -> HttpResponse {
-    let decoded = serde_json::from_str::<TestFinishArguments>(&body);
-    let test_finish_args = match decoded {
-        Ok(t) => t,
-        Err(e) => {
-            log::error!("Error decoding test finish arguments: {}", e);
-            return HttpResponse::BadRequest().body(format!("Invalid format {}", e));
-        }
-    };
+#[utoipa::path(
+    post,
+    path = "/test/finish",
+    tag = "test",
+    request_body(content = TestFinishArguments, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Test group finished", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 409, description = "Group not started or already finished", body = ApiErrorBody),
+        (status = 422, description = "Number of test groups exceeded", body = ApiErrorBody),
+    )
+)]
+pub async fn test_finish(
+    data: web::Data<AppState>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let test_finish_args = serde_json::from_str::<TestFinishArguments>(&body).map_err(|e| {
+        log::error!("Error decoding test finish arguments: {}", e);
+        ApiError::invalid_format(e)
+    })?;
 
     let mut lock = data.test.lock().await;
+    apply_timeouts(&data, &mut lock);
+    if lock.groups.len() > lock.number_of_groups {
+        return Err(groups_exceeded(lock.number_of_groups));
+    }
     if lock.finished_at.is_some() {
-        return HttpResponse::BadRequest().body("Test already finished");
+        return Err(ApiError::new(
+            ErrorCode::TestAlreadyFinished,
+            "Test already finished",
+        ));
     }
     let entry = lock.groups.get_mut(&test_finish_args.group);
     let entry = match entry {
         Some(e) => e,
         None => {
-            return HttpResponse::BadRequest().body(format!(
-                "No test was started for group {}",
-                test_finish_args.group
-            ));
+            return Err(not_started(&test_finish_args.group));
         }
     };
 
     if entry.started_at.is_none() {
-        return HttpResponse::BadRequest().body(format!(
-            "No test was started for group {}",
-            test_finish_args.group
-        ));
+        return Err(not_started(&test_finish_args.group));
     }
     if entry.finished_at.is_some() {
-        return HttpResponse::BadRequest().body(format!(
-            "Test already finished for this group {}",
-            test_finish_args.group
-        ));
+        return Err(ApiError::new(
+            ErrorCode::TestAlreadyFinished,
+            format!(
+                "Test already finished for this group {}",
+                test_finish_args.group
+            ),
+        )
+        .with_details(serde_json::json!({ "group": test_finish_args.group })));
     }
-    entry.finished_at = Some(chrono::Utc::now());
+    entry.finished_at = Some(data.now());
     entry.success = Some(test_finish_args.success);
+    entry.metrics = test_finish_args.metrics;
+    entry.message = test_finish_args.message;
+    data.audit.record(
+        AuditEntry::new(AuditAction::TestGroupFinished, data.now()).with_details(
+            serde_json::json!({
+                "group": test_finish_args.group,
+                "success": test_finish_args.success,
+            }),
+        ),
+    );
 
-    // check if all tests are finished
+    lock.finish_if_complete(data.now());
+    Ok(HttpResponse::Ok().body("Test finished successfully"))
+}
 
-    if lock.groups.len() > lock.number_of_groups {
-        return HttpResponse::BadRequest()
-            .body("Number of test groups exceeded the initialized number");
-    }
-    if lock.groups.len() == lock.number_of_groups {
-        let all_finished = lock.groups.values().all(|g| g.finished_at.is_some());
-        if all_finished {
-            lock.finished_at = Some(chrono::Utc::now());
-        }
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRerunArguments {
+    pub group: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/test/rerun",
+    tag = "test",
+    request_body(content = TestRerunArguments, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Group reset, it can be started again", body = String),
+        (status = 400, description = "Invalid request format", body = ApiErrorBody),
+        (status = 409, description = "Group not started or still running", body = ApiErrorBody),
+    )
+)]
+pub async fn test_rerun(data: web::Data<AppState>, body: String) -> Result<HttpResponse, ApiError> {
+    let test_rerun_args = serde_json::from_str::<TestRerunArguments>(&body).map_err(|e| {
+        log::error!("Error decoding test rerun arguments: {}", e);
+        ApiError::invalid_format(e)
+    })?;
+
+    let mut lock = data.test.lock().await;
+    apply_timeouts(&data, &mut lock);
+    let group = match lock.groups.get_mut(&test_rerun_args.group) {
+        Some(group) if group.started_at.is_some() => group,
+        _ => return Err(not_started(&test_rerun_args.group)),
+    };
+    if group.finished_at.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::TestAlreadyStarted,
+            format!(
+                "Test group {} is still running, finish it before re-running",
+                test_rerun_args.group
+            ),
+        )
+        .with_details(serde_json::json!({ "group": test_rerun_args.group })));
     }
+    // the number of attempts is kept, the global timeout still counts from initialization
+    *group = IntegrationTestGroup {
+        attempts: group.attempts,
+        ..Default::default()
+    };
+    lock.finished_at = None;
+    lock.success = None;
+    lock.timed_out = false;
+    data.audit.record(
+        AuditEntry::new(AuditAction::TestGroupRerun, data.now())
+            .with_details(serde_json::json!({ "group": test_rerun_args.group })),
+    );
+    Ok(HttpResponse::Ok().body(format!(
+        "Test group {} can be started again",
+        test_rerun_args.group
+    )))
+}
+
+fn not_started(group: &str) -> ApiError {
+    ApiError::new(
+        ErrorCode::TestNotStarted,
+        format!("No test was started for group {}", group),
+    )
+    .with_details(serde_json::json!({ "group": group }))
+}
 
-    HttpResponse::Ok().body("Test finished successfully")
+fn groups_exceeded(number_of_groups: usize) -> ApiError {
+    ApiError::new(
+        ErrorCode::TestGroupsExceeded,
+        "Number of test groups exceeded the initialized number",
+    )
+    .with_details(serde_json::json!({ "numberOfGroups": number_of_groups }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/test/status",
+    tag = "test",
+    responses((status = 200, description = "Current test state", body = IntegrationTest))
+)]
 pub async fn test_status(data: web::Data<AppState>) -> HttpResponse {
-    let lock = data.test.lock().await;
+    let mut lock = data.test.lock().await;
+    apply_timeouts(&data, &mut lock);
     let response = serde_json::to_string(&*lock).unwrap_or_else(|_| "{}".to_string());
     HttpResponse::Ok()
         .content_type("application/json")
         .body(response)
 }
 
-pub async fn ok_if_finished(data: web::Data<AppState>) -> HttpResponse {
-    let lock = data.test.lock().await;
+#[utoipa::path(
+    get,
+    path = "/test/finished/check",
+    tag = "test",
+    responses(
+        (status = 200, description = "Test is finished", body = String),
+        (status = 409, description = "Test is not finished yet", body = ApiErrorBody),
+    )
+)]
+pub async fn ok_if_finished(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let mut lock = data.test.lock().await;
+    apply_timeouts(&data, &mut lock);
     if lock.finished_at.is_some() {
-        HttpResponse::Ok()
+        Ok(HttpResponse::Ok()
             .content_type("application/json")
-            .body("Test is finished")
+            .body("Test is finished"))
     } else {
-        HttpResponse::BadRequest()
-            .content_type("application/json")
-            .body("Test is not finished yet")
+        Err(ApiError::new(
+            ErrorCode::TestNotFinished,
+            "Test is not finished yet",
+        ))
     }
 }
+
+#[actix_web::test]
+async fn test_group_limit_is_checked_before_changes() {
+    use crate::clock::MockClock;
+    use crate::state::test_state;
+    use std::sync::Arc;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(now));
+    let data = test_state(clock.clone());
+    let body = |value: Value| value.to_string();
+    test_initialize(
+        data.clone(),
+        body(serde_json::json!({ "numberOfGroups": 1, "groupTimeoutSecs": 60.0 })),
+    )
+    .await
+    .unwrap();
+    test_start(data.clone(), body(serde_json::json!({ "group": "a" })))
+        .await
+        .unwrap();
+    let refused = test_start(data.clone(), body(serde_json::json!({ "group": "b" })))
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code, ErrorCode::TestGroupsExceeded);
+    assert!(!data.test.lock().await.groups.contains_key("b"));
+
+    // the group runs past its timeout and is failed by the server
+    clock.advance(chrono::Duration::seconds(61));
+    ok_if_finished(data.clone()).await.unwrap();
+    {
+        let test = data.test.lock().await;
+        assert!(test.groups["a"].timed_out);
+        assert_eq!(test.success, Some(false));
+    }
+
+    test_rerun(data.clone(), body(serde_json::json!({ "group": "a" })))
+        .await
+        .unwrap();
+    test_start(data.clone(), body(serde_json::json!({ "group": "a" })))
+        .await
+        .unwrap();
+    test_finish(
+        data.clone(),
+        body(serde_json::json!({ "group": "a", "success": true, "metrics": { "offers": 3 } })),
+    )
+    .await
+    .unwrap();
+    let test = data.test.lock().await;
+    let group = &test.groups["a"];
+    assert_eq!((group.attempts, group.timed_out), (2, false));
+    assert_eq!(group.metrics["offers"], 3);
+    assert_eq!(test.success, Some(true));
+}
+
+#[actix_web::test]
+async fn test_huge_timeout_never_expires() {
+    use crate::clock::MockClock;
+    use crate::state::test_state;
+    use std::sync::Arc;
+
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let clock = Arc::new(MockClock::new(now));
+    let data = test_state(clock.clone());
+    let body = |value: Value| value.to_string();
+    test_initialize(
+        data.clone(),
+        body(serde_json::json!({ "numberOfGroups": 1, "timeoutSecs": 1e13 })),
+    )
+    .await
+    .unwrap();
+    test_start(
+        data.clone(),
+        body(serde_json::json!({ "group": "a", "timeoutSecs": 1e300 })),
+    )
+    .await
+    .unwrap();
+    clock.advance(chrono::Duration::days(365));
+    test_status(data.clone()).await;
+    let test = data.test.lock().await;
+    assert!(test.groups["a"].is_running());
+    assert!(!test.timed_out);
+}
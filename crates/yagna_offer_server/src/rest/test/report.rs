@@ -0,0 +1,298 @@
+use crate::rest::error::{ApiError, ApiErrorBody};
+use crate::rest::test::apply_timeouts;
+use crate::state::{AppState, IntegrationTest, IntegrationTestGroup};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    /// JUnit XML, one test case per group
+    Junit,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+pub struct ReportQuery {
+    #[serde(default)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupOutcome {
+    Passed,
+    Failed,
+    TimedOut,
+    Running,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupReport {
+    pub name: String,
+    pub outcome: GroupOutcome,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_secs: Option<f64>,
+    pub attempts: u32,
+    pub message: Option<String>,
+    #[schema(value_type = Object)]
+    pub metrics: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TestReport {
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_secs: Option<f64>,
+    pub success: Option<bool>,
+    pub timed_out: bool,
+    pub number_of_groups: usize,
+    pub passed: usize,
+    pub failed: usize,
+    /// Also counted in `failed`
+    pub timed_out_groups: usize,
+    pub running: usize,
+    /// Expected groups that never started
+    pub not_started: usize,
+    pub groups: Vec<GroupReport>,
+}
+
+fn duration_secs(
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+) -> Option<f64> {
+    Some((finished_at? - started_at?).num_milliseconds() as f64 / 1000.0)
+}
+
+fn group_report(name: &str, group: &IntegrationTestGroup) -> GroupReport {
+    let outcome = match (group.finished_at, group.success) {
+        (None, _) => GroupOutcome::Running,
+        _ if group.timed_out => GroupOutcome::TimedOut,
+        (Some(_), Some(true)) => GroupOutcome::Passed,
+        (Some(_), _) => GroupOutcome::Failed,
+    };
+    GroupReport {
+        name: name.to_string(),
+        outcome,
+        started_at: group.started_at,
+        finished_at: group.finished_at,
+        duration_secs: duration_secs(group.started_at, group.finished_at),
+        attempts: group.attempts,
+        message: group.message.clone(),
+        metrics: group.metrics.clone(),
+    }
+}
+
+impl TestReport {
+    pub fn new(test: &IntegrationTest) -> Self {
+        let groups: Vec<GroupReport> = test
+            .groups
+            .iter()
+            .filter(|(_, group)| group.started_at.is_some())
+            .map(|(name, group)| group_report(name, group))
+            .collect();
+        let count = |outcomes: &[GroupOutcome]| {
+            groups
+                .iter()
+                .filter(|g| outcomes.contains(&g.outcome))
+                .count()
+        };
+        Self {
+            started_at: test.started_at,
+            finished_at: test.finished_at,
+            duration_secs: duration_secs(test.started_at, test.finished_at),
+            success: test.success,
+            timed_out: test.timed_out,
+            number_of_groups: test.number_of_groups,
+            passed: count(&[GroupOutcome::Passed]),
+            failed: count(&[GroupOutcome::Failed, GroupOutcome::TimedOut]),
+            timed_out_groups: count(&[GroupOutcome::TimedOut]),
+            running: count(&[GroupOutcome::Running]),
+            not_started: test.number_of_groups.saturating_sub(groups.len()),
+            groups,
+        }
+    }
+
+    /// JUnit XML as understood by common CI systems. Running groups are reported as skipped,
+    /// metrics become properties of their test case.
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"integration\" tests=\"{}\" failures=\"{}\" time=\"{}\">",
+            self.groups.len(),
+            self.failed,
+            self.duration_secs.unwrap_or_default()
+        );
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"integration\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\"{}>",
+            self.groups.len(),
+            self.failed,
+            self.running,
+            self.duration_secs.unwrap_or_default(),
+            self.started_at
+                .map(|t| format!(" timestamp=\"{}\"", t.format("%Y-%m-%dT%H:%M:%S")))
+                .unwrap_or_default()
+        );
+        if self.not_started > 0 {
+            let _ = writeln!(
+                xml,
+                "    <properties>\n      <property name=\"notStarted\" value=\"{}\"/>\n    </properties>",
+                self.not_started
+            );
+        }
+        for group in &self.groups {
+            let _ = writeln!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"integration\" time=\"{}\">",
+                escape_xml(&group.name),
+                group.duration_secs.unwrap_or_default()
+            );
+            let mut properties = vec![("attempts".to_string(), group.attempts.to_string())];
+            properties.extend(group.metrics.iter().map(|(name, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (name.clone(), value)
+            }));
+            xml.push_str("      <properties>\n");
+            for (name, value) in properties {
+                let _ = writeln!(
+                    xml,
+                    "        <property name=\"{}\" value=\"{}\"/>",
+                    escape_xml(&name),
+                    escape_xml(&value)
+                );
+            }
+            xml.push_str("      </properties>\n");
+            let message = escape_xml(group.message.as_deref().unwrap_or_default());
+            match group.outcome {
+                GroupOutcome::Passed => {}
+                GroupOutcome::Failed => {
+                    let _ = writeln!(xml, "      <failure message=\"{}\"/>", message);
+                }
+                GroupOutcome::TimedOut => {
+                    let _ = writeln!(
+                        xml,
+                        "      <failure type=\"timeout\" message=\"{}\"/>",
+                        message
+                    );
+                }
+                GroupOutcome::Running => {
+                    xml.push_str("      <skipped message=\"still running\"/>\n");
+                }
+            }
+            if let Some(message) = &group.message {
+                let _ = writeln!(
+                    xml,
+                    "      <system-out>{}</system-out>",
+                    escape_xml(message)
+                );
+            }
+            xml.push_str("    </testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if (c as u32) < 0x20 && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[utoipa::path(
+    get,
+    path = "/test/report",
+    tag = "test",
+    params(ReportQuery),
+    responses(
+        (status = 200, description = "Results of the test run as JSON or JUnit XML", body = TestReport),
+        (status = 400, description = "Invalid report format", body = ApiErrorBody),
+    )
+)]
+pub async fn test_report(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let query = web::Query::<ReportQuery>::from_query(req.query_string())
+        .map_err(ApiError::invalid_format)?
+        .into_inner();
+    let report = {
+        let mut lock = data.test.lock().await;
+        apply_timeouts(&data, &mut lock);
+        TestReport::new(&lock)
+    };
+    Ok(match query.format {
+        ReportFormat::Json => HttpResponse::Ok().json(report),
+        ReportFormat::Junit => HttpResponse::Ok()
+            .content_type("application/xml")
+            .body(report.to_junit()),
+    })
+}
+
+#[test]
+fn test_junit_report() {
+    let now = crate::model::offer::base::test_offer().timestamp;
+    let mut test = IntegrationTest {
+        started_at: Some(now),
+        number_of_groups: 3,
+        ..Default::default()
+    };
+    test.groups.insert(
+        "passed".to_string(),
+        IntegrationTestGroup {
+            started_at: Some(now),
+            finished_at: Some(now + chrono::Duration::seconds(5)),
+            success: Some(true),
+            metrics: BTreeMap::from([("offers".to_string(), serde_json::json!(3))]),
+            attempts: 1,
+            ..Default::default()
+        },
+    );
+    test.groups.insert(
+        "failed".to_string(),
+        IntegrationTestGroup {
+            started_at: Some(now),
+            finished_at: Some(now),
+            success: Some(false),
+            message: Some("no <offers> & no luck".to_string()),
+            attempts: 1,
+            ..Default::default()
+        },
+    );
+    let report = TestReport::new(&test);
+    assert_eq!(
+        (report.passed, report.failed, report.not_started),
+        (1, 1, 1)
+    );
+    let xml = report.to_junit();
+    assert!(xml.contains("<testcase name=\"passed\" classname=\"integration\" time=\"5\">"));
+    assert!(xml.contains("<property name=\"offers\" value=\"3\"/>"));
+    assert!(xml.contains("<failure message=\"no &lt;offers&gt; &amp; no luck\"/>"));
+    assert!(xml.contains("<property name=\"notStarted\" value=\"1\"/>"));
+}
@@ -0,0 +1,278 @@
+use crate::allocations::prune_allocation_history;
+use crate::audit::{AuditAction, AuditEntry, AuditLog, RequestContext};
+pub use crate::config::CliOptions;
+use crate::config::ServerConfig;
+use crate::grouping::regroup_offers;
+use crate::jobs::{self, JobSpec};
+use crate::offers::download_offers_from_mirror;
+use crate::recorder::{RecordRequests, RequestRecorder};
+use crate::rest::demand::cancel_demand::clean_old_demands;
+use crate::rest::demand::pick_offers_for_all_demands;
+use crate::rest::error::legacy_error_response;
+use crate::rest::offer::clean_old_offers::clean_old_offers;
+use crate::rest::provider::forget_silent_providers;
+use crate::state::{AppState, Snapshot};
+use actix_web::dev::{ServerHandle, Service};
+use actix_web::{web, App, HttpServer};
+use std::sync::Arc;
+
+/// Start the periodic jobs under the scheduler
+fn spawn_jobs(data: web::Data<AppState>) {
+    let jobs = data.jobs.clone();
+    jobs.spawn(
+        data.clone(),
+        JobSpec::new(
+            jobs::CLEANUP,
+            |config| config.cleanup.offer_interval_secs,
+            |data| async move {
+                clean_old_offers(data.clone()).await;
+                prune_allocation_history(data.clone()).await;
+                forget_silent_providers(data).await;
+                Ok(())
+            },
+        )
+        .run_at_start()
+        .essential(),
+    );
+    jobs.spawn(
+        data.clone(),
+        JobSpec::new(
+            jobs::DEMAND_EXPIRY,
+            |config| config.cleanup.demand_interval_secs,
+            |data| async move {
+                clean_old_demands(data).await;
+                Ok(())
+            },
+        )
+        .run_at_start()
+        .essential(),
+    );
+    // the first download happens before the server starts
+    jobs.spawn(
+        data.clone(),
+        JobSpec::new(
+            jobs::MIRROR_SYNC,
+            |config| config.mirror.sync_interval_secs,
+            |data| async move {
+                download_offers_from_mirror(data.clone()).await?;
+                data.health.set_initial_sync_done();
+                Ok(())
+            },
+        ),
+    );
+    // offer groups are fixed when they do not rotate
+    if data.config().grouping.rotate {
+        jobs.spawn(
+            data.clone(),
+            JobSpec::new(
+                jobs::REGROUP,
+                |config| config.grouping.epoch_secs,
+                |data| async move {
+                    regroup_offers(data).await;
+                    Ok(())
+                },
+            )
+            .aligned(),
+        );
+    }
+    jobs.spawn(
+        data,
+        JobSpec::new(
+            jobs::MATCHING,
+            |config| config.matching.pick_interval_secs,
+            |data| async move {
+                if data.config().matching.enabled && !data.health.is_shutting_down() {
+                    pick_offers_for_all_demands(data).await;
+                }
+                Ok(())
+            },
+        )
+        .essential(),
+    );
+}
+
+/// Re-read the configuration on SIGHUP and apply the matching and liveness parameters.
+/// Other sections need a restart.
+#[cfg(unix)]
+fn reload_config_on_sighup(data: web::Data<AppState>, args: CliOptions) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Failed to register SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            log::info!("SIGHUP received, reloading configuration");
+            let loaded = match ServerConfig::load(&args) {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    log::error!("Configuration not reloaded: {:#}", e);
+                    continue;
+                }
+            };
+            let mut config = (*data.config()).clone();
+            config.matching = loaded.matching.clone();
+            config.liveness = loaded.liveness.clone();
+            if config != loaded {
+                log::warn!(
+                    "Only [matching] and [liveness] are reloaded, restart the server to apply other changes"
+                );
+            }
+            log::info!("Matching configuration: {:?}", config.matching);
+            log::info!("Liveness configuration: {:?}", config.liveness);
+            data.audit.record(
+                AuditEntry::new(AuditAction::ConfigReloaded, data.now()).with_details(
+                    serde_json::json!({
+                        "matching": config.matching,
+                        "liveness": config.liveness,
+                    }),
+                ),
+            );
+            data.set_config(config);
+        }
+    });
+}
+
+/// Restore offers and demands saved by the previous run
+async fn load_state(data: &AppState) -> anyhow::Result<()> {
+    if let Some(path) = &data.config().storage.path {
+        if path.exists() {
+            let snapshot = Snapshot::load(path)?;
+            log::info!(
+                "Loaded {} offers and {} demands from {}",
+                snapshot.offers.offer_map.len(),
+                snapshot.demands.demand_map.len(),
+                path.display()
+            );
+            data.restore(snapshot).await;
+        } else {
+            log::info!("State file {} not found, starting empty", path.display());
+        }
+    }
+    data.health.set_storage_loaded();
+    Ok(())
+}
+
+/// Write the state file and make sure the audit trail and recording reach the disk
+async fn flush_state(data: &AppState, recorder: Option<&RequestRecorder>) {
+    if let Some(path) = &data.config().storage.path {
+        match data.snapshot().await.save(path) {
+            Ok(()) => log::info!("State saved to {}", path.display()),
+            Err(e) => log::error!("Failed to save state: {:#}", e),
+        }
+    }
+    if let Err(e) = data.audit.flush() {
+        log::error!("Failed to flush audit log: {}", e);
+    }
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.flush() {
+            log::error!("Failed to flush request recording: {}", e);
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => log::info!("SIGTERM received"),
+                    _ = tokio::signal::ctrl_c() => log::info!("SIGINT received"),
+                }
+                return;
+            }
+            Err(e) => log::error!("Failed to register SIGTERM handler: {}", e),
+        }
+    }
+    if tokio::signal::ctrl_c().await.is_ok() {
+        log::info!("SIGINT received");
+    }
+}
+
+/// Stop assigning offers on SIGTERM or SIGINT, then let requests in progress finish
+fn stop_on_signal(data: web::Data<AppState>, server: ServerHandle) {
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("Shutting down, no new offers are assigned");
+        data.health.begin_shutdown();
+        server.stop(true).await;
+    });
+}
+
+/// Start background tasks and serve the REST API until the server is stopped
+pub async fn run_server(args: CliOptions) -> std::io::Result<()> {
+    let config = ServerConfig::load(&args).map_err(|e| {
+        log::error!("Invalid configuration: {:#}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#}", e))
+    })?;
+    log::info!("Configuration: {:?}", config);
+    let (http_addr, http_port) = (config.http.addr.clone(), config.http.port);
+
+    let audit = AuditLog::open(&config.audit).map_err(|e| {
+        log::error!("Failed to open audit log: {}", e);
+        e
+    })?;
+    let recorder = match &config.recording.path {
+        Some(path) => Some(Arc::new(RequestRecorder::open(path).map_err(|e| {
+            log::error!("Failed to open request recording {}: {}", path.display(), e);
+            e
+        })?)),
+        None => None,
+    };
+    let app_state = AppState::new(config, audit);
+    load_state(&app_state).await.map_err(|e| {
+        log::error!("Failed to load state: {:#}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:#}", e))
+    })?;
+    match download_offers_from_mirror(web::Data::new(app_state.clone())).await {
+        Ok(Some(_)) => app_state.health.set_initial_sync_done(),
+        Ok(None) => {}
+        Err(_) => log::warn!("Initial offer download failed, not ready until a sync succeeds"),
+    }
+
+    spawn_jobs(web::Data::new(app_state.clone()));
+    #[cfg(unix)]
+    reload_config_on_sighup(web::Data::new(app_state.clone()), args);
+
+    log::info!(
+        "Starting Offer Server at http://{}:{}",
+        &http_addr,
+        &http_port
+    );
+    let (server_state, app_recorder) = (app_state.clone(), recorder.clone());
+    let server = HttpServer::new(move || {
+        //let auth = HttpAuthentication::with_fn(validator);
+
+        App::new()
+            .app_data(web::Data::new(server_state.clone()))
+            .wrap_fn(|req, srv| {
+                let response = srv.call(req);
+                async move { Ok(legacy_error_response(response.await?)) }
+            })
+            .wrap(RecordRequests::new(app_recorder.clone()))
+            .wrap_fn(|req, srv| {
+                let context = RequestContext::from_request(&req);
+                context.scope(srv.call(req))
+            })
+            .wrap(actix_web::middleware::Logger::default())
+            .wrap(actix_cors::Cors::permissive())
+            .configure(crate::rest::routes::configure)
+    })
+    .bind(format!("{}:{}", http_addr, http_port))?
+    .workers(4)
+    .disable_signals()
+    .run();
+    stop_on_signal(web::Data::new(app_state.clone()), server.handle());
+    server.await?;
+
+    flush_state(&app_state, recorder.as_deref()).await;
+    log::info!("Offer Server stopped");
+    Ok(())
+}
@@ -1,50 +1,324 @@
+use crate::allocations::Allocations;
+use crate::audit::AuditLog;
+use crate::clock::{checked_add_secs, Clock, SystemClock};
+use crate::config::ServerConfig;
+use crate::grouping::Grouping;
+use crate::health::Health;
+use crate::jobs::Scheduler;
 use crate::model::demand::base::DemandSubscription;
 use crate::model::offer::attributes::OfferFlatAttributes;
 use crate::model::offer::base::GolemBaseOffer;
+use anyhow::Context;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, VecDeque};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use utoipa::ToSchema;
 use ya_client_model::NodeId;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DemandObj {
     pub demand: DemandSubscription,
+    #[schema(value_type = Vec<String>)]
     pub offer_list: VecDeque<String>,
+    /// Offers assigned to this requestor that their providers unsubscribed, newest last
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub withdrawn_offers: VecDeque<String>,
+    /// Offers the requestor took from the queue
+    #[serde(default)]
+    pub taken: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DemandProgress {
+    pub queued: u64,
+    pub taken: u64,
+    pub target_count: Option<u64>,
+    /// Offers still to be assigned, `None` without a target
+    pub remaining: Option<u64>,
+    pub complete: bool,
+}
+
+/// Withdrawn offers remembered per demand
+const MAX_WITHDRAWN_OFFERS: usize = 100;
+
+impl DemandObj {
+    /// Let the requestor know an offer it was given is gone
+    pub fn record_withdrawn(&mut self, offer_id: &str) {
+        self.withdrawn_offers.push_back(offer_id.to_string());
+        while self.withdrawn_offers.len() > MAX_WITHDRAWN_OFFERS {
+            self.withdrawn_offers.pop_front();
+        }
+    }
+
+    /// Offers assigned to this demand so far, queued or already taken
+    pub fn assigned(&self) -> u64 {
+        self.offer_list.len() as u64 + self.taken
+    }
+
+    /// How many more offers the demand wants, `None` when it has no target
+    pub fn remaining(&self) -> Option<u64> {
+        self.demand
+            .target_count
+            .map(|target| target.saturating_sub(self.assigned()))
+    }
+
+    pub fn progress(&self) -> DemandProgress {
+        let remaining = self.remaining();
+        DemandProgress {
+            queued: self.offer_list.len() as u64,
+            taken: self.taken,
+            target_count: self.demand.target_count,
+            remaining,
+            complete: remaining == Some(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct OfferObj {
     pub offer: GolemBaseOffer,
     pub pushed_at: DateTime<Utc>,
+    #[schema(value_type = Option<String>)]
     pub requestor_id: Option<NodeId>,
     pub attributes: OfferFlatAttributes,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct Offers {
     pub offer_map: BTreeMap<String, OfferObj>,
+    /// Provider id to the id of its newest offer
+    #[serde(skip)]
+    by_provider: HashMap<NodeId, String>,
+}
+
+impl Offers {
+    /// Rebuild the lookup of provider offers, needed after deserializing
+    pub fn reindex(&mut self) {
+        let mut by_provider: HashMap<NodeId, &OfferObj> = HashMap::new();
+        for offer in self.offer_map.values() {
+            let newest = by_provider.entry(offer.offer.provider_id).or_insert(offer);
+            if newest.offer.id != offer.offer.id {
+                log::warn!(
+                    "Multiple existing offers from provider {}",
+                    offer.offer.provider_id
+                );
+                if newest.offer.timestamp < offer.offer.timestamp {
+                    *newest = offer;
+                }
+            }
+        }
+        self.by_provider = by_provider
+            .into_iter()
+            .map(|(provider_id, offer)| (provider_id, offer.offer.id.clone()))
+            .collect();
+    }
+
+    /// Newest offer of the provider
+    pub fn provider_offer(&self, provider_id: &NodeId) -> Option<&OfferObj> {
+        self.by_provider
+            .get(provider_id)
+            .and_then(|offer_id| self.offer_map.get(offer_id))
+    }
+
+    /// Add an offer as the newest offer of its provider
+    pub fn insert(&mut self, offer: OfferObj) {
+        self.by_provider
+            .insert(offer.offer.provider_id, offer.offer.id.clone());
+        self.offer_map.insert(offer.offer.id.clone(), offer);
+    }
+
+    /// Remove an offer, also from the lookup of its provider
+    pub fn remove(&mut self, offer_id: &str) -> Option<OfferObj> {
+        let offer = self.offer_map.remove(offer_id)?;
+        if self
+            .by_provider
+            .get(&offer.offer.provider_id)
+            .map(String::as_str)
+            == Some(offer_id)
+        {
+            self.by_provider.remove(&offer.offer.provider_id);
+        }
+        Some(offer)
+    }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct Demands {
     pub demand_map: BTreeMap<String, DemandObj>,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderLiveness {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub heartbeats: u64,
+}
+
+/// Heartbeats received from providers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct Providers {
+    #[schema(value_type = HashMap<String, ProviderLiveness>)]
+    pub provider_map: HashMap<NodeId, ProviderLiveness>,
+}
+
+impl Providers {
+    pub fn last_seen(&self, provider_id: &NodeId) -> Option<DateTime<Utc>> {
+        self.provider_map.get(provider_id).map(|p| p.last_seen)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct IntegrationTestGroup {
     pub started_at: Option<DateTime<Utc>>,
     pub finished_at: Option<DateTime<Utc>>,
     pub success: Option<bool>,
+    /// Free-form values reported when the group finished
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub metrics: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Failed by the server because it ran past its timeout
+    #[serde(default)]
+    pub timed_out: bool,
+    /// Overrides the group timeout of the test
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
+    /// Times the group was started, more than one after re-runs
+    #[serde(default)]
+    pub attempts: u32,
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+impl IntegrationTestGroup {
+    pub fn is_running(&self) -> bool {
+        self.started_at.is_some() && self.finished_at.is_none()
+    }
+
+    fn time_out(&mut self, now: DateTime<Utc>, message: String) {
+        self.finished_at = Some(now);
+        self.success = Some(false);
+        self.timed_out = true;
+        self.message = Some(message);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct IntegrationTest {
     pub started_at: Option<DateTime<Utc>>,
     pub finished_at: Option<DateTime<Utc>>,
     pub number_of_groups: usize,
     pub success: Option<bool>,
     pub groups: BTreeMap<String, IntegrationTestGroup>,
+    /// Matching was paused by the test initialization and resumes when a group starts
+    #[serde(default)]
+    pub matching_paused: bool,
+    /// The whole test fails this long after initialization
+    #[serde(default)]
+    pub timeout_secs: Option<f64>,
+    /// Groups fail this long after they started
+    #[serde(default)]
+    pub group_timeout_secs: Option<f64>,
+    /// Failed by the server because it ran past its timeout
+    #[serde(default)]
+    pub timed_out: bool,
+}
+
+/// `None` also when the timeout is too large to be reached
+fn deadline(since: Option<DateTime<Utc>>, timeout_secs: Option<f64>) -> Option<DateTime<Utc>> {
+    checked_add_secs(since?, timeout_secs?)
+}
+
+impl IntegrationTest {
+    /// Fail running groups past their timeout, and the whole test past its own.
+    /// Returns the groups failed now.
+    pub fn apply_timeouts(&mut self, now: DateTime<Utc>) -> Vec<String> {
+        if self.finished_at.is_some() {
+            return Vec::new();
+        }
+        let test_expired = deadline(self.started_at, self.timeout_secs).is_some_and(|d| now > d);
+        let mut timed_out = Vec::new();
+        for (name, group) in self.groups.iter_mut() {
+            if !group.is_running() {
+                continue;
+            }
+            let timeout_secs = group.timeout_secs.or(self.group_timeout_secs);
+            if deadline(group.started_at, timeout_secs).is_some_and(|d| now > d) {
+                group.time_out(
+                    now,
+                    format!(
+                        "Timed out after {} seconds",
+                        timeout_secs.unwrap_or_default()
+                    ),
+                );
+                timed_out.push(name.clone());
+            } else if test_expired {
+                group.time_out(
+                    now,
+                    format!(
+                        "Test timed out after {} seconds",
+                        self.timeout_secs.unwrap_or_default()
+                    ),
+                );
+                timed_out.push(name.clone());
+            }
+        }
+        if test_expired {
+            self.timed_out = true;
+            self.finished_at = Some(now);
+            self.success = Some(false);
+        } else {
+            self.finish_if_complete(now);
+        }
+        timed_out
+    }
+
+    /// The test is finished once every expected group finished, it succeeds when all did
+    pub fn finish_if_complete(&mut self, now: DateTime<Utc>) {
+        if !self.groups.is_empty()
+            && self.groups.len() == self.number_of_groups
+            && self.groups.values().all(|g| g.finished_at.is_some())
+        {
+            self.finished_at = Some(now);
+            self.success = Some(self.groups.values().all(|g| g.success == Some(true)));
+        }
+    }
+}
+
+/// Full matcher state, used to export and restore the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub offers: Offers,
+    pub demands: Demands,
+    /// Missing in snapshots of older versions, rebuilt from the assigned offers
+    #[serde(default)]
+    pub allocations: Allocations,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse state file {}", path.display()))
+    }
+
+    /// Written to a temporary file first, so an interrupted save keeps the previous state
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let content = serde_json::to_vec(self)?;
+        std::fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write state file {}", path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace state file {}", path.display()))?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -52,5 +326,196 @@ pub struct AppState {
     pub lock: Arc<tokio::sync::Mutex<Offers>>,
     pub test: Arc<tokio::sync::Mutex<IntegrationTest>>,
     pub demands: Arc<tokio::sync::Mutex<Demands>>,
-    pub offers_given_to_node: Arc<tokio::sync::Mutex<BTreeMap<String, u64>>>,
+    pub allocations: Arc<tokio::sync::Mutex<Allocations>>,
+    pub providers: Arc<tokio::sync::Mutex<Providers>>,
+    pub config: Arc<RwLock<Arc<ServerConfig>>>,
+    pub audit: Arc<AuditLog>,
+    pub clock: Arc<dyn Clock>,
+    /// Fixed at start, changes to `[grouping]` need a restart
+    pub grouping: Arc<Grouping>,
+    pub health: Arc<Health>,
+    /// Background jobs, started by the server
+    pub jobs: Arc<Scheduler>,
+}
+
+impl AppState {
+    pub fn new(config: ServerConfig, audit: AuditLog) -> Self {
+        Self::with_clock(config, audit, Arc::new(SystemClock))
+    }
+
+    /// State whose time comes from `clock`, which also timestamps the audit trail
+    pub fn with_clock(config: ServerConfig, mut audit: AuditLog, clock: Arc<dyn Clock>) -> Self {
+        audit.set_clock(clock.clone());
+        let grouping = Arc::new(Grouping::new(&config.grouping));
+        Self {
+            lock: Arc::new(tokio::sync::Mutex::new(Offers::default())),
+            test: Arc::new(tokio::sync::Mutex::new(IntegrationTest::default())),
+            demands: Arc::new(tokio::sync::Mutex::new(Demands::default())),
+            allocations: Arc::new(Default::default()),
+            providers: Arc::new(Default::default()),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            audit: Arc::new(audit),
+            clock,
+            grouping,
+            health: Arc::new(Health::default()),
+            jobs: Arc::new(Scheduler::default()),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Current configuration; may change when the configuration is reloaded
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn set_config(&self, config: ServerConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
+    pub async fn snapshot(&self) -> Snapshot {
+        let demands = self.demands.lock().await;
+        let offers = self.lock.lock().await;
+        let allocations = self.allocations.lock().await;
+        Snapshot {
+            offers: offers.clone(),
+            demands: demands.clone(),
+            allocations: allocations.clone(),
+        }
+    }
+
+    /// Replace offers, demands and allocations with those of the snapshot
+    pub async fn restore(&self, mut snapshot: Snapshot) {
+        let now = self.now();
+        let mut demands = self.demands.lock().await;
+        let mut offers = self.lock.lock().await;
+        let mut allocations = self.allocations.lock().await;
+        snapshot.offers.reindex();
+        snapshot.allocations.reindex();
+        snapshot
+            .allocations
+            .adopt_assigned(&snapshot.offers, &snapshot.demands, now);
+        // the snapshot can come from an older version, whose attributes lack newer fields,
+        // or from a server with another grouping seed
+        for offer in snapshot.offers.offer_map.values_mut() {
+            offer.attributes = OfferFlatAttributes::from_gbo(&offer.offer, &self.grouping, now);
+        }
+        *offers = snapshot.offers;
+        *demands = snapshot.demands;
+        *allocations = snapshot.allocations;
+    }
+}
+
+/// Empty state with time from `clock`, used as a fixture in tests
+#[cfg(test)]
+pub(crate) fn test_state(clock: Arc<dyn Clock>) -> actix_web::web::Data<AppState> {
+    let audit = AuditLog::open(&Default::default()).unwrap();
+    actix_web::web::Data::new(AppState::with_clock(ServerConfig::default(), audit, clock))
+}
+
+/// Empty state frozen at the timestamp of the offer fixture, returned together with that time
+#[cfg(test)]
+pub(crate) fn test_state_at_fixture_time() -> (actix_web::web::Data<AppState>, DateTime<Utc>) {
+    let now = crate::model::offer::base::test_offer().timestamp;
+    (test_state(Arc::new(crate::clock::MockClock::new(now))), now)
+}
+
+/// Unassigned offer published at `timestamp` and valid for an hour, used as a fixture in tests
+#[cfg(test)]
+pub(crate) fn test_offer_obj(id: &str, timestamp: DateTime<Utc>) -> OfferObj {
+    let mut offer = crate::model::offer::base::test_offer();
+    offer.id = id.to_string();
+    offer.timestamp = timestamp;
+    offer.expiration = timestamp + chrono::Duration::hours(1);
+    OfferObj {
+        attributes: OfferFlatAttributes::from_gbo(
+            &offer,
+            &Grouping::new(&Default::default()),
+            timestamp,
+        ),
+        offer,
+        pushed_at: timestamp,
+        requestor_id: None,
+    }
+}
+
+/// Demand without queued offers, used as a fixture in tests
+#[cfg(test)]
+pub(crate) fn test_demand_obj(id: &str, node_id: NodeId, expiration: DateTime<Utc>) -> DemandObj {
+    DemandObj {
+        demand: DemandSubscription {
+            id: id.to_string(),
+            properties: "{}".to_string(),
+            constraints: String::new(),
+            node_id,
+            creation_ts: expiration.naive_utc(),
+            insertion_ts: None,
+            expiration_ts: expiration.naive_utc(),
+            central_net_address: None,
+            target_count: None,
+            max_per_tick: None,
+            priority: None,
+            weight: None,
+            requirements: None,
+            payment_platforms: None,
+        },
+        offer_list: VecDeque::new(),
+        withdrawn_offers: VecDeque::new(),
+        taken: 0,
+    }
+}
+
+#[actix_web::test]
+async fn test_restored_offers_get_current_attributes() {
+    use crate::rest::demand::pick_offers_for_all_demands;
+
+    let (data, now) = test_state_at_fixture_time();
+    let mut offers = Offers::default();
+    offers.insert(test_offer_obj("offer", now - chrono::Duration::minutes(1)));
+    let mut snapshot = serde_json::to_value(Snapshot {
+        offers,
+        ..Default::default()
+    })
+    .unwrap();
+    // attributes as stored before payment platforms and resources were added
+    let attributes = snapshot["offers"]["offer_map"]["offer"]["attributes"]
+        .as_object_mut()
+        .unwrap();
+    for field in [
+        "runtime_version",
+        "capabilities",
+        "cpu_cores",
+        "memory_gib",
+        "storage_gib",
+        "gpus",
+        "payment_platforms",
+        "group_epoch",
+    ] {
+        attributes.remove(field).unwrap();
+    }
+    data.restore(serde_json::from_value(snapshot).unwrap())
+        .await;
+
+    let mut demand = test_demand_obj(
+        "demand",
+        NodeId::default(),
+        now + chrono::Duration::hours(1),
+    );
+    demand.demand.central_net_address = Some("127.0.0.1".to_string());
+    demand.demand.properties = serde_json::json!({
+        "golem.com.payment.platform.erc20-polygon-glm.address": "0x01",
+    })
+    .to_string();
+    data.demands
+        .lock()
+        .await
+        .demand_map
+        .insert("demand".to_string(), demand);
+    pick_offers_for_all_demands(data.clone()).await;
+    assert_eq!(
+        data.demands.lock().await.demand_map["demand"].offer_list,
+        ["offer".to_string()]
+    );
 }
@@ -0,0 +1,192 @@
+use crate::output::{print_json, print_message, print_output, OutputFormat, Table};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use yagna_offer_client::types::{JobStatus, Snapshot};
+use yagna_offer_client::MatcherClient;
+
+#[derive(Debug, StructOpt)]
+pub enum JobsCommand {
+    /// List jobs with their run statistics
+    List,
+    /// Skip scheduled runs of the job until resumed
+    Pause {
+        name: String,
+    },
+    Resume {
+        name: String,
+    },
+    /// Run the job now, also when it is paused
+    Run {
+        name: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum MirrorCommand {
+    /// Download offers from the configured mirror now
+    SyncNow,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SnapshotCommand {
+    /// Write offers and demands to a file, or stdout when no file is given
+    Export { file: Option<PathBuf> },
+    /// Replace server offers and demands with the content of a snapshot file
+    Import { file: PathBuf },
+}
+
+pub fn jobs_table(jobs: &[JobStatus]) -> Table {
+    let mut table = Table::new(vec![
+        "JOB",
+        "STATE",
+        "ESSENTIAL",
+        "INTERVAL",
+        "RUNS",
+        "FAILURES",
+        "PANICS",
+        "LAST SUCCESS",
+        "NEXT RUN",
+        "LAST ERROR",
+    ]);
+    let time = |t: Option<chrono::DateTime<chrono::Utc>>| {
+        t.map(|t| t.to_rfc3339()).unwrap_or_else(|| "-".to_string())
+    };
+    for job in jobs {
+        let state = if !job.alive {
+            "stopped"
+        } else if job.running {
+            "running"
+        } else if job.paused {
+            "paused"
+        } else if job.stale {
+            "stale"
+        } else {
+            "idle"
+        };
+        table.add_row(vec![
+            job.name.clone(),
+            state.to_string(),
+            if job.essential { "yes" } else { "no" }.to_string(),
+            format!("{}s +{}s", job.interval_secs, job.jitter_secs),
+            job.stats.runs.to_string(),
+            job.stats.failures.to_string(),
+            job.stats.panics.to_string(),
+            time(job.stats.last_success_at),
+            time(job.next_run_at),
+            job.stats
+                .last_error
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    table
+}
+
+pub async fn ready(client: &MatcherClient, format: OutputFormat) -> anyhow::Result<()> {
+    let readiness = client.readiness().await?;
+    match format {
+        OutputFormat::Json => print_json(&readiness)?,
+        OutputFormat::Table => {
+            println!(
+                "Ready: {}, initial sync done: {}, storage loaded: {}, shutting down: {}\n",
+                readiness.ready,
+                readiness
+                    .initial_sync_done
+                    .map(|done| done.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                readiness.storage_loaded,
+                readiness.shutting_down
+            );
+            let jobs: Vec<JobStatus> = readiness.jobs.values().cloned().collect();
+            println!("{}", jobs_table(&jobs).render());
+        }
+    }
+    if !readiness.ready {
+        anyhow::bail!("Server is not ready");
+    }
+    Ok(())
+}
+
+pub async fn run_jobs(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: JobsCommand,
+) -> anyhow::Result<()> {
+    match command {
+        JobsCommand::List => {
+            let jobs = client.list_jobs().await?;
+            print_output(format, &jobs, |jobs| jobs_table(jobs))
+        }
+        JobsCommand::Pause { name } => {
+            let job = client.pause_job(&name).await?;
+            print_output(format, &job, |job| jobs_table(std::slice::from_ref(job)))
+        }
+        JobsCommand::Resume { name } => {
+            let job = client.resume_job(&name).await?;
+            print_output(format, &job, |job| jobs_table(std::slice::from_ref(job)))
+        }
+        JobsCommand::Run { name } => {
+            let job = client.run_job_now(&name).await?;
+            print_output(format, &job, |job| jobs_table(std::slice::from_ref(job)))
+        }
+    }
+}
+
+pub async fn run_mirror(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: MirrorCommand,
+) -> anyhow::Result<()> {
+    match command {
+        MirrorCommand::SyncNow => {
+            let stats = client.mirror_sync_now().await?;
+            print_output(format, &stats, |s| {
+                let mut table =
+                    Table::new(vec!["DOWNLOADED", "ADDED", "PRESENT", "REMOVED", "IGNORED"]);
+                table.add_row(vec![
+                    s.downloaded.to_string(),
+                    s.added.to_string(),
+                    s.already_present.to_string(),
+                    s.removed.to_string(),
+                    s.ignored.to_string(),
+                ]);
+                table
+            })
+        }
+    }
+}
+
+pub async fn run_snapshot(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: SnapshotCommand,
+) -> anyhow::Result<()> {
+    match command {
+        SnapshotCommand::Export { file } => {
+            let snapshot = client.export_snapshot().await?;
+            let content = serde_json::to_string_pretty(&snapshot)?;
+            match file {
+                Some(file) => {
+                    std::fs::write(&file, content)?;
+                    print_message(
+                        format,
+                        &format!(
+                            "Exported {} offers and {} demands to {}",
+                            snapshot.offers.offer_map.len(),
+                            snapshot.demands.demand_map.len(),
+                            file.display()
+                        ),
+                    )
+                }
+                None => {
+                    println!("{}", content);
+                    Ok(())
+                }
+            }
+        }
+        SnapshotCommand::Import { file } => {
+            let snapshot: Snapshot = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            print_message(format, &client.import_snapshot(&snapshot).await?)
+        }
+    }
+}
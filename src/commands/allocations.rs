@@ -0,0 +1,188 @@
+use crate::output::{print_output, OutputFormat, Table};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+use yagna_offer_client::types::{
+    Allocation, AllocationFilter, AuditEntry, AuditQuery, RequestorAllocationCounts,
+};
+use yagna_offer_client::{MatcherClient, NodeId};
+
+#[derive(Debug, StructOpt)]
+pub enum AllocationsCommand {
+    /// List allocations, newest first
+    List {
+        #[structopt(long)]
+        requestor_id: Option<NodeId>,
+        #[structopt(long)]
+        provider_id: Option<NodeId>,
+        #[structopt(long)]
+        demand_id: Option<String>,
+        #[structopt(long, help = "Only offers requestors hold now")]
+        live: bool,
+        #[structopt(long, default_value = "50")]
+        limit: usize,
+    },
+    /// Live, total and recent allocation counts by requestor
+    Counts {
+        #[structopt(long, default_value = "3600", help = "Window of the allocation rate")]
+        window_secs: f64,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AuditCommand {
+    #[structopt(long, help = "Only entries from the last N minutes")]
+    pub minutes: Option<i64>,
+    #[structopt(long)]
+    pub offer_id: Option<String>,
+    #[structopt(long)]
+    pub demand_id: Option<String>,
+    #[structopt(long)]
+    pub provider_id: Option<String>,
+    #[structopt(long)]
+    pub requestor_id: Option<String>,
+    #[structopt(long)]
+    pub request_id: Option<String>,
+    #[structopt(long, default_value = "100")]
+    pub limit: usize,
+}
+
+pub fn allocations_table(allocations: &[Allocation]) -> Table {
+    let mut table = Table::new(vec![
+        "ID",
+        "OFFER",
+        "PROVIDER",
+        "REQUESTOR",
+        "DEMAND",
+        "SOURCE",
+        "STATE",
+        "ALLOCATED AT",
+    ]);
+    for allocation in allocations {
+        table.add_row(vec![
+            allocation.id.to_string(),
+            allocation.offer_id.clone(),
+            allocation.provider_id.to_string(),
+            allocation.requestor_id.to_string(),
+            allocation
+                .demand_id
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+            format!("{:?}", allocation.source),
+            format!("{:?}", allocation.state),
+            allocation.allocated_at.to_rfc3339(),
+        ]);
+    }
+    table
+}
+
+fn allocation_counts_table(counts: &BTreeMap<String, RequestorAllocationCounts>) -> Table {
+    let mut table = Table::new(vec![
+        "REQUESTOR",
+        "LIVE",
+        "TOTAL",
+        "IN WINDOW",
+        "PER MINUTE",
+    ]);
+    for (requestor_id, count) in counts {
+        table.add_row(vec![
+            requestor_id.clone(),
+            count.live.to_string(),
+            count.total.to_string(),
+            count.in_window.to_string(),
+            format!("{:.2}", count.per_minute),
+        ]);
+    }
+    table
+}
+
+pub fn audit_table(entries: &[AuditEntry]) -> Table {
+    let mut table = Table::new(vec![
+        "SEQ",
+        "TIME",
+        "ACTION",
+        "CALLER",
+        "OFFER",
+        "DEMAND",
+        "REQUESTOR",
+    ]);
+    let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    for entry in entries {
+        table.add_row(vec![
+            entry.seq.to_string(),
+            entry.timestamp.to_rfc3339(),
+            format!("{:?}", entry.action),
+            or_dash(&entry.caller),
+            or_dash(&entry.offer_id),
+            or_dash(&entry.demand_id),
+            or_dash(&entry.requestor_id),
+        ]);
+    }
+    table
+}
+
+pub async fn run(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: AllocationsCommand,
+) -> anyhow::Result<()> {
+    match command {
+        AllocationsCommand::List {
+            requestor_id,
+            provider_id,
+            demand_id,
+            live,
+            limit,
+        } => {
+            let listing = client
+                .list_allocations(&AllocationFilter {
+                    requestor_id,
+                    provider_id,
+                    demand_id,
+                    live: live.then_some(true),
+                    limit: Some(limit),
+                    ..Default::default()
+                })
+                .await?;
+            print_output(format, &listing.allocations, |a| allocations_table(a))
+        }
+        AllocationsCommand::Counts { window_secs } => {
+            let listing = client
+                .list_allocations(&AllocationFilter {
+                    window_secs: Some(window_secs),
+                    limit: Some(0),
+                    ..Default::default()
+                })
+                .await?;
+            print_output(format, &listing.counts, allocation_counts_table)
+        }
+    }
+}
+
+pub async fn audit(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: AuditCommand,
+) -> anyhow::Result<()> {
+    let AuditCommand {
+        minutes,
+        offer_id,
+        demand_id,
+        provider_id,
+        requestor_id,
+        request_id,
+        limit,
+    } = command;
+    let entries = client
+        .audit(&AuditQuery {
+            from: minutes.map(|m| chrono::Utc::now() - chrono::Duration::minutes(m)),
+            offer_id,
+            demand_id,
+            provider_id,
+            requestor_id,
+            request_id,
+            limit: Some(limit),
+            ..Default::default()
+        })
+        .await?;
+    print_output(format, &entries, |e| audit_table(e))
+}
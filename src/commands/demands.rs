@@ -0,0 +1,174 @@
+use crate::commands::offers::offers_table;
+use crate::output::{print_message, print_output, OutputFormat, Table};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+use yagna_offer_client::model::demand::base::DemandRefresh;
+use yagna_offer_client::types::{DemandObj, OfferListEntry, TakeOfferFromQueue};
+use yagna_offer_client::{MatcherClient, NodeId};
+
+#[derive(Debug, StructOpt)]
+pub enum DemandsCommand {
+    /// List active demands
+    List,
+    /// Cancel demand, its queued offers go back to the pool
+    Cancel { demand_id: String },
+    /// Extend demand expiration
+    Refresh {
+        demand_id: String,
+        #[structopt(long, help = "New expiration, minutes from now", default_value = "60")]
+        minutes: i64,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum QueueCommand {
+    /// Show offers queued for a demand (demand id or requestor node id)
+    Show { demand: String },
+    /// Take all queued offers for a demand
+    Drain {
+        demand: String,
+        #[structopt(long, default_value = "50", help = "Offers taken per request")]
+        batch: usize,
+    },
+}
+
+fn demands_table(demands: &[DemandObj]) -> Table {
+    let mut table = Table::new(vec![
+        "ID",
+        "NODE",
+        "CENTRAL NET",
+        "QUEUED",
+        "TAKEN",
+        "TARGET",
+        "EXPIRATION",
+    ]);
+    for demand in demands {
+        let progress = demand.progress();
+        table.add_row(vec![
+            demand.demand.id.clone(),
+            demand.demand.node_id.to_string(),
+            demand
+                .demand
+                .central_net_address
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+            progress.queued.to_string(),
+            progress.taken.to_string(),
+            match (progress.target_count, progress.complete) {
+                (Some(target), true) => format!("{} (done)", target),
+                (Some(target), false) => target.to_string(),
+                (None, _) => "-".to_string(),
+            },
+            demand.demand.expiration_ts.to_string(),
+        ]);
+    }
+    table
+}
+
+fn find_demand(demands: Vec<DemandObj>, demand: &str) -> anyhow::Result<DemandObj> {
+    if let Some(found) = demands.iter().find(|d| d.demand.id == demand) {
+        return Ok(found.clone());
+    }
+    let node_id = demand.parse::<NodeId>().ok();
+    let mut candidates: Vec<DemandObj> = demands
+        .into_iter()
+        .filter(|d| Some(d.demand.node_id) == node_id)
+        .collect();
+    match candidates.len() {
+        0 => anyhow::bail!("Demand {} not found", demand),
+        1 => Ok(candidates.remove(0)),
+        _ => anyhow::bail!(
+            "Node {} has several demands, use one of {}",
+            demand,
+            candidates
+                .iter()
+                .map(|d| d.demand.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+pub async fn run(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: DemandsCommand,
+) -> anyhow::Result<()> {
+    match command {
+        DemandsCommand::List => {
+            print_output(format, &client.list_demands().await?, |d| demands_table(d))
+        }
+        DemandsCommand::Cancel { demand_id } => {
+            print_message(format, &client.cancel_demand(&demand_id).await?)
+        }
+        DemandsCommand::Refresh { demand_id, minutes } => {
+            let demand = client
+                .refresh_demand(&DemandRefresh {
+                    demand_id,
+                    expiration_ts: (chrono::Utc::now() + chrono::Duration::minutes(minutes))
+                        .naive_utc(),
+                })
+                .await?;
+            print_message(
+                format,
+                &format!("Demand {} expires at {}", demand.id, demand.expiration_ts),
+            )
+        }
+    }
+}
+
+pub async fn run_queue(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: QueueCommand,
+) -> anyhow::Result<()> {
+    match command {
+        QueueCommand::Show { demand } => {
+            let demand = find_demand(client.list_demands().await?, &demand)?;
+            let offers: BTreeMap<String, OfferListEntry> = client
+                .list_offers()
+                .await?
+                .into_iter()
+                .map(|o| (o.offer.offer.id.clone(), o))
+                .collect();
+            let queued: Vec<OfferListEntry> = demand
+                .offer_list
+                .iter()
+                .filter_map(|id| offers.get(id).cloned())
+                .collect();
+            if queued.len() != demand.offer_list.len() {
+                log::warn!(
+                    "{} queued offers are no longer present on the server",
+                    demand.offer_list.len() - queued.len()
+                );
+            }
+            print_output(format, &queued, |o| offers_table(o))
+        }
+        QueueCommand::Drain { demand, batch } => {
+            let mut taken = Vec::new();
+            loop {
+                let offers = client
+                    .take_from_queue(&TakeOfferFromQueue {
+                        demand_id: demand.clone(),
+                        take_at_once: Some(batch),
+                    })
+                    .await?;
+                if offers.is_empty() {
+                    break;
+                }
+                taken.extend(offers);
+            }
+            print_output(format, &taken, |offers| {
+                let mut table = Table::new(vec!["ID", "PROVIDER", "EXPIRATION"]);
+                for offer in offers {
+                    table.add_row(vec![
+                        offer.id.clone(),
+                        offer.node_id.to_string(),
+                        offer.expiration_ts.to_string(),
+                    ]);
+                }
+                table
+            })
+        }
+    }
+}
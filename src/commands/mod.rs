@@ -0,0 +1,59 @@
+pub mod admin;
+pub mod allocations;
+pub mod demands;
+pub mod offers;
+pub mod replay;
+pub mod test;
+
+use crate::output::OutputFormat;
+use admin::{JobsCommand, MirrorCommand, SnapshotCommand};
+use allocations::{AllocationsCommand, AuditCommand};
+use demands::{DemandsCommand, QueueCommand};
+use offers::OffersCommand;
+use structopt::StructOpt;
+use test::TestCommand;
+use yagna_offer_client::MatcherClient;
+
+/// Commands sent to a running offer server
+#[derive(Debug, StructOpt)]
+pub enum RemoteCommand {
+    /// Inspect and manage offers
+    Offers(OffersCommand),
+    /// Inspect and cancel demands
+    Demands(DemandsCommand),
+    /// Inspect and drain demand queues
+    Queue(QueueCommand),
+    /// Offers allocated to requestors
+    Allocations(AllocationsCommand),
+    /// Show the audit trail of state changes
+    Audit(AuditCommand),
+    /// Control integration test runs
+    Test(TestCommand),
+    /// Offer mirror synchronization
+    Mirror(MirrorCommand),
+    /// Export and import server state
+    Snapshot(SnapshotCommand),
+    /// Show server readiness and background jobs, fails when the server is not ready
+    Ready,
+    /// Inspect and control background jobs
+    Jobs(JobsCommand),
+}
+
+pub async fn run_command(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: RemoteCommand,
+) -> anyhow::Result<()> {
+    match command {
+        RemoteCommand::Offers(command) => offers::run(client, format, command).await,
+        RemoteCommand::Demands(command) => demands::run(client, format, command).await,
+        RemoteCommand::Queue(command) => demands::run_queue(client, format, command).await,
+        RemoteCommand::Allocations(command) => allocations::run(client, format, command).await,
+        RemoteCommand::Audit(command) => allocations::audit(client, format, command).await,
+        RemoteCommand::Test(command) => test::run(client, format, command).await,
+        RemoteCommand::Mirror(command) => admin::run_mirror(client, format, command).await,
+        RemoteCommand::Snapshot(command) => admin::run_snapshot(client, format, command).await,
+        RemoteCommand::Ready => admin::ready(client, format).await,
+        RemoteCommand::Jobs(command) => admin::run_jobs(client, format, command).await,
+    }
+}
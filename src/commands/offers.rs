@@ -0,0 +1,378 @@
+use crate::commands::allocations::{allocations_table, audit_table};
+use crate::output::{print_json, print_message, print_output, OutputFormat, Table};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+use yagna_offer_client::types::{
+    BulkReassign, OfferAssignmentChange, OfferListEntry, OfferSelector, OfferUnsubscribe,
+};
+use yagna_offer_client::{MatcherClient, NodeId};
+
+#[derive(Debug, StructOpt)]
+pub enum OffersCommand {
+    /// List offers
+    List {
+        #[structopt(long, conflicts_with = "taken", help = "Only offers not assigned yet")]
+        available: bool,
+        #[structopt(long, help = "Only offers assigned to a requestor")]
+        taken: bool,
+        #[structopt(long, help = "Only offers accepting this payment platform")]
+        payment_platform: Option<String>,
+    },
+    /// Count available and taken offers
+    Count,
+    /// Offer counts by runtime, subnet, payment platform and requestor
+    Stats,
+    /// Remove all offers from the server
+    Clear,
+    /// Withdraw an offer, or all offers of a provider
+    Unsubscribe {
+        #[structopt(long, required_unless = "provider-id", conflicts_with = "provider-id")]
+        offer_id: Option<String>,
+        #[structopt(long)]
+        provider_id: Option<NodeId>,
+    },
+    /// Show owner, queues, allocations and audit trail of an offer
+    Inspect { offer_id: String },
+    /// Return an offer, or the offers of a provider or requestor, to the pool
+    Unassign {
+        #[structopt(required_unless_one = &["provider-id", "requestor-id"])]
+        offer_id: Option<String>,
+        #[structopt(long, conflicts_with = "offer-id")]
+        provider_id: Option<NodeId>,
+        #[structopt(long, conflicts_with = "offer-id")]
+        requestor_id: Option<NodeId>,
+    },
+    /// Queue an offer, or the offers of a provider or requestor, for another demand
+    Reassign {
+        #[structopt(required_unless_one = &["provider-id", "requestor-id"])]
+        offer_id: Option<String>,
+        #[structopt(long, conflicts_with = "offer-id")]
+        provider_id: Option<NodeId>,
+        #[structopt(long, conflicts_with = "offer-id")]
+        requestor_id: Option<NodeId>,
+        #[structopt(long)]
+        demand_id: String,
+    },
+}
+
+pub fn offers_table(offers: &[OfferListEntry]) -> Table {
+    let mut table = Table::new(vec![
+        "ID",
+        "PROVIDER",
+        "NAME",
+        "RUNTIME",
+        "THREADS",
+        "SUBNET",
+        "REQUESTOR",
+        "EXPIRATION",
+        "ALIVE",
+        "LAST SEEN",
+    ]);
+    for entry in offers {
+        let offer = &entry.offer;
+        let liveness = entry.liveness.as_ref();
+        table.add_row(vec![
+            offer.offer.id.clone(),
+            offer.offer.provider_id.to_string(),
+            offer.attributes.node_name.clone(),
+            offer.attributes.exe_name.clone(),
+            offer.attributes.cpu_threads.to_string(),
+            offer.attributes.subnet.clone(),
+            offer
+                .requestor_id
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            offer.offer.expiration.to_rfc3339(),
+            liveness
+                .map(|l| if l.alive { "yes" } else { "no" }.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            liveness
+                .and_then(|l| l.last_seen)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    table
+}
+
+fn assignment_changes_table(changes: &[OfferAssignmentChange]) -> Table {
+    let mut table = Table::new(vec![
+        "OFFER",
+        "PREVIOUS REQUESTOR",
+        "PREVIOUS DEMAND",
+        "REQUESTOR",
+        "DEMAND",
+    ]);
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    for change in changes {
+        table.add_row(vec![
+            change.offer_id.clone(),
+            or_dash(change.previous_requestor_id.map(|id| id.to_string())),
+            or_dash(change.previous_demand_id.clone()),
+            or_dash(change.requestor_id.map(|id| id.to_string())),
+            or_dash(change.demand_id.clone()),
+        ]);
+    }
+    table
+}
+
+fn counts_table(counts: &BTreeMap<String, usize>, header: &'static str) -> Table {
+    let mut table = Table::new(vec![header, "OFFERS"]);
+    for (key, count) in counts {
+        table.add_row(vec![key.clone(), count.to_string()]);
+    }
+    table
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OfferCount {
+    total: usize,
+    available: usize,
+    taken: usize,
+}
+
+impl OfferCount {
+    fn from_offers(offers: &[OfferListEntry]) -> Self {
+        let taken = offers
+            .iter()
+            .filter(|o| o.offer.requestor_id.is_some())
+            .count();
+        Self {
+            total: offers.len(),
+            available: offers.len() - taken,
+            taken,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OfferStats {
+    #[serde(flatten)]
+    count: OfferCount,
+    by_runtime: BTreeMap<String, usize>,
+    by_subnet: BTreeMap<String, usize>,
+    /// Offers accepting several platforms count on each of them
+    by_payment_platform: BTreeMap<String, OfferCount>,
+    by_requestor: BTreeMap<String, usize>,
+}
+
+pub async fn run(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: OffersCommand,
+) -> anyhow::Result<()> {
+    match command {
+        OffersCommand::List {
+            available,
+            taken,
+            payment_platform: Some(payment_platform),
+        } => {
+            let offers: Vec<_> = client
+                .list_offers_on_platform(&payment_platform)
+                .await?
+                .into_iter()
+                .filter(|o| {
+                    (!available || o.offer.requestor_id.is_none())
+                        && (!taken || o.offer.requestor_id.is_some())
+                })
+                .collect();
+            print_output(format, &offers, |o| offers_table(o))
+        }
+        OffersCommand::List {
+            available, taken, ..
+        } => {
+            let offers = if available {
+                client.list_available_offers().await?
+            } else if taken {
+                client.list_taken_offers().await?
+            } else {
+                client.list_offers().await?
+            };
+            print_output(format, &offers, |o| offers_table(o))
+        }
+        OffersCommand::Count => {
+            let count = OfferCount::from_offers(&client.list_offers().await?);
+            print_output(format, &count, |c| {
+                let mut table = Table::new(vec!["TOTAL", "AVAILABLE", "TAKEN"]);
+                table.add_row(vec![
+                    c.total.to_string(),
+                    c.available.to_string(),
+                    c.taken.to_string(),
+                ]);
+                table
+            })
+        }
+        OffersCommand::Stats => {
+            let offers = client.list_offers().await?;
+            let mut stats = OfferStats {
+                count: OfferCount::from_offers(&offers),
+                by_runtime: BTreeMap::new(),
+                by_subnet: BTreeMap::new(),
+                by_payment_platform: BTreeMap::new(),
+                by_requestor: BTreeMap::new(),
+            };
+            for offer in offers.iter().map(|o| &o.offer) {
+                *stats
+                    .by_runtime
+                    .entry(offer.attributes.exe_name.clone())
+                    .or_default() += 1;
+                *stats
+                    .by_subnet
+                    .entry(offer.attributes.subnet.clone())
+                    .or_default() += 1;
+                for platform in &offer.attributes.payment_platforms {
+                    let count = stats
+                        .by_payment_platform
+                        .entry(platform.clone())
+                        .or_default();
+                    count.total += 1;
+                    match offer.requestor_id {
+                        Some(_) => count.taken += 1,
+                        None => count.available += 1,
+                    }
+                }
+                if let Some(requestor_id) = offer.requestor_id {
+                    *stats
+                        .by_requestor
+                        .entry(requestor_id.to_string())
+                        .or_default() += 1;
+                }
+            }
+            match format {
+                OutputFormat::Json => print_json(&stats),
+                OutputFormat::Table => {
+                    println!(
+                        "Total: {}, available: {}, taken: {}\n",
+                        stats.count.total, stats.count.available, stats.count.taken
+                    );
+                    println!("{}\n", counts_table(&stats.by_runtime, "RUNTIME").render());
+                    println!("{}\n", counts_table(&stats.by_subnet, "SUBNET").render());
+                    let mut table =
+                        Table::new(vec!["PAYMENT PLATFORM", "TOTAL", "AVAILABLE", "TAKEN"]);
+                    for (platform, count) in &stats.by_payment_platform {
+                        table.add_row(vec![
+                            platform.clone(),
+                            count.total.to_string(),
+                            count.available.to_string(),
+                            count.taken.to_string(),
+                        ]);
+                    }
+                    println!("{}\n", table.render());
+                    println!(
+                        "{}",
+                        counts_table(&stats.by_requestor, "REQUESTOR").render()
+                    );
+                    Ok(())
+                }
+            }
+        }
+        OffersCommand::Clear => print_message(format, &client.clear_offers().await?),
+        OffersCommand::Unsubscribe {
+            offer_id,
+            provider_id,
+        } => {
+            let result = client
+                .unsubscribe_offer(&OfferUnsubscribe {
+                    offer_id,
+                    provider_id,
+                })
+                .await?;
+            print_output(format, &result, |r| {
+                let mut table = Table::new(vec!["REMOVED OFFER"]);
+                for offer_id in &r.removed_offer_ids {
+                    table.add_row(vec![offer_id.clone()]);
+                }
+                table
+            })
+        }
+        OffersCommand::Inspect { offer_id } => {
+            let inspection = client.inspect_offer(&offer_id).await?;
+            match format {
+                OutputFormat::Json => print_json(&inspection),
+                OutputFormat::Table => {
+                    let offer = &inspection.offer;
+                    println!(
+                        "Offer {} of provider {} ({}), pushed at {}, expires at {}",
+                        offer.offer.id,
+                        offer.offer.provider_id,
+                        offer.attributes.node_name,
+                        offer.pushed_at.to_rfc3339(),
+                        offer.offer.expiration.to_rfc3339()
+                    );
+                    println!(
+                        "Requestor: {}, provider alive: {}",
+                        offer
+                            .requestor_id
+                            .map(|id| id.to_string())
+                            .unwrap_or_else(|| "-".to_string()),
+                        inspection.liveness.alive
+                    );
+                    for queued in &inspection.queued_in {
+                        println!(
+                            "Queued for demand {} at position {}",
+                            queued.demand_id, queued.position
+                        );
+                    }
+                    println!(
+                        "\n{}\n",
+                        allocations_table(&inspection.allocations).render()
+                    );
+                    println!("{}", audit_table(&inspection.audit).render());
+                    Ok(())
+                }
+            }
+        }
+        OffersCommand::Unassign {
+            offer_id: Some(offer_id),
+            ..
+        } => {
+            let change = client.unassign_offer(&offer_id).await?;
+            print_output(format, &change, |c| {
+                assignment_changes_table(std::slice::from_ref(c))
+            })
+        }
+        OffersCommand::Unassign {
+            provider_id,
+            requestor_id,
+            ..
+        } => {
+            let changes = client
+                .unassign_offers(&OfferSelector {
+                    provider_id,
+                    requestor_id,
+                })
+                .await?;
+            print_output(format, &changes, |c| assignment_changes_table(c))
+        }
+        OffersCommand::Reassign {
+            offer_id: Some(offer_id),
+            demand_id,
+            ..
+        } => {
+            let change = client.reassign_offer(&offer_id, &demand_id).await?;
+            print_output(format, &change, |c| {
+                assignment_changes_table(std::slice::from_ref(c))
+            })
+        }
+        OffersCommand::Reassign {
+            provider_id,
+            requestor_id,
+            demand_id,
+            ..
+        } => {
+            let changes = client
+                .reassign_offers(&BulkReassign {
+                    selector: OfferSelector {
+                        provider_id,
+                        requestor_id,
+                    },
+                    demand_id,
+                })
+                .await?;
+            print_output(format, &changes, |c| assignment_changes_table(c))
+        }
+    }
+}
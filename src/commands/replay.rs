@@ -0,0 +1,48 @@
+use crate::commands::allocations::allocations_table;
+use crate::output::{print_output, OutputFormat};
+use std::path::PathBuf;
+use yagna_offer_client::types::ServerConfig;
+use yagna_offer_server::recorder::read_recording;
+use yagna_offer_server::replay::{replay, ReplayOptions};
+
+pub async fn run(
+    format: OutputFormat,
+    file: PathBuf,
+    config: Option<PathBuf>,
+    settle_secs: f64,
+) -> anyhow::Result<()> {
+    let requests = read_recording(&file)?;
+    let config = match config {
+        Some(path) => ServerConfig::from_file(&path)?,
+        None => ServerConfig::default(),
+    };
+    let report = replay(
+        &requests,
+        ReplayOptions {
+            config,
+            settle_secs,
+        },
+    )
+    .await?;
+    if format == OutputFormat::Table {
+        println!(
+            "Replayed {} requests, {} matching rounds, {} status mismatches",
+            report.requests,
+            report.matching_rounds,
+            report.status_mismatches.len()
+        );
+        for mismatch in &report.status_mismatches {
+            println!(
+                "  #{} {} {}: recorded {}, replayed {}",
+                mismatch.index,
+                mismatch.method,
+                mismatch.path,
+                mismatch.recorded,
+                mismatch.replayed
+            );
+        }
+    }
+    print_output(format, &report, |report| {
+        allocations_table(&report.allocations)
+    })
+}
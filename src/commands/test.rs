@@ -0,0 +1,182 @@
+use crate::output::{print_message, print_output, OutputFormat, Table};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use yagna_offer_client::types::{TestFinishArguments, TestInitializeArguments, TestStartArguments};
+use yagna_offer_client::MatcherClient;
+
+#[derive(Debug, StructOpt)]
+pub enum TestCommand {
+    /// Initialize a new test run, this removes all offers
+    Init {
+        number_of_groups: usize,
+        #[structopt(
+            long,
+            help = "Pause matching until the first group starts, so providers can push their offers first"
+        )]
+        pause_matching: bool,
+        #[structopt(long, help = "Fail the whole test after this many seconds")]
+        timeout_secs: Option<f64>,
+        #[structopt(long, help = "Fail groups running longer than this many seconds")]
+        group_timeout_secs: Option<f64>,
+    },
+    /// Mark test group as started
+    Start {
+        group: String,
+        #[structopt(long, help = "Group timeout overriding the one of the test")]
+        timeout_secs: Option<f64>,
+    },
+    /// Mark test group as finished
+    Finish {
+        group: String,
+        #[structopt(long, help = "Report the group as failed")]
+        failed: bool,
+        #[structopt(long)]
+        message: Option<String>,
+        #[structopt(
+            long = "metric",
+            parse(try_from_str = parse_metric),
+            help = "Metric as NAME=VALUE, VALUE is read as JSON when possible, can be repeated"
+        )]
+        metrics: Vec<(String, serde_json::Value)>,
+    },
+    /// Reset a finished group so it can be started again
+    Rerun { group: String },
+    /// Test results as JSON or JUnit XML
+    Report {
+        #[structopt(long, help = "JUnit XML instead of JSON")]
+        junit: bool,
+        #[structopt(long, help = "Write the report to this file instead of stdout")]
+        file: Option<PathBuf>,
+    },
+    /// Show test run state
+    Status,
+    /// Wait until all test groups are finished
+    Wait {
+        #[structopt(long, default_value = "3600", help = "Give up after this many seconds")]
+        timeout: u64,
+        #[structopt(long, default_value = "5", help = "Seconds between checks")]
+        interval: u64,
+    },
+}
+
+fn parse_metric(value: &str) -> anyhow::Result<(String, serde_json::Value)> {
+    let (name, value) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Metric {} is not in NAME=VALUE form", value))?;
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((name.to_string(), value))
+}
+
+pub async fn run(
+    client: &MatcherClient,
+    format: OutputFormat,
+    command: TestCommand,
+) -> anyhow::Result<()> {
+    match command {
+        TestCommand::Init {
+            number_of_groups,
+            pause_matching,
+            timeout_secs,
+            group_timeout_secs,
+        } => {
+            let args = TestInitializeArguments {
+                number_of_groups,
+                pause_matching,
+                timeout_secs,
+                group_timeout_secs,
+            };
+            print_message(format, &client.test_initialize(&args).await?)
+        }
+        TestCommand::Start {
+            group,
+            timeout_secs,
+        } => {
+            let args = TestStartArguments {
+                group,
+                timeout_secs,
+            };
+            print_message(format, &client.test_start(&args).await?)
+        }
+        TestCommand::Finish {
+            group,
+            failed,
+            message,
+            metrics,
+        } => {
+            let args = TestFinishArguments {
+                group,
+                success: !failed,
+                metrics: metrics.into_iter().collect(),
+                message,
+            };
+            print_message(format, &client.test_finish(&args).await?)
+        }
+        TestCommand::Rerun { group } => print_message(format, &client.test_rerun(&group).await?),
+        TestCommand::Report { junit, file } => {
+            let content = match junit {
+                true => client.test_report_junit().await?,
+                false => serde_json::to_string_pretty(&client.test_report().await?)?,
+            };
+            match file {
+                Some(file) => {
+                    std::fs::write(&file, content)?;
+                    print_message(
+                        format,
+                        &format!("Test report written to {}", file.display()),
+                    )
+                }
+                None => {
+                    println!("{}", content);
+                    Ok(())
+                }
+            }
+        }
+        TestCommand::Status => {
+            let status = client.test_status().await?;
+            print_output(format, &status, |status| {
+                let mut table = Table::new(vec![
+                    "GROUP",
+                    "STARTED",
+                    "FINISHED",
+                    "SUCCESS",
+                    "TIMED OUT",
+                    "ATTEMPTS",
+                    "MESSAGE",
+                ]);
+                for (name, group) in &status.groups {
+                    table.add_row(vec![
+                        name.clone(),
+                        group
+                            .started_at
+                            .map(|t| t.to_rfc3339())
+                            .unwrap_or_else(|| "-".to_string()),
+                        group
+                            .finished_at
+                            .map(|t| t.to_rfc3339())
+                            .unwrap_or_else(|| "-".to_string()),
+                        group
+                            .success
+                            .map(|s| s.to_string())
+                            .unwrap_or_else(|| "-".to_string()),
+                        group.timed_out.to_string(),
+                        group.attempts.to_string(),
+                        group.message.clone().unwrap_or_else(|| "-".to_string()),
+                    ]);
+                }
+                table
+            })
+        }
+        TestCommand::Wait { timeout, interval } => {
+            let started = Instant::now();
+            while !client.test_is_finished().await? {
+                if started.elapsed() > Duration::from_secs(timeout) {
+                    anyhow::bail!("Test not finished after {} seconds", timeout);
+                }
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+            print_message(format, "Test is finished")
+        }
+    }
+}
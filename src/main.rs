@@ -1,6 +1,63 @@
+mod commands;
+mod output;
+
+use crate::commands::{run_command, RemoteCommand};
+use crate::output::OutputFormat;
 use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use yagna_offer_client::MatcherClient;
+use yagna_offer_server::server::{run_server, CliOptions};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "offer_processor",
+    about = "Operator tool for the Yagna offer server"
+)]
+struct Cli {
+    #[structopt(
+        long = "url",
+        global = true,
+        env = "OFFER_SERVER",
+        help = "Address of the running offer server",
+        default_value = "http://127.0.0.1:15155"
+    )]
+    url: String,
+
+    #[structopt(
+        long = "output",
+        short = "o",
+        global = true,
+        help = "Output format: table or json",
+        default_value = "table"
+    )]
+    output: OutputFormat,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Run the offer server
+    Serve(CliOptions),
+    #[structopt(flatten)]
+    Remote(RemoteCommand),
+    /// Replay a request recording in a fresh in-process matcher and show the assignments
+    Replay {
+        file: PathBuf,
+        #[structopt(long, help = "TOML configuration of the replayed matcher")]
+        config: Option<PathBuf>,
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "Virtual seconds simulated after the last request"
+        )]
+        settle_secs: f64,
+    },
+}
 
-async fn main_internal() {
+async fn main_internal() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     env::set_var(
         "RUST_LOG",
@@ -8,9 +65,68 @@ async fn main_internal() {
     );
 
     env_logger::init();
+    let cli = Cli::from_args();
+
+    match cli.command {
+        Command::Serve(options) => Ok(run_server(options).await?),
+        Command::Replay {
+            file,
+            config,
+            settle_secs,
+        } => commands::replay::run(cli.output, file, config, settle_secs).await,
+        Command::Remote(command) => {
+            let client = MatcherClient::new(cli.url);
+            run_command(&client, cli.output, command).await
+        }
+    }
 }
 
 #[actix_web::main]
-async fn main() {
-    main_internal().await;
+async fn main() -> anyhow::Result<()> {
+    main_internal().await
+}
+
+#[test]
+fn test_parse_arguments() {
+    use crate::commands::offers::OffersCommand;
+
+    let parse = |args: &[&str]| {
+        Cli::from_iter_safe(
+            ["offer_processor", "--url", "http://matcher:15155"]
+                .iter()
+                .chain(args),
+        )
+    };
+
+    let cli = parse(&["offers", "list", "--available", "-o", "json"]).unwrap();
+    assert_eq!(cli.url, "http://matcher:15155");
+    assert_eq!(cli.output, OutputFormat::Json);
+    assert!(matches!(
+        cli.command,
+        Command::Remote(RemoteCommand::Offers(OffersCommand::List {
+            available: true,
+            taken: false,
+            payment_platform: None,
+        }))
+    ));
+    let cli = parse(&["serve", "--http-port", "15199"]).unwrap();
+    assert_eq!(cli.output, OutputFormat::Table);
+    assert!(matches!(cli.command, Command::Serve(options) if options.http_port == Some(15199)));
+    assert!(matches!(
+        parse(&["replay", "requests.jsonl"]).unwrap().command,
+        Command::Replay { settle_secs, .. } if settle_secs == 0.0
+    ));
+    assert!(matches!(
+        parse(&["ready"]).unwrap().command,
+        Command::Remote(RemoteCommand::Ready)
+    ));
+    assert!(matches!(
+        parse(&["audit", "--minutes", "5"]).unwrap().command,
+        Command::Remote(RemoteCommand::Audit(audit)) if audit.minutes == Some(5) && audit.limit == 100
+    ));
+
+    assert!(parse(&["offers", "list", "--available", "--taken"]).is_err());
+    assert!(parse(&["offers", "unsubscribe"]).is_err());
+    assert!(parse(&["-o", "xml", "ready"]).is_err());
+    assert!(parse(&[]).is_err());
 }
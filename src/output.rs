@@ -0,0 +1,112 @@
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format {}, use table or json", s)),
+        }
+    }
+}
+
+/// Plain text table with columns padded to the widest cell
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Self {
+        Self {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn render(&self) -> String {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.len()).collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate() {
+                if i < widths.len() {
+                    widths[i] = widths[i].max(cell.chars().count());
+                }
+            }
+        }
+        let format_row = |cells: Vec<&str>| {
+            cells
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        let mut lines = vec![format_row(self.headers.clone())];
+        for row in &self.rows {
+            lines.push(format_row(row.iter().map(|s| s.as_str()).collect()));
+        }
+        lines.join("\n")
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Print `value` as JSON, or as the table built by `table` in table mode
+pub fn print_output<T: Serialize>(
+    format: OutputFormat,
+    value: &T,
+    table: impl FnOnce(&T) -> Table,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => print_json(value),
+        OutputFormat::Table => {
+            println!("{}", table(value).render());
+            Ok(())
+        }
+    }
+}
+
+/// Print a message returned by the server
+pub fn print_message(format: OutputFormat, message: &str) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => print_json(&serde_json::json!({ "message": message })),
+        OutputFormat::Table => {
+            println!("{}", message);
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_table_pads_columns_to_the_widest_cell() {
+    let mut table = Table::new(vec!["id", "state"]);
+    table.add_row(vec!["offer-1".to_string(), "taken".to_string()]);
+    table.add_row(vec!["ą".to_string(), String::new()]);
+    assert_eq!(table.render(), "id       state\noffer-1  taken\ną");
+    assert_eq!(Table::new(vec!["id"]).render(), "id");
+}
+
+#[test]
+fn test_output_format_from_str() {
+    assert_eq!("json".parse(), Ok(OutputFormat::Json));
+    assert_eq!("table".parse(), Ok(OutputFormat::Table));
+    assert!("xml".parse::<OutputFormat>().is_err());
+}